@@ -0,0 +1,12 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+
+use libactionkv::ActionKV;
+
+fuzz_target!(|data: &[u8]| {
+    let mut cursor = Cursor::new(data);
+    while ActionKV::process_record(&mut cursor).is_ok() {}
+});
@@ -10,6 +10,12 @@ const USAGE: &str = "
     akv_mem.exe FILE delete KEY
     akv_mem.exe FILE insert KEY VALUE
     akv_mem.exe FILE update KEY VALUE
+    akv_mem.exe FILE query INDEX VALUE
+    akv_mem.exe FILE stats
+    akv_mem.exe FILE compact
+    akv_mem.exe FILE backup DEST [--incremental]
+    akv_mem.exe FILE restore BACKUP
+    akv_mem.exe FILE --ns NAME <command>
 ";
 
 #[cfg(not(target_os = "windows"))]
@@ -18,6 +24,12 @@ const USAGE: &str = "
     akv_mem FILE delete KEY
     akv_mem FILE insert KEY VALUE
     akv_mem FILE update KEY VALUE
+    akv_mem FILE query INDEX VALUE
+    akv_mem FILE stats
+    akv_mem FILE compact
+    akv_mem FILE backup DEST [--incremental]
+    akv_mem FILE restore BACKUP
+    akv_mem FILE --ns NAME <command>
 ";
 
 fn main() -> Result<(), std::io::Error>{
@@ -28,6 +40,9 @@ fn main() -> Result<(), std::io::Error>{
             .arg(Arg::with_name("filename")
                 .takes_value(true)
                 .required(true))
+            .arg(Arg::with_name("namespace")
+                .long("ns")
+                .takes_value(true))
             .subcommands(vec![
                 SubCommand::with_name("keys"),
                 SubCommand::with_name("get")
@@ -39,16 +54,47 @@ fn main() -> Result<(), std::io::Error>{
                     .arg(Arg::with_name("value").takes_value(true).required(true)),
                 SubCommand::with_name("update")
                     .arg(Arg::with_name("key").takes_value(true).required(true))
-                    .arg(Arg::with_name("value").takes_value(true).required(true))
+                    .arg(Arg::with_name("value").takes_value(true).required(true)),
+                SubCommand::with_name("query")
+                    .arg(Arg::with_name("index").takes_value(true).required(true))
+                    .arg(Arg::with_name("value").takes_value(true).required(true)),
+                SubCommand::with_name("stats"),
+                SubCommand::with_name("compact"),
+                SubCommand::with_name("backup")
+                    .arg(Arg::with_name("dest").takes_value(true).required(true))
+                    .arg(Arg::with_name("incremental").long("incremental")),
+                SubCommand::with_name("restore")
+                    .arg(Arg::with_name("backup").takes_value(true).required(true))
             ])
             .get_matches();
 
 
     let filename = args.value_of("filename").expect("filename is missing");
+    let maybe_namespace = args.value_of("namespace");
+    let namespace = maybe_namespace.unwrap_or_default().as_bytes();
+
+    if let Some(matched) = args.subcommand_matches("restore") {
+        let backup = matched.value_of("backup").expect("backup is missing");
+        let records = ActionKV::restore(Path::new(backup), Path::new(filename))?;
+        println!("Restored {} records from {:?}", records, backup);
+        return Ok(());
+    }
+
+    if let Some(matched) = args.subcommand_matches("backup") {
+        let dest = Path::new(matched.value_of("dest").expect("dest is missing"));
+        let mut store = ActionKV::open(Path::new(filename))?;
+        let offset = if matched.is_present("incremental") {
+            store.backup_incremental(dest)?
+        } else {
+            store.backup(dest)?
+        };
+        println!("Backed up {} bytes to {:?}", offset, dest);
+        return Ok(());
+    }
 
     let mut cmd: Option<(String, &ArgMatches)> = None;
 
-    for name in &["get", "delete", "insert", "update"] {
+    for name in &["get", "delete", "insert", "update", "query", "stats", "compact"] {
         if let Some(matched) = args.subcommand_matches(name) {
             cmd = Some((String::from(*name), matched));
             break;
@@ -56,27 +102,57 @@ fn main() -> Result<(), std::io::Error>{
     }
 
     let mut store = ActionKV::open(Path::new(filename))?;
+    if let Some((_, matched)) = cmd.as_ref().filter(|(name, _)| name == "query") {
+        // the index is named after the JSON field it covers
+        let index_name = matched.value_of("index").expect("index is missing");
+        store.namespace(namespace).declare_index(index_name, index_name)?;
+    }
     store.load()?;
 
     match cmd {
-        None => println!("Key-value store size: {}", store.index.len()),
+        None => println!("Key-value store size: {}", store.namespace(namespace).len()),
+        Some((name, _)) if name == "stats" => {
+            let names = match maybe_namespace {
+                None => store.namespaces(),
+                Some(_) => vec![namespace.to_vec()],
+            };
+            for name in names {
+                let stats = store.namespace(&name).stats();
+                println!("{:?}: keys={} records={} stale={} bytes={}",
+                    String::from_utf8_lossy(&name), stats.keys, stats.records, stats.stale(), stats.bytes);
+            }
+        },
+        Some((name, _)) if name == "compact" => match maybe_namespace {
+            None => store.compact()?,
+            Some(_) => store.namespace(namespace).compact()?,
+        },
+        Some((name, matched)) if name == "query" => {
+            let index_name = matched.value_of("index").expect("index is missing");
+            let value = matched.value_of("value").expect("value is missing");
+            let mut table = store.namespace(namespace);
+            for key in table.find_by(index_name, value)? {
+                let found = table.get(&key)?.unwrap_or_default();
+                println!("{:?} {:?}", String::from_utf8_lossy(&key), String::from_utf8_lossy(&found));
+            }
+        },
         Some((name, matched)) => {
             let key_string = matched.value_of("key").expect("key is missing");
             let key = key_string.as_ref();
             let maybe_value = matched.value_of("value");
+            let mut table = store.namespace(namespace);
             match name.as_ref() {
-                "get" => match store.get(key)? {
+                "get" => match table.get(key)? {
                     None => eprintln!("{:?} not found", key_string),
                     Some(value) => println!("{:?}", String::from_utf8(value).ok().unwrap()),
                 },
-                "delete" => store.delete(key)?,
+                "delete" => table.delete(key)?,
                 "insert" => {
                     let value = maybe_value.expect(USAGE).as_ref();
-                    store.insert(key, value)?;
+                    table.insert(key, value)?;
                 },
                 "update" => {
                     let value = maybe_value.expect(USAGE).as_ref();
-                    store.update(key, value)?;
+                    table.update(key, value)?;
                 },
                 _ => eprintln!("{}", &USAGE),
             }
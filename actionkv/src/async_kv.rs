@@ -0,0 +1,190 @@
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::thread;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{ActionKV, ByteStr, ByteString, DEFAULT_NAMESPACE};
+
+type Reply<T> = oneshot::Sender<io::Result<T>>;
+
+enum Command {
+    Load(Reply<()>),
+    Get(ByteString, Reply<Option<ByteString>>),
+    Insert(ByteString, ByteString, Reply<()>),
+}
+
+/// An `ActionKV` driven from async code.
+///
+/// The store is owned by a dedicated writer thread. Writes that arrive while
+/// it is busy are committed together: one write to the log and one fsync for
+/// the whole group. Handles are cheap to clone and share between tasks.
+#[derive(Clone)]
+pub struct AsyncActionKV {
+    tx: mpsc::UnboundedSender<Command>,
+}
+
+impl AsyncActionKV {
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        let path = path.to_path_buf();
+        let store = tokio::task::spawn_blocking(move || ActionKV::open(&path))
+            .await
+            .map_err(io::Error::other)??;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        thread::Builder::new()
+            .name("actionkv-writer".to_string())
+            .spawn(move || run(store, rx))?;
+        Ok(AsyncActionKV { tx })
+    }
+
+    pub async fn load(&self) -> io::Result<()> {
+        self.call(Command::Load).await
+    }
+
+    pub async fn get(&self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        let key = key.to_vec();
+        self.call(|reply| Command::Get(key, reply)).await
+    }
+
+    pub async fn insert(&self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        let (key, value) = (key.to_vec(), value.to_vec());
+        self.call(|reply| Command::Insert(key, value, reply)).await
+    }
+
+    #[inline]
+    pub async fn update(&self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        self.insert(key, value).await
+    }
+
+    #[inline]
+    pub async fn delete(&self, key: &ByteStr) -> io::Result<()> {
+        self.insert(key, b"").await
+    }
+
+    async fn call<T, F: FnOnce(Reply<T>) -> Command>(&self, command: F) -> io::Result<T> {
+        let (reply, response) = oneshot::channel();
+        if self.tx.send(command(reply)).is_err() {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "store writer has stopped"));
+        }
+        match response.await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "store writer has stopped")),
+        }
+    }
+}
+
+fn run(mut store: ActionKV, mut rx: mpsc::UnboundedReceiver<Command>) {
+    let mut pending = vec![];
+
+    while let Some(first) = rx.blocking_recv() {
+        let mut batch = vec![first];
+        while let Ok(next) = rx.try_recv() {
+            batch.push(next);
+        }
+
+        for command in batch {
+            match command {
+                Command::Insert(key, value, reply) => pending.push((key, value, reply)),
+                // reads have to see every write queued before them
+                Command::Load(reply) => {
+                    store.commit(&mut pending);
+                    let _ = reply.send(store.load());
+                },
+                Command::Get(key, reply) => {
+                    store.commit(&mut pending);
+                    let _ = reply.send(store.get(&key));
+                },
+            }
+        }
+        store.commit(&mut pending);
+    }
+}
+
+impl ActionKV {
+    /// Appends the pending writes as a single group and answers their callers
+    /// once the group has been synced to disk.
+    fn commit(&mut self, pending: &mut Vec<(ByteString, ByteString, Reply<()>)>) {
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut buf = ByteString::new();
+        let mut accepted = vec![];
+        for (key, value, reply) in pending.drain(..) {
+            let start = buf.len();
+            match ActionKV::write_record(&mut buf, DEFAULT_NAMESPACE, &key, &value) {
+                Ok(()) => accepted.push((key, value, start as u64, (buf.len() - start) as u64, reply)),
+                Err(err) => {
+                    buf.truncate(start);
+                    let _ = reply.send(Err(err));
+                },
+            }
+        }
+
+        match self.append_synced(&buf) {
+            Ok(position) => {
+                let keyspace = self.keyspaces.entry(DEFAULT_NAMESPACE.to_vec()).or_default();
+                for (key, value, offset, record_len, reply) in accepted {
+                    keyspace.track(&key, &value, position + offset, record_len);
+                    let _ = reply.send(Ok(()));
+                }
+            },
+            Err(err) => {
+                for (_, _, _, _, reply) in accepted {
+                    let _ = reply.send(Err(io::Error::new(err.kind(), err.to_string())));
+                }
+            },
+        }
+    }
+
+    /// Appends the whole group or nothing: a failed write or sync truncates
+    /// the log back to where the group started.
+    fn append_synced(&mut self, buf: &ByteStr) -> io::Result<u64> {
+        let position = self.f.seek(SeekFrom::End(0))?;
+        if let Err(err) = self.f.write_all(buf).and_then(|_| self.f.sync_data()) {
+            let _ = self.f.set_len(position).and_then(|_| self.f.sync_data());
+            return Err(err);
+        }
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_writes() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("async.kv");
+
+        let store = AsyncActionKV::open(&path).await?;
+        store.load().await?;
+
+        let mut tasks = vec![];
+        for i in 0..64u32 {
+            let store = store.clone();
+            tasks.push(tokio::spawn(async move {
+                store.insert(&i.to_le_bytes(), format!("value {}", i).as_bytes()).await
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap()?;
+        }
+        store.delete(&0u32.to_le_bytes()).await?;
+
+        assert_eq!(store.get(&7u32.to_le_bytes()).await?, Some(b"value 7".to_vec()));
+        assert_eq!(store.get(&0u32.to_le_bytes()).await?, Some(vec![]));
+        assert_eq!(store.get(b"missing").await?, None);
+
+        let mut reopened = ActionKV::open(&path)?;
+        reopened.load()?;
+        assert_eq!(reopened.len(), 64);
+        assert_eq!(reopened.get(&63u32.to_le_bytes())?, Some(b"value 63".to_vec()));
+
+        Ok(())
+    }
+}
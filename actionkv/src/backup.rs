@@ -0,0 +1,214 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::ActionKV;
+
+impl ActionKV {
+    /// Copies the log to `dest`, up to the end of its last complete record.
+    ///
+    /// Records appended while the copy is being taken are left out, so the backup
+    /// is always a consistent prefix of the log. Returns the offset it ends at.
+    pub fn backup(&mut self, dest: &Path) -> io::Result<u64> {
+        let mut out = File::create(dest)?;
+        let end = self.copy_records(0, &mut out)?;
+        out.sync_all()?;
+        Ok(end)
+    }
+
+    /// Appends the records written since the previous backup to `dest`.
+    ///
+    /// The size of `dest` is the offset the previous backup ended at. Its last
+    /// record is checked against the log at that offset, so a log that has been
+    /// compacted or replaced since then is refused in favour of a full backup.
+    pub fn backup_incremental(&mut self, dest: &Path) -> io::Result<u64> {
+        let mut out = OpenOptions::new().create(true).append(true).open(dest)?;
+        let start = out.metadata()?.len();
+        self.check_prefix(dest, start)?;
+        let end = self.copy_records(start, &mut out)?;
+        out.sync_all()?;
+        Ok(end)
+    }
+
+    /// Replaces the log at `path` with the one at `backup`, after checking that
+    /// every record in it is intact. Returns the number of records restored.
+    pub fn restore(backup: &Path, path: &Path) -> io::Result<usize> {
+        let mut f = BufReader::new(File::open(backup)?);
+        let (records, end) = scan(&mut f, 0)?;
+        if end != f.get_ref().metadata()?.len() {
+            let error_msg = format!("backup ends with an incomplete record at offset {}", end);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error_msg));
+        }
+
+        let mut tmp_path = path.to_path_buf().into_os_string();
+        tmp_path.push(".restore");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        fs::copy(backup, &tmp_path)?;
+        File::open(&tmp_path)?.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(records)
+    }
+
+    /// Checks that the backup at `dest`, `len` bytes long, ends with the same
+    /// record as the log does at that offset.
+    fn check_prefix(&self, dest: &Path, len: u64) -> io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mut backup = BufReader::new(File::open(dest)?);
+        let mut last = 0;
+        loop {
+            let position = backup.stream_position()?;
+            if position == len {
+                break;
+            }
+            if let Err(err) = ActionKV::process_record(&mut backup) {
+                let error_msg = format!("backup record at offset {}: {}", position, err);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error_msg));
+            }
+            last = position;
+        }
+
+        let mut expected = vec![0; (len - last) as usize];
+        backup.seek(SeekFrom::Start(last))?;
+        backup.read_exact(&mut expected)?;
+
+        let mut f = File::open(&self.path)?;
+        let mut actual = vec![0; expected.len()];
+        f.seek(SeekFrom::Start(last))?;
+        match f.read_exact(&mut actual) {
+            Ok(()) if actual == expected => Ok(()),
+            Ok(()) => {
+                let error_msg = format!("backup does not match the log at offset {}, take a full backup", last);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, error_msg))
+            },
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                let error_msg = format!("backup is ahead of the log ({} bytes), take a full backup", len);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, error_msg))
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    fn copy_records<W: Write>(&mut self, start: u64, out: &mut W) -> io::Result<u64> {
+        // a separate handle, so that the store's own file position is left alone
+        let mut f = BufReader::new(File::open(&self.path)?);
+        let log_len = f.get_ref().metadata()?.len();
+        if start > log_len {
+            let error_msg = format!("backup is ahead of the log ({} > {} bytes)", start, log_len);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error_msg));
+        }
+
+        let (_, end) = scan(&mut f, start)?;
+        f.seek(SeekFrom::Start(start))?;
+        io::copy(&mut f.take(end - start), out)?;
+        Ok(end)
+    }
+}
+
+/// Validates the records from `start` onwards, returning how many there are
+/// and the offset right after the last complete one.
+fn scan<R: Read + Seek>(f: &mut R, start: u64) -> io::Result<(usize, u64)> {
+    let mut records = 0;
+    let mut end = f.seek(SeekFrom::Start(start))?;
+    loop {
+        match ActionKV::process_record(f) {
+            Ok(_) => records += 1,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => {
+                let error_msg = format!("record at offset {}: {}", end, err);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error_msg));
+            },
+        }
+        end = f.stream_position()?;
+    }
+    Ok((records, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_incremental_backup() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("store.kv");
+        let dest = dir.path().join("store.kv.bak");
+        let mut store = ActionKV::open(&path)?;
+
+        store.insert(b"a", b"1")?;
+        let end = store.backup(&dest)?;
+        assert_eq!(end, fs::metadata(&dest)?.len());
+
+        store.insert(b"b", b"2")?;
+        store.namespace(b"users").insert(b"a", b"3")?;
+        // a record torn by a concurrent writer stays out of the backup
+        OpenOptions::new().append(true).open(&path)?.write_all(&[0xFF; 6])?;
+
+        let end = store.backup_incremental(&dest)?;
+        assert_eq!(end, fs::metadata(&path)?.len() - 6);
+        assert_eq!(end, fs::metadata(&dest)?.len());
+
+        let restored = dir.path().join("restored.kv");
+        assert_eq!(ActionKV::restore(&dest, &restored)?, 3);
+
+        let mut store = ActionKV::open(&restored)?;
+        store.load()?;
+        assert_eq!(store.get(b"b")?, Some(b"2".to_vec()));
+        assert_eq!(store.namespace(b"users").get(b"a")?, Some(b"3".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_backup_after_compaction() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("store.kv");
+        let dest = dir.path().join("store.kv.bak");
+        let mut store = ActionKV::open(&path)?;
+
+        store.insert(b"a", b"1")?;
+        store.insert(b"a", b"2")?;
+        store.backup(&dest)?;
+
+        // the log is as long as the backup again, but its records have moved
+        store.compact()?;
+        store.insert(b"b", b"3")?;
+        assert_eq!(fs::metadata(&path)?.len(), fs::metadata(&dest)?.len());
+
+        let err = store.backup_incremental(&dest).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        store.backup(&dest)?;
+        store.insert(b"c", b"4")?;
+        assert_eq!(store.backup_incremental(&dest)?, fs::metadata(&path)?.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_rejects_corruption() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("store.kv");
+        let dest = dir.path().join("store.kv.bak");
+        let mut store = ActionKV::open(&path)?;
+        store.insert(b"a", b"1")?;
+        store.backup(&dest)?;
+
+        let mut bytes = fs::read(&dest)?;
+        *bytes.last_mut().unwrap() ^= 0xFF;
+        fs::write(&dest, &bytes)?;
+        assert!(ActionKV::restore(&dest, &path).is_err());
+
+        fs::write(&dest, &bytes[..bytes.len() - 1])?;
+        assert!(ActionKV::restore(&dest, &path).is_err());
+
+        assert_eq!(store.get(b"a")?, Some(b"1".to_vec()));
+
+        Ok(())
+    }
+}
@@ -1,25 +1,90 @@
 use std::fs::OpenOptions;
 use std::io::{BufReader, SeekFrom, Seek, Read, BufWriter, Write};
 use std::{collections::HashMap, fs::File, io};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crc::crc32;
 use serde_derive::{Serialize, Deserialize};
 
+#[cfg(feature = "async")]
+mod async_kv;
+mod backup;
+mod secondary;
+
+#[cfg(feature = "async")]
+pub use async_kv::AsyncActionKV;
+pub use secondary::SecondaryIndex;
+
 type ByteString = Vec<u8>;
 type ByteStr = [u8];
 
+/// Set on a record's key length when the key is prefixed with a namespace.
+const NAMESPACE_FLAG: u32 = 1 << 31;
+
+/// The namespace used by the `ActionKV` methods that don't take one.
+const DEFAULT_NAMESPACE: &ByteStr = b"";
+
+/// Moved live records and per-namespace `(records, bytes)` of a rewritten log.
+type Compacted = (Vec<(ByteString, ByteString, u64)>, HashMap<ByteString, (usize, u64)>);
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyValuePair {
+    pub namespace: ByteString,
     pub key: ByteString,
     pub value: ByteString,
 }
 
+/// The in-memory state of one namespace: where its keys live in the log,
+/// its secondary indexes and how much of the log it occupies.
+#[derive(Debug, Default)]
+pub struct Keyspace {
+    pub index: HashMap<ByteString, u64>,
+    secondary: HashMap<String, SecondaryIndex>,
+    records: usize,
+    bytes: u64,
+}
+
+impl Keyspace {
+    fn track(&mut self, key: &ByteStr, value: &ByteStr, position: u64, record_len: u64) {
+        for index in self.secondary.values_mut() {
+            index.update(key, value);
+        }
+        self.index.insert(key.to_vec(), position);
+        self.records += 1;
+        self.bytes += record_len;
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats { keys: self.index.len(), records: self.records, bytes: self.bytes }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub keys: usize,
+    pub records: usize,
+    pub bytes: u64,
+}
+
+impl Stats {
+    /// Records that were superseded by a later write of the same key.
+    pub fn stale(&self) -> usize {
+        self.records - self.keys
+    }
+}
+
 #[derive(Debug)]
 pub struct ActionKV {
     f: File,
-    pub index: HashMap<ByteString, u64>,
+    path: PathBuf,
+    keyspaces: HashMap<ByteString, Keyspace>,
+}
+
+/// A view of the store restricted to a single namespace.
+pub struct Namespace<'a> {
+    store: &'a mut ActionKV,
+    name: ByteString,
 }
 
 macro_rules! debug {
@@ -35,22 +100,97 @@ macro_rules! debug {
 
 impl ActionKV {
     pub fn open(path: &Path) -> io::Result<Self> {
-        let f = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .append(true)
-                .open(path)?;
+        let f = ActionKV::open_log(path)?;
         debug!("file obj: {:#?}", f);
-        let index = HashMap::new();
-        Ok(ActionKV { f, index })
+        let path = path.to_path_buf();
+        let mut keyspaces = HashMap::new();
+        keyspaces.insert(DEFAULT_NAMESPACE.to_vec(), Keyspace::default());
+        Ok(ActionKV { f, path, keyspaces })
+    }
+
+    fn open_log(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(path)
+    }
+
+    pub fn namespace(&mut self, name: &ByteStr) -> Namespace<'_> {
+        Namespace { store: self, name: name.to_vec() }
+    }
+
+    pub fn namespaces(&self) -> Vec<ByteString> {
+        let mut names: Vec<ByteString> = self.keyspaces.keys().cloned().collect();
+        names.sort();
+        names
     }
 
+    pub fn keyspace(&self, namespace: &ByteStr) -> Option<&Keyspace> {
+        self.keyspaces.get(namespace)
+    }
+
+    /// The index of the default namespace.
+    pub fn index(&self) -> &HashMap<ByteString, u64> {
+        &self.keyspaces[DEFAULT_NAMESPACE].index
+    }
+
+    pub fn len(&self) -> usize {
+        self.index().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index().is_empty()
+    }
+
+    /// Declares a secondary index over the `field` of JSON values.
+    ///
+    /// Indexes are not persisted, so they should be declared before `load()`;
+    /// declaring one on an already loaded store scans the current values.
+    pub fn declare_index(&mut self, name: &str, field: &str) -> io::Result<()> {
+        self.declare_index_in(DEFAULT_NAMESPACE, name, field)
+    }
+
+    fn declare_index_in(&mut self, namespace: &ByteStr, name: &str, field: &str) -> io::Result<()> {
+        let mut index = SecondaryIndex::new(field);
+        let positions: Vec<u64> = match self.keyspaces.get(namespace) {
+            None => vec![],
+            Some(keyspace) => keyspace.index.values().copied().collect(),
+        };
+        for position in positions {
+            let kv = self.get_at(position)?;
+            index.update(&kv.key, &kv.value);
+        }
+        self.keyspaces.entry(namespace.to_vec())
+            .or_default()
+            .secondary
+            .insert(name.to_string(), index);
+        Ok(())
+    }
+
+    pub fn find_by(&self, index_name: &str, value: &str) -> io::Result<Vec<ByteString>> {
+        self.find_by_in(DEFAULT_NAMESPACE, index_name, value)
+    }
+
+    fn find_by_in(&self, namespace: &ByteStr, index_name: &str, value: &str) -> io::Result<Vec<ByteString>> {
+        let index = self.keyspaces.get(namespace)
+            .and_then(|keyspace| keyspace.secondary.get(index_name));
+        match index {
+            None => {
+                let error_msg = format!("secondary index {:?} is not declared", index_name);
+                Err(io::Error::new(io::ErrorKind::NotFound, error_msg))
+            },
+            Some(index) => Ok(index.find(value)),
+        }
+    }
+
+    /// Reads the log into the index. A record torn by a crash at the end of
+    /// the log is cut off, so that later writes don't land behind it.
     pub fn load(&mut self) -> io::Result<()> {
         let mut f = BufReader::new(&mut self.f);
 
-        loop {
-            let position = f.seek(SeekFrom::Current(0))?;
+        let end = loop {
+            let position = f.stream_position()?;
             debug!("load: position={}", position);
             let maybe_kv = ActionKV::process_record(&mut f);
             let kv = match maybe_kv {
@@ -58,13 +198,22 @@ impl ActionKV {
                 Err(err) => {
                     match err.kind() {
                         io::ErrorKind::UnexpectedEof => {
-                            break;
+                            break position;
                         },
                         _ => return Err(err),
                     }
                 }
             };
-            self.index.insert(kv.key, position);
+            let record_len = f.stream_position()? - position;
+            self.keyspaces.entry(kv.namespace)
+                .or_default()
+                .track(&kv.key, &kv.value, position, record_len);
+        };
+
+        if end < self.f.metadata()?.len() {
+            debug!("load: truncating torn record at {}", end);
+            self.f.set_len(end)?;
+            self.f.sync_all()?;
         }
         Ok(())
     }
@@ -74,7 +223,11 @@ impl ActionKV {
     }
 
     pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
-        let position = match self.index.get(key) {
+        self.get_in(DEFAULT_NAMESPACE, key)
+    }
+
+    fn get_in(&mut self, namespace: &ByteStr, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        let position = match self.keyspaces.get(namespace).and_then(|ks| ks.index.get(key)) {
             None => return Ok(None),
             Some(position) => *position,
         };
@@ -92,8 +245,9 @@ impl ActionKV {
     pub fn find(&mut self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
         let mut f = BufReader::new(&mut self.f);
         let mut found: Option<(u64, ByteString)> = None;
+        f.seek(SeekFrom::Start(0))?;
         loop {
-            let position = f.seek(SeekFrom::Current(0))?;
+            let position = f.stream_position()?;
             let maybe_kv = ActionKV::process_record(&mut f);
             let kv = match maybe_kv {
                 Ok(kv) => kv,
@@ -107,7 +261,7 @@ impl ActionKV {
                     }
                 }
             };
-            if kv.key == target {
+            if kv.namespace == DEFAULT_NAMESPACE && kv.key == target {
                 found = Some((position, kv.value));
             }
         }
@@ -115,31 +269,63 @@ impl ActionKV {
     }
 
     pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
-        let position = self.insert_but_ignore_index(key, value)?;
-        self.index.insert(key.to_vec(), position);
+        self.insert_in(DEFAULT_NAMESPACE, key, value)
+    }
+
+    fn insert_in(&mut self, namespace: &ByteStr, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        let position = self.append_record(namespace, key, value)?;
+        let record_len = self.seek_to_end()? - position;
+        self.keyspaces.entry(namespace.to_vec())
+            .or_default()
+            .track(key, value, position, record_len);
         Ok(())
     }
 
     pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
+        self.append_record(DEFAULT_NAMESPACE, key, value)
+    }
+
+    fn append_record(&mut self, namespace: &ByteStr, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
         let mut f = BufWriter::new(&mut self.f);
-        let key_len = key.len();
-        let val_len = value.len();
-        let mut tmp = ByteString::with_capacity(key_len + val_len);
+        let next_byte = SeekFrom::End(0);
+        let curr_position = f.seek(next_byte)?;
+        ActionKV::write_record(&mut f, namespace, key, value)?;
+        Ok(curr_position)
+    }
+
+    fn write_record<W: Write>(f: &mut W, namespace: &ByteStr, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        if namespace.len() > u8::MAX as usize {
+            let error_msg = format!("namespace is longer than {} bytes", u8::MAX);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error_msg));
+        }
 
-        for byte in key   { tmp.push(*byte); }
-        for byte in value { tmp.push(*byte); }
+        let mut tmp = ByteString::with_capacity(1 + namespace.len() + key.len() + value.len());
+        if !namespace.is_empty() {
+            tmp.push(namespace.len() as u8);
+            tmp.extend_from_slice(namespace);
+        }
+        tmp.extend_from_slice(key);
+
+        let key_len = tmp.len();
+        if key_len as u64 >= NAMESPACE_FLAG as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "key is too long"));
+        }
+        let key_len = if namespace.is_empty() {
+            key_len as u32
+        } else {
+            key_len as u32 | NAMESPACE_FLAG
+        };
+
+        tmp.extend_from_slice(value);
 
         let checksum = crc32::checksum_ieee(&tmp);
 
-        let next_byte = SeekFrom::End(0);
-        let curr_position = f.seek(SeekFrom::Current(0))?;
-        f.seek(next_byte)?;
         f.write_u32::<LittleEndian>(checksum)?;
-        f.write_u32::<LittleEndian>(key_len as u32)?;
-        f.write_u32::<LittleEndian>(val_len as u32)?;
+        f.write_u32::<LittleEndian>(key_len)?;
+        f.write_u32::<LittleEndian>(value.len() as u32)?;
         f.write_all(&tmp)?;
 
-        Ok(curr_position)
+        Ok(())
     }
 
     #[inline]
@@ -152,33 +338,201 @@ impl ActionKV {
         self.insert(key, b"")
     }
 
-    fn process_record<R: Read>(f: &mut R) -> io::Result<KeyValuePair> {
+    pub fn stats(&self) -> Stats {
+        self.keyspaces[DEFAULT_NAMESPACE].stats()
+    }
+
+    /// Rewrites the log, dropping every record superseded by a later write.
+    ///
+    /// Records are judged against the log itself, so the store need not be loaded.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.rewrite(|_| true)
+    }
+
+    fn compact_in(&mut self, namespace: &ByteStr) -> io::Result<()> {
+        self.rewrite(|name| name == namespace)
+    }
+
+    /// Copies the log into a new file, keeping stale records only for namespaces
+    /// that `compacted` rejects, and swaps it in place of the current one.
+    fn rewrite<F: Fn(&ByteStr) -> bool>(&mut self, compacted: F) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let result = self.write_compacted(&tmp_path, compacted)
+            .and_then(|done| std::fs::rename(&tmp_path, &self.path).map(|_| done));
+        let (moved, stats) = match result {
+            Ok(done) => done,
+            Err(err) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                return Err(err);
+            }
+        };
+        self.f = ActionKV::open_log(&self.path)?;
+
+        // keys the store never loaded stay out of the index
+        for (namespace, key, position) in moved {
+            if let Some(slot) = self.keyspaces.get_mut(&namespace).and_then(|k| k.index.get_mut(&key)) {
+                *slot = position;
+            }
+        }
+        for (name, keyspace) in self.keyspaces.iter_mut() {
+            let (records, bytes) = stats.get(name).copied().unwrap_or_default();
+            keyspace.records = records;
+            keyspace.bytes = bytes;
+        }
+        Ok(())
+    }
+
+    /// Writes the compacted log to `tmp_path`, returning where the live records
+    /// ended up and the per-namespace record counts and sizes.
+    ///
+    /// A record is live when it is the last one written for its key, which is
+    /// found by a first pass over the log rather than from the in-memory index.
+    fn write_compacted<F: Fn(&ByteStr) -> bool>(&mut self, tmp_path: &Path, compacted: F) -> io::Result<Compacted> {
+        let mut latest: HashMap<(ByteString, ByteString), u64> = HashMap::new();
+        let mut f = BufReader::new(&mut self.f);
+        f.seek(SeekFrom::Start(0))?;
+        loop {
+            let position = f.stream_position()?;
+            match ActionKV::process_record(&mut f) {
+                Ok(kv) => latest.insert((kv.namespace, kv.key), position),
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+        }
+
+        let mut moved = vec![];
+        let mut stats: HashMap<ByteString, (usize, u64)> = HashMap::new();
+        let mut out = BufWriter::new(File::create(tmp_path)?);
+        let mut out_position = 0;
+        f.seek(SeekFrom::Start(0))?;
+
+        loop {
+            let position = f.stream_position()?;
+            let kv = match ActionKV::process_record(&mut f) {
+                Ok(kv) => kv,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+            let KeyValuePair { namespace, key, value } = kv;
+            let id = (namespace, key);
+            let live = latest.get(&id) == Some(&position);
+            let (namespace, key) = id;
+            if !live && compacted(&namespace) {
+                continue;
+            }
+
+            ActionKV::write_record(&mut out, &namespace, &key, &value)?;
+            let record_len = out.stream_position()? - out_position;
+            let (records, bytes) = stats.entry(namespace.clone()).or_default();
+            *records += 1;
+            *bytes += record_len;
+            if live {
+                moved.push((namespace, key, out_position));
+            }
+            out_position += record_len;
+        }
+
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        Ok((moved, stats))
+    }
+
+    pub fn process_record<R: Read>(f: &mut R) -> io::Result<KeyValuePair> {
         let saved_checksum = f.read_u32::<LittleEndian>()?;
         debug!("record: crc={:04x}", saved_checksum);
 
         let key_len = f.read_u32::<LittleEndian>()?;
         let val_len = f.read_u32::<LittleEndian>()?;
-        let data_len = key_len + val_len;
-        let mut data = ByteString::with_capacity(data_len as usize);
+        let namespaced = key_len & NAMESPACE_FLAG != 0;
+        let key_len = key_len & !NAMESPACE_FLAG;
+        let data_len = key_len as u64 + val_len as u64;
+        let mut data = ByteString::new();
 
         {
             f.by_ref()
-            .take(data_len as u64)
+            .take(data_len)
             .read_to_end(&mut data)?;
         }
 
-        debug_assert_eq!(data.len(), data_len as usize);
+        // a record cut short by a crash is treated as the end of the log
+        if data.len() as u64 != data_len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated record"));
+        }
 
         let checksum = crc32::checksum_ieee(&data);
 
         if checksum != saved_checksum {
             let error_msg = format!("data corruption encountered ({:08x} != {:08x})", checksum, saved_checksum);
-            Err(io::Error::new(io::ErrorKind::InvalidData, error_msg))
-        } else {
-            let value = data.split_off(key_len as usize);
-            let key = data;
-            Ok(KeyValuePair { key, value })
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error_msg));
         }
+
+        let value = data.split_off(key_len as usize);
+        let mut key = data;
+        let mut namespace = ByteString::new();
+
+        if namespaced {
+            let ns_len = match key.first() {
+                Some(ns_len) if (*ns_len as usize) < key.len() => *ns_len as usize,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed namespace")),
+            };
+            let rest = key.split_off(1 + ns_len);
+            namespace = key.split_off(1);
+            key = rest;
+        }
+
+        Ok(KeyValuePair { namespace, key, value })
+    }
+}
+
+impl Namespace<'_> {
+    pub fn name(&self) -> &ByteStr {
+        &self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.keyspace(&self.name).map_or(0, |keyspace| keyspace.index.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn declare_index(&mut self, name: &str, field: &str) -> io::Result<()> {
+        self.store.declare_index_in(&self.name, name, field)
+    }
+
+    pub fn find_by(&self, index_name: &str, value: &str) -> io::Result<Vec<ByteString>> {
+        self.store.find_by_in(&self.name, index_name, value)
+    }
+
+    pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        self.store.get_in(&self.name, key)
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        self.store.insert_in(&self.name, key, value)
+    }
+
+    #[inline]
+    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        self.insert(key, value)
+    }
+
+    #[inline]
+    pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
+        self.insert(key, b"")
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.store.keyspace(&self.name).map(Keyspace::stats).unwrap_or_default()
+    }
+
+    /// Drops this namespace's stale records, leaving the other namespaces untouched.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.store.compact_in(&self.name)
     }
 }
 
@@ -186,19 +540,94 @@ impl ActionKV {
 mod tests {
     use super::*;
 
+    use proptest::prelude::*;
+    use tempfile::TempDir;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(ByteString, ByteString),
+        Delete(ByteString),
+        Get(ByteString),
+    }
+
+    fn key() -> impl Strategy<Value = ByteString> {
+        // a handful of short keys, so that sequences revisit the same keys often
+        prop::collection::vec(0u8..4, 1..3)
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (key(), prop::collection::vec(any::<u8>(), 0..32)).prop_map(|(k, v)| Op::Insert(k, v)),
+            key().prop_map(Op::Delete),
+            key().prop_map(Op::Get),
+        ]
+    }
+
+    fn apply(store: &mut ActionKV, model: &mut HashMap<ByteString, ByteString>, op: &Op) -> io::Result<()> {
+        match op {
+            Op::Insert(k, v) => {
+                store.insert(k, v)?;
+                model.insert(k.clone(), v.clone());
+            },
+            Op::Delete(k) => {
+                store.delete(k)?;
+                model.insert(k.clone(), vec![]);
+            },
+            Op::Get(k) => {
+                assert_eq!(store.get(k)?, model.get(k).cloned());
+            },
+        }
+        Ok(())
+    }
+
+    fn assert_matches(store: &mut ActionKV, model: &HashMap<ByteString, ByteString>) -> io::Result<()> {
+        assert_eq!(store.index().len(), model.len());
+        for (k, v) in model {
+            assert_eq!(store.get(k)?.as_ref(), Some(v));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_by() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("users.kv");
+        {
+            let mut store = ActionKV::open(&path)?;
+            store.declare_index("email", "email")?;
+            store.insert(b"alice", br#"{"email": "alice@example.com"}"#)?;
+            store.insert(b"bob", br#"{"email": "bob@example.com"}"#)?;
+            store.insert(b"carol", br#"{"email": "bob@example.com"}"#)?;
+            store.delete(b"carol")?;
+
+            assert_eq!(store.find_by("email", "bob@example.com")?, vec![b"bob".to_vec()]);
+            assert!(store.find_by("name", "bob").is_err());
+        }
+
+        let mut store = ActionKV::open(&path)?;
+        store.declare_index("email", "email")?;
+        store.load()?;
+        assert_eq!(store.find_by("email", "alice@example.com")?, vec![b"alice".to_vec()]);
+        assert_eq!(store.find_by("email", "bob@example.com")?, vec![b"bob".to_vec()]);
+
+        Ok(())
+    }
+
     #[test]
     fn test_open() {
-        let path = Path::new("/tmp/empty.kv");
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("empty.kv");
 
         let kv = ActionKV::open(&path);
 
         assert!(kv.is_ok());
-        assert_eq!(kv.unwrap().index.len(), 0);
+        assert_eq!(kv.unwrap().index().len(), 0);
     }
 
     #[test]
     fn test_load() -> Result<(), std::io::Error> {
-        let path = Path::new("/tmp/some.kv");
+        let dir = TempDir::new()?;
+        let path = dir.path().join("some.kv");
         {
             // should close file before reading in the store
             let file = File::create(&path)?;
@@ -212,8 +641,204 @@ mod tests {
 
         let mut kv = ActionKV::open(&path)?;
         kv.load()?;
-        assert_eq!(kv.index.len(), 1);
+        assert_eq!(kv.index().len(), 1);
 
         Ok(())
     }
+
+    #[test]
+    fn test_corrupted_record() -> Result<(), std::io::Error> {
+        let mut record = vec![];
+        record.write_u32::<LittleEndian>(0xDEADBEEF)?;
+        record.write_u32::<LittleEndian>(0x01)?;
+        record.write_u32::<LittleEndian>(0x01)?;
+        record.extend_from_slice(&[0xAA, 0xBB]);
+
+        let err = ActionKV::process_record(&mut io::Cursor::new(record)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_namespaces() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("tables.kv");
+        {
+            let mut store = ActionKV::open(&path)?;
+            store.insert(b"1", b"default")?;
+            store.namespace(b"users").insert(b"1", b"alice")?;
+            store.namespace(b"users").update(b"1", b"bob")?;
+            store.namespace(b"orders").insert(b"1", b"book")?;
+
+            assert_eq!(store.get(b"1")?, Some(b"default".to_vec()));
+            assert_eq!(store.namespace(b"users").get(b"1")?, Some(b"bob".to_vec()));
+            assert_eq!(store.namespace(b"carts").get(b"1")?, None);
+        }
+
+        let mut store = ActionKV::open(&path)?;
+        store.load()?;
+        assert_eq!(store.namespaces(), vec![b"".to_vec(), b"orders".to_vec(), b"users".to_vec()]);
+        assert_eq!(store.namespace(b"users").get(b"1")?, Some(b"bob".to_vec()));
+        assert_eq!(store.namespace(b"orders").get(b"1")?, Some(b"book".to_vec()));
+        assert_eq!(store.find(b"1")?.map(|(_, value)| value), Some(b"default".to_vec()));
+
+        let long_name = vec![b'x'; 256];
+        assert!(store.namespace(&long_name).insert(b"1", b"").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_namespace() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("compact.kv");
+        let mut store = ActionKV::open(&path)?;
+        for value in [b"a", b"b", b"c"] {
+            store.insert(b"key", value)?;
+            store.namespace(b"users").insert(b"key", value)?;
+        }
+        assert_eq!(store.namespace(b"users").stats().stale(), 2);
+
+        store.namespace(b"users").compact()?;
+        assert_eq!(store.namespace(b"users").stats().stale(), 0);
+        assert_eq!(store.stats().stale(), 2);
+        assert_eq!(store.namespace(b"users").get(b"key")?, Some(b"c".to_vec()));
+        assert_eq!(store.get(b"key")?, Some(b"c".to_vec()));
+
+        store.compact()?;
+        assert_eq!(store.stats(), Stats { keys: 1, records: 1, bytes: 16 });
+        assert_eq!(std::fs::metadata(&path)?.len(), 16 + 22);
+
+        let mut reopened = ActionKV::open(&path)?;
+        reopened.load()?;
+        assert_eq!(reopened.namespace(b"users").stats(), store.namespace(b"users").stats());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_without_load() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("compact.kv");
+        {
+            let mut store = ActionKV::open(&path)?;
+            store.insert(b"alice", b"1")?;
+            store.insert(b"alice", b"2")?;
+            store.insert(b"bob", b"3")?;
+            store.namespace(b"users").insert(b"carol", b"4")?;
+        }
+
+        let mut store = ActionKV::open(&path)?;
+        store.compact()?;
+        assert!(!dir.path().join("compact.kv.compact").exists());
+
+        let mut reopened = ActionKV::open(&path)?;
+        reopened.load()?;
+        assert_eq!(reopened.get(b"alice")?, Some(b"2".to_vec()));
+        assert_eq!(reopened.get(b"bob")?, Some(b"3".to_vec()));
+        assert_eq!(reopened.namespace(b"users").get(b"carol")?, Some(b"4".to_vec()));
+        assert_eq!(reopened.stats().stale(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_writes_after_torn_tail() -> Result<(), std::io::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("crash.kv");
+        {
+            let mut store = ActionKV::open(&path)?;
+            store.insert(b"a", b"1")?;
+        }
+        // half of a record header, as left by a crash mid-write
+        OpenOptions::new().append(true).open(&path)?.write_all(&[0xFF; 6])?;
+
+        let mut store = ActionKV::open(&path)?;
+        store.load()?;
+        store.insert(b"b", b"2")?;
+        drop(store);
+
+        let mut store = ActionKV::open(&path)?;
+        store.load()?;
+        assert_eq!(store.get(b"a")?, Some(b"1".to_vec()));
+        assert_eq!(store.get(b"b")?, Some(b"2".to_vec()));
+
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_compact_preserves_contents(ops in prop::collection::vec(op(), 0..64)) {
+            let dir = TempDir::new()?;
+            let path = dir.path().join("compact.kv");
+            let mut model = HashMap::new();
+
+            let mut store = ActionKV::open(&path)?;
+            for op in &ops {
+                apply(&mut store, &mut model, op)?;
+            }
+            store.compact()?;
+            assert_matches(&mut store, &model)?;
+            assert_eq!(store.stats().stale(), 0);
+
+            let mut reopened = ActionKV::open(&path)?;
+            reopened.load()?;
+            assert_matches(&mut reopened, &model)?;
+        }
+
+        #[test]
+        fn prop_matches_hashmap(ops in prop::collection::vec(op(), 0..64)) {
+            let dir = TempDir::new()?;
+            let path = dir.path().join("model.kv");
+            let mut model = HashMap::new();
+
+            let mut store = ActionKV::open(&path)?;
+            for op in &ops {
+                apply(&mut store, &mut model, op)?;
+            }
+            assert_matches(&mut store, &model)?;
+
+            let mut reopened = ActionKV::open(&path)?;
+            reopened.load()?;
+            assert_matches(&mut reopened, &model)?;
+        }
+
+        #[test]
+        fn prop_load_survives_truncation(
+            ops in prop::collection::vec(op(), 1..32),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let dir = TempDir::new()?;
+            let path = dir.path().join("crash.kv");
+            let mut model = HashMap::new();
+            // the model as it was at every record boundary of the log
+            let mut snapshots = vec![(0, model.clone())];
+
+            let mut store = ActionKV::open(&path)?;
+            for op in &ops {
+                apply(&mut store, &mut model, op)?;
+                snapshots.push((store.seek_to_end()?, model.clone()));
+            }
+            drop(store);
+
+            let file_len = std::fs::metadata(&path)?.len();
+            let offset = cut.index(file_len as usize + 1) as u64;
+            OpenOptions::new().write(true).open(&path)?.set_len(offset)?;
+
+            let (_, expected) = snapshots.iter()
+                .rev()
+                .find(|(end, _)| *end <= offset)
+                .unwrap();
+
+            let mut store = ActionKV::open(&path)?;
+            store.load()?;
+            assert_matches(&mut store, expected)?;
+        }
+
+        #[test]
+        fn prop_process_record_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+            let _ = ActionKV::process_record(&mut io::Cursor::new(bytes));
+        }
+    }
 }
@@ -0,0 +1,107 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::Value;
+
+use crate::{ByteStr, ByteString};
+
+/// Maps values of a JSON field to the keys whose documents contain them.
+///
+/// The field is a dotted path into the document, e.g. `email` or `address.city`.
+/// Values that are not valid JSON, or don't have the field, are not indexed.
+#[derive(Debug)]
+pub struct SecondaryIndex {
+    field: String,
+    entries: HashMap<String, BTreeSet<ByteString>>,
+    by_key: HashMap<ByteString, String>,
+}
+
+impl SecondaryIndex {
+    pub fn new(field: &str) -> Self {
+        SecondaryIndex {
+            field: field.to_string(),
+            entries: HashMap::new(),
+            by_key: HashMap::new(),
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_key.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_key.is_empty()
+    }
+
+    pub fn find(&self, value: &str) -> Vec<ByteString> {
+        match self.entries.get(value) {
+            None => vec![],
+            Some(keys) => keys.iter().cloned().collect(),
+        }
+    }
+
+    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) {
+        self.remove(key);
+        if let Some(field_value) = self.extract(value) {
+            self.entries.entry(field_value.clone()).or_default().insert(key.to_vec());
+            self.by_key.insert(key.to_vec(), field_value);
+        }
+    }
+
+    pub fn remove(&mut self, key: &ByteStr) {
+        let field_value = match self.by_key.remove(key) {
+            None => return,
+            Some(field_value) => field_value,
+        };
+        if let Some(keys) = self.entries.get_mut(&field_value) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.entries.remove(&field_value);
+            }
+        }
+    }
+
+    fn extract(&self, value: &ByteStr) -> Option<String> {
+        let doc: Value = serde_json::from_slice(value).ok()?;
+        let mut node = &doc;
+        for part in self.field.split('.') {
+            node = node.get(part)?;
+        }
+        match node {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let index = SecondaryIndex::new("address.city");
+
+        assert_eq!(index.extract(br#"{"address": {"city": "Oslo"}}"#), Some("Oslo".to_string()));
+        assert_eq!(index.extract(br#"{"address": {"zip": 1234}}"#), None);
+        assert_eq!(index.extract(b"not json"), None);
+    }
+
+    #[test]
+    fn test_update_moves_key() {
+        let mut index = SecondaryIndex::new("email");
+
+        index.update(b"alice", br#"{"email": "a@example.com"}"#);
+        index.update(b"alice", br#"{"email": "alice@example.com"}"#);
+
+        assert!(index.find("a@example.com").is_empty());
+        assert_eq!(index.find("alice@example.com"), vec![b"alice".to_vec()]);
+
+        index.update(b"alice", b"");
+        assert!(index.is_empty());
+    }
+}
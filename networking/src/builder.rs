@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use crate::{
+    ConnectionPool, HTTPReader, Headers, Interface, PageReader, PageReaderProtocol, ProxyConfig,
+    ReaderOptions, RedirectPolicy, Resolver, RetryPolicy, TCPReader, TlsConnector, Trace,
+};
+
+/// Configures a reader for one of the protocols.
+///
+/// Settings that only make sense for sockets `libnet` opens itself, such as
+/// the resolver, TLS roots, connection pool, userspace TCP stack and trace,
+/// are ignored by `HTTPReader`.
+pub struct ReaderBuilder {
+    protocol: PageReaderProtocol,
+    headers: Headers,
+    redirects: RedirectPolicy,
+    options: ReaderOptions,
+    resolver: Option<Resolver>,
+    tls: Option<TlsConnector>,
+    pool: Option<ConnectionPool>,
+    proxy: ProxyConfig,
+    userspace: Option<Interface>,
+    trace: Option<Arc<Trace>>,
+}
+
+impl ReaderBuilder {
+    pub fn new(protocol: PageReaderProtocol) -> Self {
+        ReaderBuilder {
+            protocol,
+            headers: Headers::new(),
+            redirects: RedirectPolicy::new(),
+            options: ReaderOptions::new(),
+            resolver: None,
+            tls: None,
+            pool: None,
+            proxy: ProxyConfig::none(),
+            userspace: None,
+            trace: None,
+        }
+    }
+
+    /// Sends a header field with every request, unless the request sets it.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.headers.insert("User-Agent", user_agent);
+        self
+    }
+
+    pub fn redirects(mut self, redirects: RedirectPolicy) -> Self {
+        self.redirects = redirects;
+        self
+    }
+
+    pub fn options(mut self, options: ReaderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.options.retry = retry;
+        self
+    }
+
+    pub fn resolver(mut self, resolver: Resolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsConnector) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn pool(mut self, pool: ConnectionPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Connects over a userspace TCP stack instead of the kernel's.
+    pub fn userspace(mut self, iface: Interface) -> Self {
+        self.userspace = Some(iface);
+        self
+    }
+
+    /// Records the traffic of every connection in `trace`.
+    pub fn trace(mut self, trace: Arc<Trace>) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    pub fn build(self) -> Box<dyn PageReader + Send + Sync> {
+        match self.protocol {
+            PageReaderProtocol::HTTP => Box::new(self.build_http()),
+            PageReaderProtocol::TCP => Box::new(self.build_tcp()),
+        }
+    }
+
+    pub fn build_http(self) -> HTTPReader {
+        HTTPReader { redirects: self.redirects, options: self.options, headers: self.headers, proxy: self.proxy }
+    }
+
+    pub fn build_tcp(self) -> TCPReader {
+        let mut reader = TCPReader {
+            redirects: self.redirects,
+            resolver: self.resolver,
+            tls: self.tls,
+            options: self.options,
+            headers: self.headers,
+            proxy: self.proxy,
+            userspace: self.userspace,
+            trace: self.trace,
+            ..TCPReader::new()
+        };
+        if let Some(pool) = self.pool {
+            reader.pool = pool;
+        }
+        reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::testing::{response, StandIn};
+
+    #[test]
+    fn test_default_headers() {
+        let server = StandIn::serve(vec![
+            response("200 OK", &[], "tcp"),
+            response("200 OK", &[], "http"),
+        ]);
+
+        for protocol in [PageReaderProtocol::TCP, PageReaderProtocol::HTTP] {
+            let reader = protocol.builder()
+                .user_agent("net-test/1.0")
+                .header("Accept", "text/plain")
+                .retry(RetryPolicy::none())
+                .build();
+            reader.read_page(&server.url("/")).unwrap();
+        }
+
+        for head in server.requests() {
+            let head = head.to_ascii_lowercase();
+            assert!(head.contains("user-agent: net-test/1.0\r\n"), "{}", head);
+            assert!(head.contains("accept: text/plain\r\n"), "{}", head);
+        }
+    }
+
+    #[test]
+    fn test_request_headers_win() {
+        let server = StandIn::serve(vec![response("200 OK", &[], "")]);
+        let reader = ReaderBuilder::new(PageReaderProtocol::TCP)
+            .header("Accept", "text/plain")
+            .build_tcp();
+
+        let mut request = crate::Request::get(server.url("/").parse().unwrap());
+        request.headers.insert("Accept", "application/json");
+        reader.fetch(&request).unwrap();
+
+        let head = &server.requests()[0];
+        assert!(head.contains("Accept: application/json\r\n"));
+        assert!(!head.contains("text/plain"));
+    }
+
+    #[test]
+    fn test_options() {
+        let options = ReaderOptions { read_timeout: Some(Duration::from_secs(1)), ..ReaderOptions::new() };
+        let reader = PageReaderProtocol::TCP.builder()
+            .options(options)
+            .retry(RetryPolicy::none())
+            .redirects(RedirectPolicy::none())
+            .pool(ConnectionPool::new(Duration::from_secs(1), 1))
+            .build_tcp();
+
+        assert_eq!(reader.options.read_timeout, Some(Duration::from_secs(1)));
+        assert_eq!(reader.options.retry, RetryPolicy::none());
+        assert_eq!(reader.redirect_policy(), RedirectPolicy::none());
+    }
+}
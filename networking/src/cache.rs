@@ -0,0 +1,458 @@
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::http::{self, Headers, Method, Request, Response};
+use crate::{PageError, PageReader, RedirectPolicy, Url};
+
+/// The first line of every cache file, so other files are never mistaken
+/// for entries.
+const MAGIC: &str = "libnet-cache/1";
+/// The most freshness guessed from `Last-Modified`, as RFC 9111 suggests.
+const MAX_HEURISTIC_FRESHNESS: Duration = Duration::from_secs(24 * 60 * 60);
+/// Statuses that may be stored without explicit freshness information.
+const CACHEABLE_BY_DEFAULT: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+/// Fields a `304 Not Modified` response can't update.
+const UNUPDATABLE: [&str; 5] = ["Content-Length", "Transfer-Encoding", "Content-Encoding", "Connection", "Keep-Alive"];
+
+/// Keeps responses fetched through another reader on disk, one file per URL.
+///
+/// Fresh responses are answered from disk. Stale ones are revalidated with
+/// `If-None-Match` or `If-Modified-Since`, and reused if the server answers
+/// `304 Not Modified`. A cache directory that can't be written to just means
+/// nothing gets cached.
+pub struct CachingReader<R> {
+    inner: R,
+    dir: PathBuf,
+}
+
+impl<R: PageReader> CachingReader<R> {
+    pub fn new<P: Into<PathBuf>>(inner: R, dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(CachingReader { inner, dir })
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Forgets every stored response.
+    pub fn clear(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "entry") {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{:016x}.entry", fnv1a(url.to_string().as_bytes())))
+    }
+}
+
+impl<R: PageReader> PageReader for CachingReader<R> {
+    fn fetch(&self, request: &Request) -> Result<Response, PageError> {
+        if request.method != Method::Get {
+            let response = self.inner.fetch(request)?;
+            // whatever was stored for the URL may have just changed
+            if !request.method.is_safe() && response.is_success() {
+                let _ = fs::remove_file(self.path(&request.url));
+            }
+            return Ok(response);
+        }
+
+        let directives = CacheControl::parse(&request.headers);
+        // the caller is revalidating or asking for a part itself
+        let conditional = ["If-None-Match", "If-Modified-Since", "Range"].iter().any(|name| request.headers.contains(name));
+        if directives.no_store || conditional {
+            return self.inner.fetch(request);
+        }
+
+        let path = self.path(&request.url);
+        let now = SystemTime::now();
+        let cached = Entry::load(&path).ok().flatten().filter(|entry| entry.matches(request));
+        if let Some(entry) = &cached {
+            if !directives.no_cache && entry.age(now) < entry.freshness_lifetime() {
+                return Ok(entry.served(now));
+            }
+        }
+
+        let mut revalidation = request.clone();
+        if let Some(entry) = &cached {
+            if let Some(etag) = entry.response.headers.get("ETag") {
+                revalidation.headers.insert("If-None-Match", etag);
+            }
+            if let Some(modified) = entry.response.headers.get("Last-Modified") {
+                revalidation.headers.insert("If-Modified-Since", modified);
+            }
+        }
+        let response = self.inner.fetch(&revalidation)?;
+
+        match cached {
+            Some(mut entry) if response.status == 304 => {
+                entry.update(&response.headers);
+                entry.stored = now;
+                let _ = entry.save(&path);
+                Ok(entry.served(now))
+            },
+            _ => {
+                if storable(&response) {
+                    let entry = Entry::new(request, response.clone(), now);
+                    let _ = entry.save(&path);
+                } else {
+                    let _ = fs::remove_file(&path);
+                }
+                Ok(response)
+            },
+        }
+    }
+
+    fn redirect_policy(&self) -> RedirectPolicy {
+        self.inner.redirect_policy()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline()
+    }
+}
+
+/// The `Cache-Control` directives `CachingReader` cares about.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(headers: &Headers) -> CacheControl {
+        let mut directives = CacheControl::default();
+        for directive in headers.get_all("Cache-Control").flat_map(|value| value.split(',')) {
+            let (name, argument) = match directive.split_once('=') {
+                None => (directive.trim(), None),
+                Some((name, argument)) => (name.trim(), Some(argument.trim().trim_matches('"'))),
+            };
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => directives.no_store = true,
+                "no-cache" => directives.no_cache = true,
+                "max-age" => directives.max_age = argument.and_then(|secs| secs.parse().ok()),
+                _ => {},
+            }
+        }
+        directives
+    }
+}
+
+/// A stored response, with what's needed to tell whether it still applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    url: String,
+    stored: SystemTime,
+    /// The values the request had for the fields named by `Vary`.
+    vary: Headers,
+    response: Response,
+}
+
+impl Entry {
+    fn new(request: &Request, response: Response, stored: SystemTime) -> Entry {
+        let mut vary = Headers::new();
+        for name in varying_fields(&response.headers) {
+            vary.append(name, request.headers.get(name).unwrap_or_default());
+        }
+        Entry { url: request.url.to_string(), stored, vary, response }
+    }
+
+    /// Reads an entry back; a missing or unreadable file is simply a miss.
+    fn load(path: &Path) -> io::Result<Option<Entry>> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut r = BufReader::new(file);
+        if http::read_line(&mut r)? != MAGIC {
+            return Ok(None);
+        }
+        let meta = http::read_fields(&mut r)?;
+        let head = http::read_head(&mut r)?;
+        let (url, stored, length) = match (meta.get("Url"), meta.get("Stored"), meta.get("Body-Length")) {
+            (Some(url), Some(stored), Some(length)) => {
+                let stored = stored.parse().ok().and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)));
+                match (stored, length.parse()) {
+                    (Some(stored), Ok(length)) => (url.to_string(), stored, length),
+                    _ => return Ok(None),
+                }
+            },
+            _ => return Ok(None),
+        };
+        let mut vary = Headers::new();
+        for (name, value) in meta.iter() {
+            if let Some(name) = name.strip_prefix("Vary-") {
+                vary.append(name, value);
+            }
+        }
+        let mut body = vec![];
+        r.take(length).read_to_end(&mut body)?;
+        if body.len() as u64 != length {
+            return Ok(None);
+        }
+
+        let response = Response { status: head.status, reason: head.reason, headers: head.headers, body };
+        Ok(Some(Entry { url, stored, vary, response }))
+    }
+
+    /// Writes the entry to a temporary file first, so that a reader never
+    /// sees half an entry.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let stored = self.stored.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut raw = format!("{}\r\nUrl: {}\r\nStored: {}\r\nBody-Length: {}\r\n", MAGIC, self.url, stored, self.response.body.len());
+        for (name, value) in self.vary.iter() {
+            raw.push_str(&format!("Vary-{}: {}\r\n", name, value));
+        }
+        raw.push_str(&format!("\r\nHTTP/1.1 {} {}\r\n", self.response.status, self.response.reason));
+        for (name, value) in self.response.headers.iter() {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        raw.push_str("\r\n");
+
+        let temporary = path.with_extension("tmp");
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(raw.as_bytes())?;
+        file.write_all(&self.response.body)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Whether the entry answers `request`: the same URL, and the same values
+    /// for any fields the response varies on.
+    fn matches(&self, request: &Request) -> bool {
+        self.url == request.url.to_string()
+            && self.vary.iter().all(|(name, value)| request.headers.get(name).unwrap_or_default() == value)
+    }
+
+    fn age(&self, now: SystemTime) -> Duration {
+        let age = self.response.headers.get("Age")
+            .and_then(|age| age.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+        now.duration_since(self.stored).unwrap_or_default().saturating_add(age)
+    }
+
+    /// How long after it was sent the response stays fresh (RFC 9111 section 4.2.1).
+    fn freshness_lifetime(&self) -> Duration {
+        let headers = &self.response.headers;
+        let directives = CacheControl::parse(headers);
+        if directives.no_cache {
+            return Duration::ZERO;
+        }
+        if let Some(max_age) = directives.max_age {
+            return Duration::from_secs(max_age);
+        }
+        let date = headers.get("Date").and_then(http::parse_date).unwrap_or(self.stored);
+        if let Some(expires) = headers.get("Expires") {
+            // an invalid date, such as 0, means already expired
+            return http::parse_date(expires)
+                .and_then(|expires| expires.duration_since(date).ok())
+                .unwrap_or_default();
+        }
+        match headers.get("Last-Modified").and_then(http::parse_date) {
+            Some(modified) => (date.duration_since(modified).unwrap_or_default() / 10).min(MAX_HEURISTIC_FRESHNESS),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Takes the fields of a `304 Not Modified` response, which describe the
+    /// stored response as it is now.
+    fn update(&mut self, headers: &Headers) {
+        let names: Vec<&str> = headers.iter()
+            .map(|(name, _)| name)
+            .filter(|name| !UNUPDATABLE.iter().any(|n| n.eq_ignore_ascii_case(name)))
+            .collect();
+        for name in &names {
+            self.response.headers.remove(name);
+        }
+        for (name, value) in headers.iter().filter(|(name, _)| names.contains(name)) {
+            self.response.headers.append(name, value);
+        }
+    }
+
+    /// The stored response, as handed out at `now`.
+    fn served(&self, now: SystemTime) -> Response {
+        let mut response = self.response.clone();
+        response.headers.insert("Age", &self.age(now).as_secs().to_string());
+        response
+    }
+}
+
+fn storable(response: &Response) -> bool {
+    let directives = CacheControl::parse(&response.headers);
+    let explicit = directives.max_age.is_some() || response.headers.contains("Expires");
+    let validated = response.headers.contains("ETag") || response.headers.contains("Last-Modified");
+    !directives.no_store
+        && !varying_fields(&response.headers).any(|name| name == "*")
+        && (CACHEABLE_BY_DEFAULT.contains(&response.status) || explicit)
+        && (explicit || validated)
+}
+
+fn varying_fields(headers: &Headers) -> impl Iterator<Item = &str> {
+    headers.get_all("Vary")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// The 64-bit FNV-1a hash, used to name cache files.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{response, StandIn, TempDir};
+    use crate::{RetryPolicy, TCPReader};
+
+    fn cached(dir: &TempDir) -> CachingReader<TCPReader> {
+        CachingReader::new(TCPReader::builder().retry(RetryPolicy::none()).build_tcp(), dir.path()).unwrap()
+    }
+
+    #[test]
+    fn test_fresh_responses_come_from_disk() {
+        let dir = TempDir::new();
+        let server = StandIn::serve(vec![
+            response("200 OK", &[("Cache-Control", "max-age=60"), ("Vary", "Accept-Language")], "english"),
+            response("200 OK", &[("Cache-Control", "max-age=60"), ("Vary", "Accept-Language")], "french"),
+        ]);
+        let reader = cached(&dir);
+        let mut request = Request::get(Url::parse(&server.url("/page")).unwrap());
+        request.headers.insert("Accept-Language", "en");
+
+        assert_eq!(reader.fetch(&request).unwrap().text(), "english");
+        let page = reader.fetch(&request).unwrap();
+        assert_eq!(page.text(), "english");
+        assert_eq!(page.headers.get("Age"), Some("0"));
+        assert_eq!(server.requests().len(), 1);
+
+        request.headers.insert("Accept-Language", "fr");
+        assert_eq!(reader.fetch(&request).unwrap().text(), "french");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_revalidates_with_etag() {
+        let dir = TempDir::new();
+        let server = StandIn::serve(vec![
+            response("200 OK", &[("ETag", "\"v1\""), ("Cache-Control", "no-cache")], "body"),
+            response("304 Not Modified", &[("ETag", "\"v1\""), ("Cache-Control", "max-age=60")], ""),
+        ]);
+        let url = server.url("/etag");
+
+        assert_eq!(cached(&dir).read_page(&url).unwrap().text(), "body");
+        // a new reader over the same directory still has the entry
+        let reader = cached(&dir);
+        let page = reader.read_page(&url).unwrap();
+        assert_eq!((page.status, page.text()), (200, "body".to_string()));
+        assert!(server.requests()[1].to_ascii_lowercase().contains("if-none-match: \"v1\"\r\n"), "{:?}", server.requests());
+
+        // the 304 made it fresh for another minute
+        assert_eq!(reader.read_page(&url).unwrap().text(), "body");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_revalidates_with_last_modified() {
+        let dir = TempDir::new();
+        let modified = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let server = StandIn::serve(vec![
+            response("200 OK", &[("Last-Modified", modified), ("Cache-Control", "max-age=0")], "old"),
+            response("200 OK", &[("Last-Modified", "Mon, 07 Nov 1994 08:49:37 GMT"), ("Cache-Control", "max-age=0")], "new"),
+        ]);
+        let reader = cached(&dir);
+        let url = server.url("/modified");
+
+        assert_eq!(reader.read_page(&url).unwrap().text(), "old");
+        assert_eq!(reader.read_page(&url).unwrap().text(), "new");
+
+        let requests = server.requests();
+        assert!(requests[1].to_ascii_lowercase().contains(&format!("if-modified-since: {}\r\n", modified.to_ascii_lowercase())));
+    }
+
+    #[test]
+    fn test_no_store() {
+        let dir = TempDir::new();
+        let server = StandIn::serve(vec![
+            response("200 OK", &[("Cache-Control", "no-store, max-age=60")], "secret"),
+            response("200 OK", &[("Cache-Control", "no-store, max-age=60")], "secret"),
+        ]);
+        let reader = cached(&dir);
+
+        reader.read_page(&server.url("/")).unwrap();
+        reader.read_page(&server.url("/")).unwrap();
+
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_unsafe_methods_invalidate() {
+        let dir = TempDir::new();
+        let server = StandIn::serve(vec![
+            response("200 OK", &[("Cache-Control", "max-age=60")], "v1"),
+            response("204 No Content", &[], ""),
+            response("200 OK", &[("Cache-Control", "max-age=60")], "v2"),
+        ]);
+        let reader = cached(&dir);
+        let url = Url::parse(&server.url("/item")).unwrap();
+
+        assert_eq!(reader.read_page(&server.url("/item")).unwrap().text(), "v1");
+        assert_eq!(reader.fetch(&Request::put(url).with_body("v2")).unwrap().status, 204);
+        assert_eq!(reader.read_page(&server.url("/item")).unwrap().text(), "v2");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_freshness_lifetime() {
+        let stored = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let entry = |headers: &[(&str, &str)]| {
+            let mut response = Response::new(200, "");
+            for (name, value) in headers {
+                response.headers.append(name, value);
+            }
+            Entry { url: String::new(), stored, vary: Headers::new(), response }
+        };
+
+        let date = "Sun, 09 Sep 2001 01:46:40 GMT";
+        assert_eq!(entry(&[("Cache-Control", "max-age=30"), ("Expires", "0")]).freshness_lifetime(), Duration::from_secs(30));
+        assert_eq!(entry(&[("Date", date), ("Expires", "Sun, 09 Sep 2001 01:47:40 GMT")]).freshness_lifetime(), Duration::from_secs(60));
+        assert_eq!(entry(&[("Expires", "0")]).freshness_lifetime(), Duration::ZERO);
+        assert_eq!(entry(&[("Date", date), ("Last-Modified", "Sun, 09 Sep 2001 01:30:00 GMT")]).freshness_lifetime(), Duration::from_secs(100));
+        assert_eq!(entry(&[("Date", date), ("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT")]).freshness_lifetime(), MAX_HEURISTIC_FRESHNESS);
+    }
+
+    #[test]
+    fn test_out_of_range_times() {
+        let dir = TempDir::new();
+        let path = dir.path().join("entry");
+        let raw = format!("{}\r\nUrl: http://example.com/\r\nStored: {}\r\nBody-Length: 0\r\n\r\nHTTP/1.1 200 OK\r\n\r\n", MAGIC, u64::MAX);
+        fs::write(&path, raw).unwrap();
+        assert!(Entry::load(&path).unwrap().is_none());
+
+        let mut response = Response::new(200, "");
+        response.headers.insert("Age", &u64::MAX.to_string());
+        let entry = Entry { url: String::new(), stored: UNIX_EPOCH, vary: Headers::new(), response };
+        assert_eq!(entry.age(UNIX_EPOCH + Duration::from_secs(5)), Duration::MAX);
+    }
+}
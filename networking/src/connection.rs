@@ -0,0 +1,169 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::options::remaining;
+use crate::tcp::UserTcpStream;
+use crate::tls::TlsStream;
+use crate::trace::Traced;
+
+/// A connection to a server, in the clear or over TLS, through the kernel's
+/// TCP or the userspace one.
+pub enum Stream {
+    Plain(TcpStream),
+    User(Box<UserTcpStream>),
+    Tls(Box<TlsStream>),
+    /// Any of the others, recording its traffic.
+    Traced(Box<Traced>),
+}
+
+impl Stream {
+    /// The underlying kernel socket, if there is one.
+    pub fn tcp(&self) -> Option<&TcpStream> {
+        match self {
+            Stream::Plain(stream) => Some(stream),
+            Stream::User(_) => None,
+            Stream::Tls(stream) => stream.get_ref().tcp(),
+            Stream::Traced(stream) => stream.get_ref().tcp(),
+        }
+    }
+
+    /// The local and remote addresses of the connection.
+    pub fn addrs(&self) -> Option<(SocketAddr, SocketAddr)> {
+        match self {
+            Stream::Plain(stream) => Some((stream.local_addr().ok()?, stream.peer_addr().ok()?)),
+            Stream::User(stream) => Some((stream.local_addr().into(), stream.peer_addr().into())),
+            Stream::Tls(stream) => stream.get_ref().addrs(),
+            Stream::Traced(stream) => stream.get_ref().addrs(),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.set_read_timeout(timeout),
+            Stream::User(stream) => stream.set_read_timeout(timeout),
+            Stream::Tls(stream) => stream.get_ref().set_read_timeout(timeout),
+            Stream::Traced(stream) => stream.get_ref().set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.set_write_timeout(timeout),
+            Stream::User(stream) => stream.set_write_timeout(timeout),
+            Stream::Tls(stream) => stream.get_ref().set_write_timeout(timeout),
+            Stream::Traced(stream) => stream.get_ref().set_write_timeout(timeout),
+        }
+    }
+
+    /// Checks that the server hasn't closed an idle connection, or sent
+    /// anything unexpected on it, without blocking.
+    pub fn is_open(&self) -> bool {
+        match self {
+            Stream::Plain(tcp) => {
+                if tcp.set_nonblocking(true).is_err() {
+                    return false;
+                }
+                let open = matches!(tcp.peek(&mut [0]), Err(err) if err.kind() == io::ErrorKind::WouldBlock);
+                tcp.set_nonblocking(false).is_ok() && open
+            },
+            Stream::User(stream) => stream.is_open(),
+            Stream::Tls(stream) => stream.get_ref().is_open(),
+            Stream::Traced(stream) => stream.get_ref().is_open(),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Plain(stream)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            Stream::User(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+            Stream::Traced(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::User(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+            Stream::Traced(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::User(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+            Stream::Traced(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Connects to the first of `addrs` that answers within `timeout`.
+pub(crate) fn connect(addrs: &[SocketAddr], timeout: Option<Duration>, deadline: Option<Instant>) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to");
+    for addr in addrs {
+        let result = match remaining(timeout, deadline)? {
+            None => TcpStream::connect(addr),
+            Some(timeout) => TcpStream::connect_timeout(addr, timeout),
+        };
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// A stream whose reads and writes give up after `timeout`, and all of them
+/// once `deadline` has passed.
+pub(crate) struct Timed<'a> {
+    stream: &'a mut Stream,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl<'a> Timed<'a> {
+    pub fn new(stream: &'a mut Stream, timeout: Option<Duration>, deadline: Option<Instant>) -> Self {
+        Timed { stream, timeout, deadline }
+    }
+}
+
+impl Read for Timed<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(remaining(self.timeout, self.deadline)?)?;
+        self.stream.read(buf).map_err(timed_out)
+    }
+}
+
+impl Write for Timed<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(remaining(self.timeout, self.deadline)?)?;
+        self.stream.write(buf).map_err(timed_out)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.set_write_timeout(remaining(self.timeout, self.deadline)?)?;
+        self.stream.flush().map_err(timed_out)
+    }
+}
+
+// a socket timeout shows up as WouldBlock on some platforms
+pub(crate) fn timed_out(err: io::Error) -> io::Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock => io::Error::new(io::ErrorKind::TimedOut, "operation timed out"),
+        _ => err,
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+
+use crate::http::Headers;
+
+/// Sent with requests unless they set their own.
+pub const ACCEPT_ENCODING: &str = "gzip, deflate";
+
+/// How far into an HTML document to look for a `<meta>` charset, as
+/// browsers do.
+const META_SNIFF_LEN: usize = 1024;
+
+/// Undoes a `Content-Encoding` as the body is read, leaving it as the
+/// server meant it; the headers lose the fields that described the encoded
+/// body. Codings we never ask for are left alone.
+pub fn decode_reader<'a, R: Read + 'a>(headers: &mut Headers, body: R) -> io::Result<Box<dyn Read + 'a>> {
+    let codings: Vec<String> = headers.get_all("Content-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(|coding| coding.trim().to_ascii_lowercase())
+        .filter(|coding| !coding.is_empty() && coding != "identity")
+        .collect();
+    let mut body: Box<dyn Read + 'a> = Box::new(body);
+    if codings.is_empty() || !codings.iter().all(|coding| matches!(coding.as_str(), "gzip" | "x-gzip" | "deflate")) {
+        return Ok(body);
+    }
+
+    // codings are listed in the order they were applied
+    for coding in codings.iter().rev() {
+        body = match coding.as_str() {
+            "deflate" => inflate(body)?,
+            _ => Box::new(GzDecoder::new(body)),
+        };
+    }
+    headers.remove("Content-Encoding");
+    headers.remove("Content-Length");
+    Ok(body)
+}
+
+/// `deflate` is meant to be zlib-wrapped, but some servers send the raw
+/// stream instead, so look at the header to tell.
+fn inflate<'a>(body: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+    let mut body = BufReader::new(body);
+    let start = body.fill_buf()?;
+    let zlib = start.len() >= 2
+        && start[0] & 0x0f == 8
+        && u16::from_be_bytes([start[0], start[1]]) % 31 == 0;
+    Ok(if zlib { Box::new(ZlibDecoder::new(body)) } else { Box::new(DeflateDecoder::new(body)) })
+}
+
+/// Picks the character encoding of a body: a byte order mark first, then
+/// the `Content-Type` charset, then a `<meta>` tag in HTML. Without any of
+/// those, UTF-8 if the body is valid UTF-8 and windows-1252 (which browsers
+/// use for Latin-1) otherwise.
+pub fn encoding(headers: &Headers, body: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+    let content_type = headers.get("Content-Type").unwrap_or_default();
+    if let Some(encoding) = charset_param(content_type).and_then(|label| Encoding::for_label(label.as_bytes())) {
+        return encoding;
+    }
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    if media_type.eq_ignore_ascii_case("text/html") || media_type.is_empty() {
+        if let Some(encoding) = meta_charset(&body[..body.len().min(META_SNIFF_LEN)]) {
+            return encoding;
+        }
+    }
+    if std::str::from_utf8(body).is_ok() {
+        UTF_8
+    } else {
+        WINDOWS_1252
+    }
+}
+
+/// The `charset` parameter of a media type, e.g. of `text/html; charset="utf-8"`.
+fn charset_param(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim().eq_ignore_ascii_case("charset").then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Finds `<meta charset="...">` or `<meta http-equiv="Content-Type"
+/// content="...; charset=...">`.
+fn meta_charset(head: &[u8]) -> Option<&'static Encoding> {
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+    for tag in head.split("<meta").skip(1) {
+        let tag = tag.split('>').next().unwrap_or_default();
+        let label = match tag.find("charset=") {
+            Some(at) => tag[at + "charset=".len()..].trim_start_matches(['"', '\'']),
+            None => continue,
+        };
+        let end = label.find(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace()).unwrap_or(label.len());
+        // a page can't declare itself in an encoding it isn't ASCII-compatible with
+        match Encoding::for_label(&label.as_bytes()[..end]) {
+            Some(encoding) if encoding.is_ascii_compatible() => return Some(encoding),
+            Some(_) => return Some(UTF_8),
+            None => {},
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    use crate::Response;
+
+    fn decode_body(response: &mut Response) -> io::Result<()> {
+        let body = std::mem::take(&mut response.body);
+        let mut decoded = vec![];
+        decode_reader(&mut response.headers, io::Cursor::new(body))?.read_to_end(&mut decoded)?;
+        response.body = decoded;
+        Ok(())
+    }
+
+    fn with_headers(headers: &[(&str, &str)], body: Vec<u8>) -> Response {
+        let mut response = Response::new(200, body);
+        for (name, value) in headers {
+            response.headers.append(name, value);
+        }
+        response
+    }
+
+    #[test]
+    fn test_decode_body() {
+        let mut gzip = GzEncoder::new(vec![], Compression::default());
+        gzip.write_all(b"hello, gzip").unwrap();
+        let mut response = with_headers(&[("Content-Encoding", "gzip"), ("Content-Length", "31")], gzip.finish().unwrap());
+        decode_body(&mut response).unwrap();
+        assert_eq!(response.body, b"hello, gzip");
+        assert!(!response.headers.contains("Content-Encoding") && !response.headers.contains("Content-Length"));
+
+        // deflate applied on top of gzip
+        let mut gzip = GzEncoder::new(vec![], Compression::default());
+        gzip.write_all(b"twice").unwrap();
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(&gzip.finish().unwrap()).unwrap();
+        let mut response = with_headers(&[("Content-Encoding", "gzip, deflate")], zlib.finish().unwrap());
+        decode_body(&mut response).unwrap();
+        assert_eq!(response.body, b"twice");
+
+        let mut response = with_headers(&[("Content-Encoding", "br")], b"\x0b\x02\x80".to_vec());
+        decode_body(&mut response).unwrap();
+        assert_eq!(response.body, b"\x0b\x02\x80");
+
+        let mut response = with_headers(&[("Content-Encoding", "gzip")], b"not gzip".to_vec());
+        assert!(decode_body(&mut response).is_err());
+
+        let mut raw = flate2::write::DeflateEncoder::new(vec![], Compression::default());
+        raw.write_all(b"raw deflate").unwrap();
+        let mut response = with_headers(&[("Content-Encoding", "deflate")], raw.finish().unwrap());
+        decode_body(&mut response).unwrap();
+        assert_eq!(response.body, b"raw deflate");
+    }
+
+    #[test]
+    fn test_encoding() {
+        let latin1 = b"caf\xe9".to_vec();
+        let html = |charset: &str| format!("<html><head><meta charset=\"{}\"></head>", charset).into_bytes();
+
+        assert_eq!(encoding(&Headers::new(), b"caf\xc3\xa9"), UTF_8);
+        assert_eq!(encoding(&Headers::new(), &latin1), WINDOWS_1252);
+        assert_eq!(with_headers(&[("Content-Type", "text/plain; charset=ISO-8859-1")], latin1).text(), "café");
+        assert_eq!(encoding(&Headers::new(), b"\xef\xbb\xbfbom"), UTF_8);
+        assert_eq!(encoding(&Headers::new(), &html("shift_jis")).name(), "Shift_JIS");
+        assert_eq!(encoding(&Headers::new(), &html("utf-16le")), UTF_8);
+
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+        let body = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=koi8-r\">";
+        assert_eq!(encoding(&headers, body).name(), "KOI8-R");
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{PageReader, Response, Robots, Url};
+
+/// A fetched page, or why it couldn't be fetched.
+#[derive(Debug)]
+pub struct Page {
+    pub url: Url,
+    /// How many links away from the starting URLs the page was found.
+    pub depth: usize,
+    pub result: Result<Response, String>,
+}
+
+/// Fetches a batch of URLs on a pool of worker threads, and optionally
+/// follows the links in the HTML pages it finds.
+///
+/// Every page is fetched at most once. Requests to the same host are spaced
+/// at least `host_delay` apart, or by the site's `Crawl-delay` if that's
+/// longer.
+#[derive(Debug, Clone)]
+pub struct Crawler {
+    pub workers: usize,
+    /// How many links deep to crawl; with `0` only the given URLs are fetched.
+    pub max_depth: usize,
+    /// Only follow links to the hosts of the given URLs.
+    pub same_host: bool,
+    /// Skip pages a site's `robots.txt` asks `user_agent` to stay away from.
+    pub robots: bool,
+    pub user_agent: String,
+    pub host_delay: Duration,
+}
+
+impl Crawler {
+    pub fn new() -> Self {
+        Crawler {
+            workers: 8,
+            max_depth: 0,
+            same_host: true,
+            robots: true,
+            user_agent: "net".to_string(),
+            host_delay: Duration::from_millis(250),
+        }
+    }
+
+    /// Fetches `urls` and whatever they lead to through `reader`, handing
+    /// each page to `visit` on the calling thread as soon as it's done.
+    pub fn run<R, F>(&self, reader: &R, urls: Vec<Url>, mut visit: F)
+    where
+        R: PageReader + Sync + ?Sized,
+        F: FnMut(Page),
+    {
+        let hosts: HashSet<String> = urls.iter().map(|url| url.host.clone()).collect();
+        let frontier = Frontier::new(urls);
+        let (tx, rx) = mpsc::channel();
+
+        thread::scope(|scope| {
+            for _ in 0..self.workers.max(1) {
+                let tx = tx.clone();
+                let (frontier, hosts) = (&frontier, &hosts);
+                scope.spawn(move || {
+                    while let Some((url, depth, claim)) = frontier.next() {
+                        let (page, links) = self.fetch(reader, frontier, url, depth);
+                        let links = links.into_iter().filter(|link| !self.same_host || hosts.contains(&link.host));
+                        claim.done(links, depth + 1);
+                        if tx.send(page).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            for page in rx {
+                visit(page);
+            }
+        });
+    }
+
+    fn fetch<R: PageReader + ?Sized>(&self, reader: &R, frontier: &Frontier, url: Url, depth: usize) -> (Page, Vec<Url>) {
+        let origin = format!("{}://{}", url.scheme, url.authority());
+        let robots = frontier.robots(&origin);
+        let robots = robots.get_or_init(|| {
+            if !self.robots {
+                return Robots::allow_all();
+            }
+            frontier.wait_turn(&origin, self.host_delay);
+            match reader.read_page(&format!("{}/robots.txt", origin)) {
+                Ok(response) if response.is_success() => Robots::parse(&response.text(), &self.user_agent),
+                // no robots.txt, or none we could read
+                _ => Robots::allow_all(),
+            }
+        });
+        if !robots.allowed(&url.request_target()) {
+            let result = Err("disallowed by robots.txt".to_string());
+            return (Page { url, depth, result }, vec![]);
+        }
+
+        frontier.wait_turn(&origin, self.host_delay.max(robots.crawl_delay.unwrap_or_default()));
+        let result = reader.read_page(&url.to_string()).map_err(|err| err.to_string());
+
+        let links = match &result {
+            Ok(response) if depth < self.max_depth && is_html(response) => {
+                links(&response.text()).iter()
+                    .filter_map(|link| url.join(link).ok())
+                    .filter(|link| matches!(link.scheme.as_str(), "http" | "https" | "tcp"))
+                    .collect()
+            },
+            _ => vec![],
+        };
+        (Page { url, depth, result }, links)
+    }
+}
+
+impl Default for Crawler {
+    fn default() -> Self {
+        Crawler::new()
+    }
+}
+
+/// The URLs waiting to be fetched, and what's known about their hosts.
+struct Frontier {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    queue: VecDeque<(Url, usize)>,
+    seen: HashSet<Url>,
+    /// Pages being fetched, which may still add links to the queue.
+    busy: usize,
+    hosts: HashMap<String, Host>,
+}
+
+#[derive(Default)]
+struct Host {
+    robots: Arc<OnceLock<Robots>>,
+    next_request: Option<Instant>,
+}
+
+impl Frontier {
+    fn new(urls: Vec<Url>) -> Self {
+        let mut state = State { queue: VecDeque::new(), seen: HashSet::new(), busy: 0, hosts: HashMap::new() };
+        for url in urls {
+            if state.seen.insert(url.clone()) {
+                state.queue.push_back((url, 0));
+            }
+        }
+        Frontier { state: Mutex::new(state), changed: Condvar::new() }
+    }
+
+    /// Takes the next URL to fetch, waiting while other workers may still
+    /// find more. Returns `None` once the crawl is over.
+    fn next(&self) -> Option<(Url, usize, Claim<'_>)> {
+        let mut state = self.lock();
+        loop {
+            if let Some((url, depth)) = state.queue.pop_front() {
+                state.busy += 1;
+                return Some((url, depth, Claim { frontier: self }));
+            }
+            if state.busy == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    /// Queues the links found on a page, unless they have been seen before.
+    fn queue<I: IntoIterator<Item = Url>>(&self, links: I, depth: usize) {
+        let mut state = self.lock();
+        for link in links {
+            if state.seen.insert(link.clone()) {
+                state.queue.push_back((link, depth));
+            }
+        }
+    }
+
+    fn robots(&self, origin: &str) -> Arc<OnceLock<Robots>> {
+        self.lock().hosts.entry(origin.to_string()).or_default().robots.clone()
+    }
+
+    /// Sleeps until a request to `origin` is due, keeping the next one `delay` away.
+    fn wait_turn(&self, origin: &str, delay: Duration) {
+        let now = Instant::now();
+        let at = {
+            let mut state = self.lock();
+            let host = state.hosts.entry(origin.to_string()).or_default();
+            let at = host.next_request.map_or(now, |next| next.max(now));
+            host.next_request = Some(at + delay);
+            at
+        };
+        thread::sleep(at - now);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// A page taken from the frontier. It counts as being fetched until it is
+/// dropped, even by a worker that panics, so the others never wait on it
+/// for good.
+struct Claim<'a> {
+    frontier: &'a Frontier,
+}
+
+impl Claim<'_> {
+    /// Marks the page as done and queues the links found on it.
+    fn done<I: IntoIterator<Item = Url>>(self, links: I, depth: usize) {
+        self.frontier.queue(links, depth);
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        let mut state = self.frontier.lock();
+        state.busy -= 1;
+        self.frontier.changed.notify_all();
+    }
+}
+
+fn is_html(response: &Response) -> bool {
+    response.headers.get("Content-Type").is_none_or(|kind| kind.to_ascii_lowercase().contains("html"))
+}
+
+/// The targets of the `href` attributes in `html`, as written.
+pub fn links(html: &str) -> Vec<String> {
+    let lower = html.to_ascii_lowercase();
+    let mut links = vec![];
+    let mut rest = 0;
+    while let Some(found) = lower[rest..].find("href") {
+        let start = rest + found;
+        rest = start + 4;
+        // only whole attribute names
+        if !lower[..start].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let value = html[rest..].trim_start();
+        let value = match value.strip_prefix('=') {
+            None => continue,
+            Some(value) => value.trim_start(),
+        };
+        let link = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or_default(),
+            _ => value.split(|c: char| c.is_ascii_whitespace() || c == '>').next().unwrap_or_default(),
+        };
+        let link = link.trim();
+        if !link.is_empty() && !link.starts_with('#') && !has_other_scheme(link) {
+            links.push(link.replace("&amp;", "&"));
+        }
+    }
+    links
+}
+
+/// Catches `mailto:`, `javascript:` and the like, which `Url::join` would
+/// take for relative paths.
+fn has_other_scheme(link: &str) -> bool {
+    match link.split_once(':') {
+        None => false,
+        Some((scheme, _)) => {
+            !scheme.is_empty()
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+                && !matches!(scheme.to_ascii_lowercase().as_str(), "http" | "https" | "tcp")
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{response, StandIn};
+    use crate::{PageError, Request, TCPReader};
+
+    const HTML: [(&str, &str); 1] = [("Content-Type", "text/html; charset=utf-8")];
+
+    #[test]
+    fn test_links() {
+        let html = r##"<p><a class="x" HREF="/a?x=1&amp;y=2">a</a> <a href='b.html'>b</a>
+            <a href=c>c</a> <a href="#top">top</a> <a href="mailto:me@example.com">me</a>
+            <link rel="stylesheet" href = "https://cdn.example.com/site.css" >
+            <a data-href="/not-a-link">no</a>"##;
+
+        assert_eq!(links(html), ["/a?x=1&y=2", "b.html", "c", "https://cdn.example.com/site.css"]);
+    }
+
+    #[test]
+    fn test_batch() {
+        let server = StandIn::route(vec![
+            ("/one", response("200 OK", &HTML, r#"<a href="/three">three</a>"#)),
+            ("/two", response("200 OK", &[], "two")),
+        ]);
+        let urls = ["/one", "/two", "/missing", "/one"].iter()
+            .map(|path| Url::parse(&server.url(path)).unwrap())
+            .collect();
+        let crawler = Crawler { host_delay: Duration::ZERO, ..Crawler::new() };
+
+        let mut pages = vec![];
+        crawler.run(&TCPReader::new(), urls, |page| pages.push(page));
+
+        let mut statuses: Vec<(String, u16)> = pages.iter()
+            .map(|page| (page.url.path.clone(), page.result.as_ref().unwrap().status))
+            .collect();
+        statuses.sort();
+        assert_eq!(statuses, [("/missing".to_string(), 404), ("/one".to_string(), 200), ("/two".to_string(), 200)]);
+    }
+
+    #[test]
+    fn test_crawl() {
+        let server = StandIn::route(vec![
+            ("/robots.txt", response("200 OK", &[], "User-agent: *\nDisallow: /private\n")),
+            ("/", response("200 OK", &HTML, r#"<a href="/a">a</a> <a href="/private/x">x</a>
+                <a href="http://elsewhere.example/">elsewhere</a>"#)),
+            ("/a", response("200 OK", &HTML, r#"<a href="b">b</a> <a href="/">home</a>"#)),
+            ("/b", response("200 OK", &HTML, r#"<a href="/c">c</a>"#)),
+            ("/c", response("200 OK", &HTML, "too deep")),
+        ]);
+        let crawler = Crawler { max_depth: 2, host_delay: Duration::ZERO, workers: 3, ..Crawler::new() };
+
+        let mut pages = vec![];
+        let start = Url::parse(&server.url("/")).unwrap();
+        crawler.run(&TCPReader::new(), vec![start], |page| pages.push(page));
+
+        let mut visited: Vec<(String, usize, bool)> = pages.iter()
+            .map(|page| (page.url.path.clone(), page.depth, page.result.is_ok()))
+            .collect();
+        visited.sort();
+        assert_eq!(visited, [
+            ("/".to_string(), 0, true),
+            ("/a".to_string(), 1, true),
+            ("/b".to_string(), 2, true),
+            ("/private/x".to_string(), 1, false),
+        ]);
+        let robots = server.requests().iter().filter(|head| head.starts_with("GET /robots.txt ")).count();
+        assert_eq!(robots, 1);
+    }
+
+    #[test]
+    fn test_host_delay() {
+        let server = StandIn::route(vec![("/", response("200 OK", &[], "ok"))]);
+        let urls = ["/?1", "/?2", "/?3"].iter().map(|path| Url::parse(&server.url(path)).unwrap()).collect();
+        let crawler = Crawler { robots: false, host_delay: Duration::from_millis(100), ..Crawler::new() };
+
+        let started = Instant::now();
+        crawler.run(&TCPReader::new(), urls, |_| {});
+
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    /// Fails hard on `/boom`, and answers everything else.
+    struct Explosive;
+
+    impl PageReader for Explosive {
+        fn fetch(&self, request: &Request) -> Result<Response, PageError> {
+            assert_ne!(request.url.path, "/boom", "reader blew up");
+            Ok(Response::new(200, "ok"))
+        }
+    }
+
+    #[test]
+    fn test_worker_panic_ends_crawl() {
+        let urls: Vec<Url> = ["/boom", "/a", "/b", "/c"].iter()
+            .map(|path| Url::parse(&format!("http://example.com{}", path)).unwrap())
+            .collect();
+        let crawler = Crawler { robots: false, host_delay: Duration::ZERO, workers: 2, ..Crawler::new() };
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let result = std::panic::catch_unwind(|| {
+                let mut fetched = 0;
+                crawler.run(&Explosive, urls, |_| fetched += 1);
+                fetched
+            });
+            let _ = tx.send(result.is_err());
+        });
+
+        // the panic comes back out of `run` once the other pages are done
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Ok(true));
+    }
+}
@@ -0,0 +1,464 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How many CNAMEs are followed before giving up on a name.
+const MAX_CNAME_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    A,
+    CNAME,
+    AAAA,
+}
+
+impl RecordType {
+    fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::CNAME => 5,
+            RecordType::AAAA => 28,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    CNAME(String),
+    Other(u16, Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.data {
+            RecordData::A(addr) => write!(f, "{} {} A {}", self.name, self.ttl, addr),
+            RecordData::AAAA(addr) => write!(f, "{} {} AAAA {}", self.name, self.ttl, addr),
+            RecordData::CNAME(target) => write!(f, "{} {} CNAME {}", self.name, self.ttl, target),
+            RecordData::Other(code, data) => write!(f, "{} {} TYPE{} ({} bytes)", self.name, self.ttl, code, data.len()),
+        }
+    }
+}
+
+/// A stub resolver that sends queries over UDP to a single name server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolver {
+    pub server: SocketAddr,
+    pub timeout: Duration,
+    pub attempts: usize,
+}
+
+impl Resolver {
+    pub fn new(server: SocketAddr) -> Self {
+        Resolver { server, timeout: Duration::from_secs(2), attempts: 3 }
+    }
+
+    /// Uses the first `nameserver` listed in `/etc/resolv.conf`.
+    pub fn from_system() -> io::Result<Self> {
+        let conf = fs::read_to_string("/etc/resolv.conf")?;
+        conf.lines()
+            .filter_map(|line| line.trim().strip_prefix("nameserver"))
+            .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+            .map(|ip| Resolver::new(SocketAddr::new(ip, 53)))
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no nameserver in /etc/resolv.conf"))
+    }
+
+    /// Looks up the IPv4 and IPv6 addresses of `name`, following CNAMEs.
+    pub fn resolve(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        if let Ok(ip) = name.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+
+        let mut addrs = vec![];
+        let mut failure = None;
+        for kind in [RecordType::A, RecordType::AAAA] {
+            let mut name = name.to_string();
+            for _ in 0..MAX_CNAME_DEPTH {
+                let records = match self.query(&name, kind) {
+                    Ok(records) => records,
+                    Err(err) => {
+                        failure = Some(err);
+                        break;
+                    },
+                };
+                let found: Vec<IpAddr> = records.iter()
+                    .filter_map(|record| match record.data {
+                        RecordData::A(addr) => Some(IpAddr::V4(addr)),
+                        RecordData::AAAA(addr) => Some(IpAddr::V6(addr)),
+                        _ => None,
+                    })
+                    .collect();
+                let alias = records.iter().find_map(|record| match &record.data {
+                    RecordData::CNAME(target) => Some(target.clone()),
+                    _ => None,
+                });
+                match alias {
+                    // the server didn't chase the alias for us
+                    Some(target) if found.is_empty() => name = target,
+                    _ => {
+                        addrs.extend(found);
+                        break;
+                    },
+                }
+            }
+        }
+
+        if addrs.is_empty() {
+            if let Some(err) = failure {
+                return Err(err);
+            }
+            let error_msg = format!("{} has no addresses", name);
+            return Err(io::Error::new(io::ErrorKind::NotFound, error_msg));
+        }
+        Ok(addrs)
+    }
+
+    /// Sends a single query and returns the answer section of the response.
+    pub fn query(&self, name: &str, kind: RecordType) -> io::Result<Vec<Record>> {
+        let bind_addr: SocketAddr = match self.server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(self.server)?;
+
+        let id: u16 = rand::random();
+        let query = build_query(id, name, kind)?;
+        let mut buf = [0u8; 4096];
+        // reported if no good answer arrives, as it may explain why
+        let mut stray = None;
+
+        for _ in 0..self.attempts.max(1) {
+            socket.send(&query)?;
+            // stray datagrams don't buy an attempt more time
+            let deadline = Instant::now() + self.timeout;
+            loop {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    break;
+                }
+                socket.set_read_timeout(Some(left))?;
+                let n = match socket.recv(&mut buf) {
+                    Ok(n) => n,
+                    Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+                    Err(err) => return Err(err),
+                };
+                let message = match parse_response(&buf[..n]) {
+                    Ok(message) => message,
+                    Err(err) => {
+                        stray = Some(err);
+                        continue;
+                    },
+                };
+                // a late answer to an earlier query or a spoofing attempt
+                if message.id != id {
+                    continue;
+                }
+                return match message.rcode {
+                    0 => Ok(message.answers),
+                    3 => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", name))),
+                    rcode => Err(io::Error::other(format!("name server failed with rcode {}", rcode))),
+                };
+            }
+        }
+        Err(stray.unwrap_or_else(|| io::Error::new(io::ErrorKind::TimedOut, format!("no answer from {}", self.server))))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    pub rcode: u8,
+    pub answers: Vec<Record>,
+}
+
+pub fn build_query(id: u16, name: &str, kind: RecordType) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(512);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&0x0100u16.to_be_bytes()); // recursion desired
+    query.extend_from_slice(&1u16.to_be_bytes());
+    query.extend_from_slice(&[0; 6]);
+    encode_name(&mut query, name)?;
+    query.extend_from_slice(&kind.code().to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes()); // IN
+    Ok(query)
+}
+
+pub fn parse_response(msg: &[u8]) -> io::Result<Message> {
+    let mut r = Cursor { msg, pos: 0 };
+    let id = r.u16()?;
+    let flags = r.u16()?;
+    if flags & 0x8000 == 0 {
+        return Err(malformed("not a response"));
+    }
+    if flags & 0x0200 != 0 {
+        return Err(malformed("response was truncated"));
+    }
+    let rcode = (flags & 0x000F) as u8;
+    let questions = r.u16()?;
+    let answers = r.u16()?;
+    r.u16()?;
+    r.u16()?;
+
+    for _ in 0..questions {
+        r.name()?;
+        r.take(4)?;
+    }
+
+    let mut records = Vec::with_capacity(answers as usize);
+    for _ in 0..answers {
+        let name = r.name()?;
+        let kind = r.u16()?;
+        let _class = r.u16()?;
+        let ttl = r.u32()?;
+        let len = r.u16()? as usize;
+        let start = r.pos;
+        let rdata = r.take(len)?;
+        let data = match (kind, len) {
+            (1, 4) => RecordData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            (28, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                RecordData::AAAA(Ipv6Addr::from(octets))
+            },
+            (5, _) => RecordData::CNAME(Cursor { msg, pos: start }.name()?),
+            _ => RecordData::Other(kind, rdata.to_vec()),
+        };
+        records.push(Record { name, ttl, data });
+    }
+
+    Ok(Message { id, rcode, answers: records })
+}
+
+fn encode_name(buf: &mut Vec<u8>, name: &str) -> io::Result<()> {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            let error_msg = format!("invalid domain name {:?}", name);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error_msg));
+        }
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    Ok(())
+}
+
+struct Cursor<'a> {
+    msg: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.msg.get(self.pos..self.pos + n).ok_or_else(|| malformed("message ended early"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed name, leaving the cursor after its first part.
+    fn name(&mut self) -> io::Result<String> {
+        let mut labels = vec![];
+        let mut pos = self.pos;
+        let mut jumped = false;
+        // each pointer must go backwards, so this bounds the number of jumps
+        let mut limit = pos;
+
+        loop {
+            let len = *self.msg.get(pos).ok_or_else(|| malformed("name ended early"))? as usize;
+            match len & 0xC0 {
+                0x00 if len == 0 => {
+                    if !jumped {
+                        self.pos = pos + 1;
+                    }
+                    break;
+                },
+                0x00 => {
+                    let label = self.msg.get(pos + 1..pos + 1 + len).ok_or_else(|| malformed("label ended early"))?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos += 1 + len;
+                },
+                0xC0 => {
+                    let low = *self.msg.get(pos + 1).ok_or_else(|| malformed("pointer ended early"))? as usize;
+                    let target = ((len & 0x3F) << 8) | low;
+                    if target >= limit {
+                        return Err(malformed("name pointer loops"));
+                    }
+                    if !jumped {
+                        self.pos = pos + 2;
+                        jumped = true;
+                    }
+                    limit = target;
+                    pos = target;
+                },
+                _ => return Err(malformed("unknown label type")),
+            }
+        }
+        Ok(labels.join("."))
+    }
+}
+
+fn malformed(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed DNS message: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use crate::testing::{response, StandIn};
+    use crate::{PageReader, TCPReader};
+
+    /// Answers every query for `www.example.com` with a CNAME to `example.com`
+    /// and every query for `example.com` with the loopback address of the asked type.
+    fn mock_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            loop {
+                let (n, peer) = socket.recv_from(&mut buf).unwrap();
+                let query = &buf[..n];
+                let mut r = Cursor { msg: query, pos: 12 };
+                let name = r.name().unwrap();
+                let kind = r.u16().unwrap();
+
+                let mut response = query.to_vec();
+                response[2] |= 0x80;
+                let answer = match (name.as_str(), kind) {
+                    ("www.example.com", _) => {
+                        let mut rdata = vec![];
+                        encode_name(&mut rdata, "example.com").unwrap();
+                        Some((5u16, rdata))
+                    },
+                    ("example.com", 1) => Some((1, vec![127, 0, 0, 1])),
+                    ("example.com", 28) => Some((28, Ipv6Addr::LOCALHOST.octets().to_vec())),
+                    _ => None,
+                };
+                match answer {
+                    None => response[3] |= 3,
+                    Some((kind, rdata)) => {
+                        response[7] = 1;
+                        response.extend_from_slice(&[0xC0, 12]);
+                        response.extend_from_slice(&kind.to_be_bytes());
+                        response.extend_from_slice(&[0, 1, 0, 0, 0x0E, 0x10]);
+                        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+                        response.extend_from_slice(&rdata);
+                    },
+                }
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_build_query() {
+        let query = build_query(0xABCD, "example.com", RecordType::A).unwrap();
+
+        assert_eq!(&query[..4], &[0xAB, 0xCD, 0x01, 0x00]);
+        assert_eq!(&query[12..], b"\x07example\x03com\x00\x00\x01\x00\x01");
+        assert!(build_query(1, "bad..name", RecordType::A).is_err());
+    }
+
+    #[test]
+    fn test_resolve_follows_cname() {
+        let resolver = Resolver::new(mock_server());
+
+        let records = resolver.query("www.example.com", RecordType::A).unwrap();
+        assert_eq!(records[0].data, RecordData::CNAME("example.com".to_string()));
+        assert_eq!(records[0].ttl, 3600);
+
+        let addrs = resolver.resolve("www.example.com").unwrap();
+        assert_eq!(addrs, vec![
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ]);
+    }
+
+    #[test]
+    fn test_tcp_reader_uses_resolver() {
+        let server = StandIn::serve(vec![response("200 OK", &[], "resolved")]);
+        let mut reader = TCPReader::new();
+        reader.resolver = Some(Resolver::new(mock_server()));
+
+        let page = reader.read_page(&format!("http://www.example.com:{}/", server.port)).unwrap();
+
+        assert_eq!(page.text(), "resolved");
+        assert!(server.requests()[0].contains(&format!("Host: www.example.com:{}", server.port)));
+    }
+
+    #[test]
+    fn test_nxdomain() {
+        let resolver = Resolver::new(mock_server());
+
+        let err = resolver.resolve("missing.example.com").unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_skips_malformed_datagrams() {
+        // sends junk ahead of every answer, and answers only the second attempt
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            for attempt in 0..2 {
+                let (n, peer) = socket.recv_from(&mut buf).unwrap();
+                socket.send_to(b"junk", peer).unwrap();
+                if attempt == 1 {
+                    let mut response = buf[..n].to_vec();
+                    response[2] |= 0x80;
+                    socket.send_to(&response, peer).unwrap();
+                }
+            }
+        });
+        let resolver = Resolver { timeout: Duration::from_millis(200), ..Resolver::new(addr) };
+
+        assert_eq!(resolver.query("example.com", RecordType::A).unwrap(), vec![]);
+
+        // with nothing but junk, the junk is what's reported
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = silent.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((_, peer)) = silent.recv_from(&mut buf) {
+                let _ = silent.send_to(b"junk", peer);
+            }
+        });
+        let resolver = Resolver { timeout: Duration::from_millis(100), attempts: 2, ..Resolver::new(addr) };
+
+        assert_eq!(resolver.query("example.com", RecordType::A).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_pointer_loop() {
+        let mut msg = vec![0, 1, 0x80, 0, 0, 0, 0, 1, 0, 0, 0, 0];
+        msg.extend_from_slice(&[0xC0, 12]);
+
+        assert!(parse_response(&msg).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::http::Request;
+use crate::{PageError, PageReader, Url};
+
+/// How much of a download has arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes in the file so far, including any from an earlier attempt.
+    pub downloaded: u64,
+    /// The size of the whole file, if the server said.
+    pub total: Option<u64>,
+}
+
+/// Saves the body of `url` to `path` as it arrives, calling `progress` after
+/// every chunk. With `resume`, a partial file left by an earlier attempt is
+/// continued with a `Range` request; servers that don't support ranges send
+/// the whole file again. Returns the size of the file.
+pub fn download<R, F>(reader: &R, url: &Url, path: &Path, resume: bool, mut progress: F) -> Result<u64, PageError>
+where
+    R: PageReader + ?Sized,
+    F: FnMut(Progress),
+{
+    let existing = match std::fs::metadata(path) {
+        Ok(metadata) if resume && metadata.is_file() => metadata.len(),
+        _ => 0,
+    };
+    let mut request = Request::get(url.clone());
+    // a range of a compressed body can't be decompressed on its own
+    request.headers.insert("Accept-Encoding", "identity");
+    if existing > 0 {
+        request.headers.insert("Range", &format!("bytes={}-", existing));
+    }
+
+    let mut response = reader.stream(request)?;
+    let content_range = response.headers.get("Content-Range").and_then(ContentRange::parse);
+    let (mut file, mut downloaded, total) = match response.status {
+        206 if existing > 0 => {
+            let range = content_range.ok_or("partial response without a usable Content-Range")?;
+            if range.first != Some(existing) {
+                return Err(format!("asked to resume at byte {}, but the server sent {:?}", existing, range).into());
+            }
+            (OpenOptions::new().append(true).open(path)?, existing, range.total)
+        },
+        // the file was complete already
+        416 if existing > 0 && content_range.and_then(|range| range.total) == Some(existing) => {
+            progress(Progress { downloaded: existing, total: Some(existing) });
+            return Ok(existing);
+        },
+        status if (200..300).contains(&status) => (File::create(path)?, 0, response.content_length()),
+        status => return Err(format!("{} answered {} {}", url, status, response.reason).into()),
+    };
+
+    progress(Progress { downloaded, total });
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = match response.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        file.write_all(&buf[..n])?;
+        downloaded += n as u64;
+        progress(Progress { downloaded, total });
+    }
+    file.sync_all()?;
+
+    if total.is_some_and(|total| total != downloaded) {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("got {} of {:?} bytes", downloaded, total)).into());
+    }
+    Ok(downloaded)
+}
+
+/// A `Content-Range` field, e.g. `bytes 100-199/1000` or `bytes */1000`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ContentRange {
+    first: Option<u64>,
+    last: Option<u64>,
+    total: Option<u64>,
+}
+
+impl ContentRange {
+    fn parse(value: &str) -> Option<ContentRange> {
+        let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+        let total = match total.trim() {
+            "*" => None,
+            total => Some(total.parse().ok()?),
+        };
+        let (first, last) = match range.trim() {
+            "*" => (None, None),
+            range => {
+                let (first, last) = range.split_once('-')?;
+                (Some(first.parse().ok()?), Some(last.parse().ok()?))
+            },
+        };
+        Some(ContentRange { first, last, total })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::testing::{response, StandIn, TempDir};
+    use crate::TCPReader;
+
+    #[test]
+    fn test_resumes_with_range() {
+        let dir = TempDir::new();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "hello").unwrap();
+        let server = StandIn::serve(vec![response("206 Partial Content", &[("Content-Range", "bytes 5-10/11")], " world")]);
+        let url = Url::parse(&server.url("/file.txt")).unwrap();
+
+        let mut updates = vec![];
+        let size = download(&TCPReader::new(), &url, &path, true, |progress| updates.push(progress)).unwrap();
+
+        assert_eq!(size, 11);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello world");
+        assert_eq!(updates.first(), Some(&Progress { downloaded: 5, total: Some(11) }));
+        assert_eq!(updates.last(), Some(&Progress { downloaded: 11, total: Some(11) }));
+        let head = server.requests()[0].to_ascii_lowercase();
+        assert!(head.contains("range: bytes=5-\r\n") && head.contains("accept-encoding: identity\r\n"), "{}", head);
+    }
+
+    #[test]
+    fn test_restarts_without_range_support() {
+        let dir = TempDir::new();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "stale").unwrap();
+        let server = StandIn::serve(vec![
+            response("200 OK", &[], "the whole file"),
+            response("416 Range Not Satisfiable", &[("Content-Range", "bytes */14")], ""),
+        ]);
+        let url = Url::parse(&server.url("/file.txt")).unwrap();
+
+        assert_eq!(download(&TCPReader::new(), &url, &path, true, |_| {}).unwrap(), 14);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "the whole file");
+
+        // resuming a finished download changes nothing
+        assert_eq!(download(&TCPReader::new(), &url, &path, true, |_| {}).unwrap(), 14);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "the whole file");
+    }
+
+    #[test]
+    fn test_content_range() {
+        assert_eq!(ContentRange::parse("bytes 0-499/1234"), Some(ContentRange { first: Some(0), last: Some(499), total: Some(1234) }));
+        assert_eq!(ContentRange::parse("bytes 10-20/*"), Some(ContentRange { first: Some(10), last: Some(20), total: None }));
+        assert_eq!(ContentRange::parse("bytes */1234"), Some(ContentRange { first: None, last: None, total: Some(1234) }));
+        assert_eq!(ContentRange::parse("items 0-1/2"), None);
+    }
+}
@@ -0,0 +1,707 @@
+use std::fmt;
+use std::io::{self, prelude::*};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use crate::proxy::percent_encode;
+use crate::{content, Url};
+
+/// Header fields in the order they were received; names compare case-insensitively.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub const fn new() -> Self {
+        Headers(Vec::new())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0.iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Adds a field, keeping any existing fields with the same name.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.0.push((name.to_string(), value.to_string()));
+    }
+
+    /// Sets a field, replacing any existing fields with the same name.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// These fields, plus any of `defaults` that aren't set here.
+    pub fn with_defaults(&self, defaults: &Headers) -> Headers {
+        let mut merged = Headers::new();
+        for (name, value) in defaults.iter().filter(|(name, _)| !self.contains(name)) {
+            merged.append(name, value);
+        }
+        merged.0.extend(self.0.iter().cloned());
+        merged
+    }
+
+    /// Checks whether a comma-separated field such as `Connection` lists `token`.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Method {
+    #[default]
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+        }
+    }
+
+    /// Whether sending the request twice has the same effect as sending it
+    /// once, so it can be retried after a failure.
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(self, Method::Post | Method::Patch)
+    }
+
+    /// Whether the request only reads, leaving the server's state alone.
+    pub fn is_safe(&self) -> bool {
+        matches!(self, Method::Get | Method::Head | Method::Options)
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Method {
+    type Err = UnknownMethod;
+
+    fn from_str(input: &str) -> Result<Method, Self::Err> {
+        match input.to_ascii_uppercase().as_str() {
+            "GET" => Ok(Method::Get),
+            "HEAD" => Ok(Method::Head),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            "PATCH" => Ok(Method::Patch),
+            "OPTIONS" => Ok(Method::Options),
+            _ => Err(UnknownMethod(input.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownMethod(pub String);
+
+impl fmt::Display for UnknownMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown method {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownMethod {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Method,
+    pub url: Url,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// When the whole fetch, redirects included, has to be finished by.
+    pub(crate) deadline: Option<Instant>,
+}
+
+impl Request {
+    pub fn new(method: Method, url: Url) -> Self {
+        Request { method, url, headers: Headers::new(), body: vec![], deadline: None }
+    }
+
+    pub fn get(url: Url) -> Self {
+        Request::new(Method::Get, url)
+    }
+
+    pub fn head(url: Url) -> Self {
+        Request::new(Method::Head, url)
+    }
+
+    pub fn post(url: Url) -> Self {
+        Request::new(Method::Post, url)
+    }
+
+    pub fn put(url: Url) -> Self {
+        Request::new(Method::Put, url)
+    }
+
+    pub fn delete(url: Url) -> Self {
+        Request::new(Method::Delete, url)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sends `json`, which must already be serialized, as the body.
+    pub fn with_json(self, json: &str) -> Self {
+        self.with_content_type("application/json").with_body(json)
+    }
+
+    /// Sends `fields` as an HTML form would, URL-encoded.
+    pub fn with_form(self, fields: &[(&str, &str)]) -> Self {
+        let body = fields.iter()
+            .map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        self.with_content_type("application/x-www-form-urlencoded").with_body(body)
+    }
+
+    /// Authenticates with `user` and `password` using the Basic scheme.
+    pub fn with_basic_auth(mut self, user: &str, password: &str) -> Self {
+        let credentials = BASE64.encode(format!("{}:{}", user, password));
+        self.headers.insert("Authorization", &format!("Basic {}", credentials));
+        self
+    }
+
+    fn with_content_type(mut self, content_type: &str) -> Self {
+        if !self.headers.contains("Content-Type") {
+            self.headers.insert("Content-Type", content_type);
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// A response with the standard reason phrase for `status`.
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Response { status, reason: reason_phrase(status).to_string(), headers: Headers::new(), body: body.into() }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// The body as text, decoded from the charset the response declares or,
+    /// failing that, the one it looks like. Malformed sequences are replaced.
+    pub fn text(&self) -> String {
+        let (text, _, _) = content::encoding(&self.headers, &self.body).decode(&self.body);
+        text.into_owned()
+    }
+
+    /// The name of the character encoding `text` decodes the body from.
+    pub fn charset(&self) -> &'static str {
+        content::encoding(&self.headers, &self.body).name()
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        matches!(self.status, 301 | 302 | 303 | 307 | 308)
+    }
+}
+
+/// A response whose body is read from the connection as it arrives, rather
+/// than all at once.
+pub struct StreamingResponse<'a> {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Headers,
+    body: Box<dyn Read + 'a>,
+}
+
+impl<'a> StreamingResponse<'a> {
+    pub fn new<R: Read + 'a>(status: u16, reason: String, headers: Headers, body: R) -> Self {
+        StreamingResponse { status, reason, headers, body: Box::new(body) }
+    }
+
+    /// The length of the body, if the server gave one.
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers.get("Content-Length").and_then(|length| length.trim().parse().ok())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Reads the rest of the body into an ordinary response.
+    pub fn into_response(mut self) -> io::Result<Response> {
+        let mut body = vec![];
+        self.body.read_to_end(&mut body)?;
+        Ok(Response { status: self.status, reason: self.reason, headers: self.headers, body })
+    }
+}
+
+impl From<Response> for StreamingResponse<'_> {
+    fn from(response: Response) -> Self {
+        StreamingResponse::new(response.status, response.reason, response.headers, io::Cursor::new(response.body))
+    }
+}
+
+impl Read for StreamingResponse<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+impl fmt::Debug for StreamingResponse<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamingResponse")
+            .field("status", &self.status)
+            .field("reason", &self.reason)
+            .field("headers", &self.headers)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What retrying and following redirects need to know of a response,
+/// however its body is read.
+pub(crate) trait Reply {
+    fn status(&self) -> u16;
+    fn headers(&self) -> &Headers;
+
+    fn is_redirect(&self) -> bool {
+        matches!(self.status(), 301 | 302 | 303 | 307 | 308)
+    }
+}
+
+impl Reply for Response {
+    fn status(&self) -> u16 {
+        self.status
+    }
+
+    fn headers(&self) -> &Headers {
+        &self.headers
+    }
+}
+
+impl Reply for StreamingResponse<'_> {
+    fn status(&self) -> u16 {
+        self.status
+    }
+
+    fn headers(&self) -> &Headers {
+        &self.headers
+    }
+}
+
+/// The reason phrase RFC 9110 gives `status`, or an empty one.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        416 => "Range Not Satisfiable",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+/// Parses an HTTP date in any of the three formats RFC 9110 has recipients
+/// accept, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn parse_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let (day, month, year, time) = match parts.as_slice() {
+        // IMF-fixdate
+        [_, day, month, year, time, "GMT"] => (day.parse().ok()?, *month, year.parse().ok()?, *time),
+        // obsolete RFC 850 format, with a two-digit year
+        [_, date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+            let year: i64 = year.parse().ok()?;
+            let year = if year < 70 { year + 2000 } else if year < 100 { year + 1900 } else { year };
+            (day.parse().ok()?, month, year, *time)
+        },
+        // ANSI C's asctime() format
+        [_, month, day, time, year] => (day.parse().ok()?, *month, year.parse().ok()?, *time),
+        _ => return None,
+    };
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as i64 + 1;
+    let mut time = time.split(':').map(|n| n.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    // four-digit years only, which also keeps the arithmetic below from overflowing
+    if !(1..=31).contains(&day) || !(0..=9999).contains(&year) {
+        return None;
+    }
+    if !(0..=23).contains(&hour) || !(0..=59).contains(&minute) || !(0..=60).contains(&second) {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The status line and header fields of a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseHead {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    pub headers: Headers,
+}
+
+/// How the end of a response body is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyLength {
+    Empty,
+    Fixed(u64),
+    Chunked,
+    UntilClose,
+}
+
+impl ResponseHead {
+    pub fn body_length(&self) -> io::Result<BodyLength> {
+        if (100..200).contains(&self.status) || self.status == 204 || self.status == 304 {
+            return Ok(BodyLength::Empty);
+        }
+        if self.headers.has_token("Transfer-Encoding", "chunked") {
+            return Ok(BodyLength::Chunked);
+        }
+        match self.headers.get("Content-Length") {
+            None => Ok(BodyLength::UntilClose),
+            Some(value) => value.trim()
+                .parse()
+                .map(BodyLength::Fixed)
+                .map_err(|_| invalid_data(format!("invalid Content-Length {:?}", value))),
+        }
+    }
+
+    /// Whether the server leaves the connection open once the body has been read.
+    pub fn keep_alive(&self) -> bool {
+        let framed = !matches!(self.body_length(), Ok(BodyLength::UntilClose) | Err(_));
+        let persistent = if self.version == "HTTP/1.0" {
+            self.headers.has_token("Connection", "keep-alive")
+        } else {
+            !self.headers.has_token("Connection", "close")
+        };
+        framed && persistent
+    }
+}
+
+/// Reads a status line and header fields, skipping any interim `1xx` responses.
+pub fn read_head<R: BufRead>(r: &mut R) -> io::Result<ResponseHead> {
+    loop {
+        let status_line = read_line(r)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default().to_string();
+        if !version.starts_with("HTTP/") {
+            return Err(invalid_data(format!("malformed status line {:?}", status_line)));
+        }
+        let status = parts.next()
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| invalid_data(format!("malformed status line {:?}", status_line)))?;
+        let reason = parts.next().unwrap_or_default().to_string();
+        let headers = read_fields(r)?;
+
+        if (100..200).contains(&status) && status != 101 {
+            continue;
+        }
+        return Ok(ResponseHead { version, status, reason, headers });
+    }
+}
+
+/// Reads header fields up to the empty line that ends them.
+pub(crate) fn read_fields<R: BufRead>(r: &mut R) -> io::Result<Headers> {
+    let mut headers = Headers::new();
+    loop {
+        let line = read_line(r)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        match line.split_once(':') {
+            None => return Err(invalid_data(format!("malformed header {:?}", line))),
+            Some((name, value)) => headers.append(name.trim(), value.trim()),
+        }
+    }
+}
+
+/// Reads a whole response, leaving `r` at the start of the next one when the
+/// body has an explicit length.
+pub fn read_response<R: BufRead>(r: &mut R) -> io::Result<(ResponseHead, Vec<u8>)> {
+    let head = read_head(r)?;
+    let mut body = vec![];
+    Body::new(r, head.body_length()?).read_to_end(&mut body)?;
+    Ok((head, body))
+}
+
+/// A reader over a response body that stops where the body ends.
+pub struct Body<R> {
+    inner: R,
+    length: BodyLength,
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> Body<R> {
+    pub fn new(inner: R, length: BodyLength) -> Self {
+        let remaining = match length {
+            BodyLength::Fixed(n) => n,
+            _ => 0,
+        };
+        let done = remaining == 0 && matches!(length, BodyLength::Empty | BodyLength::Fixed(_));
+        Body { inner, length, remaining, done }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let line = read_line(&mut self.inner)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        self.remaining = u64::from_str_radix(size, 16)
+            .map_err(|_| invalid_data(format!("invalid chunk size {:?}", line)))?;
+        if self.remaining == 0 {
+            // skip the trailer section
+            while !read_line(&mut self.inner)?.is_empty() {}
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        match self.length {
+            BodyLength::Empty => Ok(0),
+            BodyLength::UntilClose => self.inner.read(buf),
+            BodyLength::Fixed(_) => {
+                let max = buf.len().min(self.remaining as usize);
+                let n = self.inner.read(&mut buf[..max])?;
+                if n == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body ended early"));
+                }
+                self.remaining -= n as u64;
+                self.done = self.remaining == 0;
+                Ok(n)
+            },
+            BodyLength::Chunked => {
+                if self.remaining == 0 {
+                    self.next_chunk()?;
+                    if self.done {
+                        return Ok(0);
+                    }
+                }
+                let max = buf.len().min(self.remaining as usize);
+                let n = self.inner.read(&mut buf[..max])?;
+                if n == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "chunk ended early"));
+                }
+                self.remaining -= n as u64;
+                if self.remaining == 0 && !read_line(&mut self.inner)?.is_empty() {
+                    return Err(invalid_data("chunk is longer than its size".to_string()));
+                }
+                Ok(n)
+            },
+        }
+    }
+}
+
+pub(crate) fn read_line<R: BufRead>(r: &mut R) -> io::Result<String> {
+    let mut line = vec![];
+    r.read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed mid-line"));
+    }
+    while matches!(line.last(), Some(b'\n') | Some(b'\r')) {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| invalid_data("non-ASCII protocol line".to_string()))
+}
+
+pub(crate) fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_builder() {
+        let url = Url::parse("http://example.com/form").unwrap();
+
+        let request = Request::post(url.clone())
+            .with_form(&[("name", "Jo Doe"), ("note", "a&b=c")])
+            .with_basic_auth("Aladdin", "open sesame");
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.body, b"name=Jo%20Doe&note=a%26b%3Dc");
+        assert_eq!(request.headers.get("Content-Type"), Some("application/x-www-form-urlencoded"));
+        assert_eq!(request.headers.get("Authorization"), Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+
+        let request = Request::put(url)
+            .with_header("Content-Type", "application/vnd.api+json")
+            .with_json(r#"{"ok":true}"#);
+        assert_eq!(request.body, br#"{"ok":true}"#);
+        assert_eq!(request.headers.get("Content-Type"), Some("application/vnd.api+json"));
+    }
+
+    #[test]
+    fn test_method_from_str() {
+        assert_eq!("delete".parse::<Method>(), Ok(Method::Delete));
+        assert_eq!(Method::Options.to_string(), "OPTIONS");
+        assert_eq!("BREW".parse::<Method>().unwrap_err().to_string(), r#"unknown method "BREW""#);
+        assert!(Method::Put.is_idempotent() && !Method::Post.is_idempotent());
+    }
+
+    #[test]
+    fn test_content_length() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\nContent-Type: text/plain\r\n\r\n\xff\xfe\x00\x01HTTP/1.1";
+        let mut r = io::Cursor::new(&raw[..]);
+
+        let (head, body) = read_response(&mut r).unwrap();
+
+        assert_eq!(head.status, 200);
+        assert_eq!(head.reason, "OK");
+        assert_eq!(head.headers.get("content-type"), Some("text/plain"));
+        assert_eq!(body, b"\xff\xfe\x00\x01");
+        assert!(head.keep_alive());
+        assert_eq!(r.position(), raw.len() as u64 - 8);
+    }
+
+    #[test]
+    fn test_chunked() {
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\n";
+        let mut r = io::Cursor::new(&raw[..]);
+
+        let (head, body) = read_response(&mut r).unwrap();
+
+        assert_eq!(head.status, 200);
+        assert_eq!(body, b"hello, world");
+        assert_eq!(r.position(), raw.len() as u64);
+    }
+
+    #[test]
+    fn test_until_close() {
+        let raw = b"HTTP/1.0 200 OK\r\nServer: test\r\n\r\nno length";
+        let (head, body) = read_response(&mut io::Cursor::new(&raw[..])).unwrap();
+
+        assert_eq!(head.body_length().unwrap(), BodyLength::UntilClose);
+        assert!(!head.keep_alive());
+        assert_eq!(body, b"no length");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+
+        assert_eq!(parse_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse_date("Thu, 29 Feb 2024 00:00:00 GMT"), Some(UNIX_EPOCH + Duration::from_secs(1709164800)));
+        assert_eq!(parse_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_date("0"), None);
+        assert_eq!(parse_date("Sun, 06 Nov 999999999999999 08:49:37 GMT"), None);
+        assert_eq!(parse_date("Sun Nov  6 08:49:37 -9223372036854775808"), None);
+        assert_eq!(parse_date("Sun, 06 Nov 1994 -1:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn test_truncated_body() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort";
+        let err = read_response(&mut io::Cursor::new(&raw[..])).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
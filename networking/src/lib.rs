@@ -1,64 +1,697 @@
 use std::io::prelude::*;
-use std::net::TcpStream;
+use std::io::BufReader;
+use std::net::{SocketAddr, SocketAddrV4, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use reqwest;
+mod builder;
+mod cache;
+mod connection;
+mod content;
+mod crawl;
+mod download;
+pub mod dns;
+pub mod http;
+mod options;
+pub mod packet;
+mod pool;
+mod proxy;
+mod redirect;
+mod robots;
+pub mod server;
+pub mod tcp;
+mod tls;
+mod trace;
+mod url;
+pub mod websocket;
+
+#[cfg(test)]
+mod testing;
+
+use connection::Timed;
+use http::{Body, BodyLength};
+use pool::Lease;
+pub use builder::ReaderBuilder;
+pub use cache::CachingReader;
+pub use connection::Stream;
+pub use crawl::{links, Crawler, Page};
+pub use download::{download, Progress};
+pub use dns::Resolver;
+pub use http::{Headers, Method, Request, Response, StreamingResponse, UnknownMethod};
+pub use options::{ReaderOptions, RetryPolicy};
+pub use packet::MacAddress;
+pub use pool::ConnectionPool;
+pub use proxy::{Proxy, ProxyConfig, ProxyKind};
+pub use redirect::{RedirectError, RedirectPolicy};
+pub use robots::Robots;
+pub use server::{Handler, Server, ServerRequest, StaticFiles};
+pub use tcp::Interface;
+pub use tls::TlsConnector;
+pub use trace::{Trace, TraceFormat, Traced};
+pub use url::{Url, UrlError};
+pub use websocket::{Message, WebSocket};
 
 type PageError = Box<dyn std::error::Error>;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PageReaderProtocol {
     HTTP,
     TCP
 }
 
 impl PageReaderProtocol {
-    pub fn create_reader(&self) -> Box<&'static dyn PageReader> {
-        match self {
-            PageReaderProtocol::HTTP => Box::new(&HTTPReader{}),
-            PageReaderProtocol::TCP => Box::new(&TCPReader{})
+    /// A reader with the default configuration.
+    pub fn create_reader(&self) -> Box<dyn PageReader + Send + Sync> {
+        self.builder().build()
+    }
+
+    pub fn builder(&self) -> ReaderBuilder {
+        ReaderBuilder::new(*self)
+    }
+
+    /// Picks the reader for a URL: `tcp://` URLs are fetched over a plain socket
+    /// by `TCPReader`, `http://` and `https://` ones by `HTTPReader`.
+    /// `TCPReader` can still be asked for `http://` and `https://` URLs.
+    pub fn for_url(url: &Url) -> Option<PageReaderProtocol> {
+        match url.scheme.as_str() {
+            "http" | "https" => Some(PageReaderProtocol::HTTP),
+            "tcp" => Some(PageReaderProtocol::TCP),
+            _ => None,
         }
     }
 }
 
 impl std::str::FromStr for PageReaderProtocol {
-    type Err = ();
+    type Err = UnknownProtocol;
 
     fn from_str(input: &str) -> Result<PageReaderProtocol, Self::Err> {
-        match input {
+        match input.to_ascii_lowercase().as_str() {
             "http" => Ok(PageReaderProtocol::HTTP),
             "tcp" => Ok(PageReaderProtocol::TCP),
-            _ => Err(()),
+            _ => Err(UnknownProtocol(input.to_string())),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownProtocol(pub String);
+
+impl std::fmt::Display for UnknownProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown protocol {:?}, expected \"http\" or \"tcp\"", self.0)
+    }
+}
+
+impl std::error::Error for UnknownProtocol {}
+
 pub trait PageReader {
-    fn read_page(&self, url: &str) -> Result<String, PageError>;
+    /// Sends a single request, returning redirects as they are.
+    fn fetch(&self, request: &Request) -> Result<Response, PageError>;
+
+    fn redirect_policy(&self) -> RedirectPolicy {
+        RedirectPolicy::default()
+    }
+
+    /// When a fetch started now has to be finished by, across all of its
+    /// redirects and retries.
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Like `fetch`, but returns as soon as the response head has arrived,
+    /// leaving the body to be read from the response. Readers that can't
+    /// stream read the whole body first.
+    fn fetch_stream(&self, request: &Request) -> Result<StreamingResponse<'_>, PageError> {
+        Ok(self.fetch(request)?.into())
+    }
+
+    /// Fetches `url`, following redirects as the reader's policy allows.
+    fn read_page(&self, url: &str) -> Result<Response, PageError> {
+        let request = Request::get(Url::parse(url)?);
+        redirect::follow(self, request, &self.redirect_policy())
+    }
+
+    /// Sends `request`, following redirects as the reader's policy allows.
+    fn send(&self, request: Request) -> Result<Response, PageError> {
+        redirect::follow(self, request, &self.redirect_policy())
+    }
+
+    /// Sends `request`, following redirects, and streams the final body.
+    fn stream(&self, request: Request) -> Result<StreamingResponse<'_>, PageError> {
+        redirect::follow_with(request, &self.redirect_policy(), self.deadline(), |request| self.fetch_stream(request))
+    }
+}
+
+impl<T: PageReader + ?Sized> PageReader for &T {
+    fn fetch(&self, request: &Request) -> Result<Response, PageError> {
+        (**self).fetch(request)
+    }
+
+    fn fetch_stream(&self, request: &Request) -> Result<StreamingResponse<'_>, PageError> {
+        (**self).fetch_stream(request)
+    }
+
+    fn redirect_policy(&self) -> RedirectPolicy {
+        (**self).redirect_policy()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        (**self).deadline()
+    }
 }
 
-pub struct HTTPReader {}
+impl<T: PageReader + ?Sized> PageReader for Box<T> {
+    fn fetch(&self, request: &Request) -> Result<Response, PageError> {
+        (**self).fetch(request)
+    }
+
+    fn fetch_stream(&self, request: &Request) -> Result<StreamingResponse<'_>, PageError> {
+        (**self).fetch_stream(request)
+    }
+
+    fn redirect_policy(&self) -> RedirectPolicy {
+        (**self).redirect_policy()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        (**self).deadline()
+    }
+}
+
+pub struct HTTPReader {
+    pub redirects: RedirectPolicy,
+    pub options: ReaderOptions,
+    /// Sent with every request that doesn't set the same fields.
+    pub headers: Headers,
+    pub proxy: ProxyConfig,
+}
+
+impl HTTPReader {
+    pub const fn new() -> Self {
+        HTTPReader {
+            redirects: RedirectPolicy::new(),
+            options: ReaderOptions::new(),
+            headers: Headers::new(),
+            proxy: ProxyConfig::none(),
+        }
+    }
+
+    pub fn builder() -> ReaderBuilder {
+        ReaderBuilder::new(PageReaderProtocol::HTTP)
+    }
+
+    fn open_once(&self, request: &Request, deadline: Option<Instant>) -> Result<StreamingResponse<'static>, PageError> {
+        // redirects are followed by the PageReader layer, the same way for every reader
+        let mut client = reqwest::Client::builder()
+            .redirect(reqwest::RedirectPolicy::none())
+            .connect_timeout(self.options.connect_timeout)
+            .timeout(options::remaining(self.options.read_timeout, deadline)?);
+        if let Some(proxy) = self.proxy.to_reqwest() {
+            client = client.proxy(proxy);
+        }
+        let client = client.build()?;
+        let method = reqwest::Method::from_bytes(request.method.as_str().as_bytes())?;
+        let mut builder = client.request(method, request.url.to_string().as_str());
+        if !request.body.is_empty() {
+            builder = builder.body(request.body.clone());
+        }
+        let mut headers = request.headers.with_defaults(&self.headers);
+        if !headers.contains("Accept-Encoding") {
+            headers.insert("Accept-Encoding", content::ACCEPT_ENCODING);
+        }
+        for (name, value) in headers.iter() {
+            builder = builder.header(name, value);
+        }
+        let response = builder.send()?;
+
+        let mut headers = Headers::new();
+        for (name, value) in response.headers() {
+            headers.append(name.as_str(), &String::from_utf8_lossy(value.as_bytes()));
+        }
+        let status = response.status();
+        let reason = status.canonical_reason().unwrap_or_default().to_string();
+        // reqwest undoes gzip itself, but not deflate; a response without a
+        // body keeps its headers, which describe the body it would have had
+        let bodiless = request.method == Method::Head || matches!(status.as_u16(), 100..=199 | 204 | 304);
+        let body: Box<dyn Read> = if bodiless {
+            Box::new(response)
+        } else {
+            content::decode_reader(&mut headers, response)?
+        };
+        Ok(StreamingResponse::new(status.as_u16(), reason, headers, body))
+    }
+}
+
+impl Default for HTTPReader {
+    fn default() -> Self {
+        HTTPReader::new()
+    }
+}
 
 impl PageReader for HTTPReader {
-    fn read_page(&self, url: &str) -> Result<String, PageError> {
-        let mut response = reqwest::get(format!("http://{}", url).as_str())?;
-        let content = response.text()?;
-        Ok(content)
+    fn fetch(&self, request: &Request) -> Result<Response, PageError> {
+        let url = &request.url;
+        if url.scheme != "http" && url.scheme != "https" {
+            return Err(format!("HTTPReader can't fetch {} URLs", url.scheme).into());
+        }
+        options::retry(&self.options, request, |deadline| Ok(self.open_once(request, deadline)?.into_response()?))
+    }
+
+    fn fetch_stream(&self, request: &Request) -> Result<StreamingResponse<'_>, PageError> {
+        let url = &request.url;
+        if url.scheme != "http" && url.scheme != "https" {
+            return Err(format!("HTTPReader can't fetch {} URLs", url.scheme).into());
+        }
+        options::retry(&self.options, request, |deadline| self.open_once(request, deadline))
+    }
+
+    fn redirect_policy(&self) -> RedirectPolicy {
+        self.redirects
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.options.deadline()
     }
 }
 
-pub struct TCPReader {}
+pub struct TCPReader {
+    pub redirects: RedirectPolicy,
+    /// Looks up host names itself instead of asking the operating system.
+    pub resolver: Option<Resolver>,
+    /// Verifies `https` servers; uses the bundled web PKI roots if unset.
+    pub tls: Option<TlsConnector>,
+    pub options: ReaderOptions,
+    /// Keep-alive connections left open by earlier requests.
+    pub pool: ConnectionPool,
+    /// Sent with every request that doesn't set the same fields.
+    pub headers: Headers,
+    /// Proxies to tunnel through; `TCPReader` always asks HTTP proxies for a
+    /// `CONNECT` tunnel, even for plain `http` URLs.
+    pub proxy: ProxyConfig,
+    /// Connects with this userspace TCP stack instead of the kernel's.
+    pub userspace: Option<Interface>,
+    /// Records what goes over every connection the reader opens.
+    pub trace: Option<Arc<Trace>>,
+}
+
+impl TCPReader {
+    pub const fn new() -> Self {
+        TCPReader {
+            redirects: RedirectPolicy::new(),
+            resolver: None,
+            tls: None,
+            options: ReaderOptions::new(),
+            pool: ConnectionPool::new(Duration::from_secs(90), 6),
+            headers: Headers::new(),
+            proxy: ProxyConfig::none(),
+            userspace: None,
+            trace: None,
+        }
+    }
+
+    pub fn builder() -> ReaderBuilder {
+        ReaderBuilder::new(PageReaderProtocol::TCP)
+    }
+
+    fn addrs(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        match &self.resolver {
+            None => Ok((host, port).to_socket_addrs()?.collect()),
+            Some(resolver) => Ok(resolver.resolve(host)?
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, port))
+                .collect()),
+        }
+    }
+
+    /// Opens a connection to `host` over the kernel's TCP or, if set, the
+    /// userspace one, which only speaks IPv4.
+    fn open(&self, host: &str, port: u16, deadline: Option<Instant>) -> std::io::Result<Stream> {
+        let addrs = self.addrs(host, port)?;
+        let iface = match &self.userspace {
+            None => return Ok(Stream::Plain(connection::connect(&addrs, self.options.connect_timeout, deadline)?)),
+            Some(iface) => iface,
+        };
+        let mut last_err = std::io::Error::new(std::io::ErrorKind::NotFound, format!("no IPv4 addresses for {}", host));
+        for addr in addrs {
+            let SocketAddr::V4(addr) = addr else { continue };
+            let timeout = options::remaining(self.options.connect_timeout, deadline)?;
+            match iface.connect(SocketAddrV4::new(*addr.ip(), addr.port()), timeout) {
+                Ok(stream) => return Ok(Stream::User(Box::new(stream))),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn connect(&self, url: &Url, deadline: Option<Instant>) -> std::io::Result<Stream> {
+        let tcp = match self.proxy.for_url(url) {
+            None => self.open(&url.host, url.port, deadline)?,
+            Some(proxy) => {
+                let mut tcp = self.open(&proxy.host, proxy.port, deadline)?;
+                tcp.set_read_timeout(options::remaining(self.options.read_timeout, deadline)?)?;
+                tcp.set_write_timeout(options::remaining(self.options.read_timeout, deadline)?)?;
+                proxy::tunnel(&mut tcp, proxy, &url.host, url.port, |host| {
+                    self.addrs(host, url.port)?
+                        .first()
+                        .map(SocketAddr::ip)
+                        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("no addresses for {}", host)))
+                })?;
+                tcp
+            },
+        };
+        let stream = match url.scheme.as_str() {
+            "https" => {
+                let connector = self.tls.as_ref().unwrap_or_else(|| TlsConnector::shared());
+                Stream::Tls(Box::new(connector.connect(&url.host, tcp)?))
+            },
+            _ => tcp,
+        };
+        Ok(match &self.trace {
+            None => stream,
+            Some(trace) => trace.wrap(stream),
+        })
+    }
+
+    /// Sends `request` and reads the response head, leaving the body to be
+    /// read from the connection. The connection goes back to the pool once
+    /// the body has been read to the end.
+    fn open_once(&self, request: &Request, deadline: Option<Instant>) -> Result<StreamingResponse<'_>, PageError> {
+        loop {
+            let lease = self.pool.checkout(&request.url, deadline, || self.connect(&request.url, deadline))?;
+            let reused = lease.reused;
+            let mut conn = BufReader::new(Leased { lease, timeout: self.options.read_timeout, deadline, received: false });
+            let head = match self.write_request(request, conn.get_mut()).and_then(|_| http::read_head(&mut conn)) {
+                Ok(head) => head,
+                // the server may have closed an idle connection just as it was
+                // reused, so try again on another one, unless it may have
+                // acted on a request that isn't safe to send twice
+                Err(err) if reused && request.method.is_idempotent() && is_stale(&err, conn.get_ref().received) => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            let length = match request.method {
+                Method::Head => BodyLength::Empty,
+                _ => head.body_length()?,
+            };
+            let body = PooledBody { body: Some(Body::new(conn, length)), keep_alive: head.keep_alive() };
+            let mut headers = head.headers;
+            // nothing to decode, and Content-Length still says how long the
+            // encoded body would have been
+            let body: Box<dyn Read> = if length == BodyLength::Empty {
+                Box::new(body)
+            } else {
+                content::decode_reader(&mut headers, body)?
+            };
+            return Ok(StreamingResponse::new(head.status, head.reason, headers, body));
+        }
+    }
+
+    fn write_request<W: Write>(&self, request: &Request, conn: &mut W) -> std::io::Result<()> {
+        let url = &request.url;
+        let mut headers = request.headers.with_defaults(&self.headers);
+        if !headers.contains("Accept-Encoding") {
+            headers.insert("Accept-Encoding", content::ACCEPT_ENCODING);
+        }
+        let expects_body = matches!(request.method, Method::Post | Method::Put | Method::Patch);
+        if (expects_body || !request.body.is_empty()) && !headers.contains("Content-Length") {
+            headers.insert("Content-Length", &request.body.len().to_string());
+        }
+
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method, url.request_target());
+        if !headers.contains("Host") {
+            head.push_str(&format!("Host: {}\r\n", url.authority()));
+        }
+        for (name, value) in headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        conn.write_all(head.as_bytes())?;
+        conn.write_all(&request.body)?;
+        conn.flush()
+    }
+}
+
+/// Whether `err` is what a reused connection the server had already closed
+/// fails with: a reset or a broken pipe, or the end of the stream before any
+/// byte of the response arrived.
+fn is_stale(err: &std::io::Error, received: bool) -> bool {
+    use std::io::ErrorKind::*;
+    !received && matches!(err.kind(), ConnectionReset | ConnectionAborted | BrokenPipe | UnexpectedEof)
+}
+
+/// A pooled connection, timed out as `TCPReader`'s options say.
+struct Leased<'a> {
+    lease: Lease<'a>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    /// Whether any byte has been read from the connection.
+    received: bool,
+}
+
+impl Read for Leased<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = Timed::new(self.lease.stream(), self.timeout, self.deadline).read(buf)?;
+        self.received |= n > 0;
+        Ok(n)
+    }
+}
+
+impl Write for Leased<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Timed::new(self.lease.stream(), self.timeout, self.deadline).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Timed::new(self.lease.stream(), self.timeout, self.deadline).flush()
+    }
+}
+
+/// A response body that hands its connection back to the pool once it has
+/// been read to the end, if the server keeps the connection open.
+struct PooledBody<'a> {
+    body: Option<Body<BufReader<Leased<'a>>>>,
+    keep_alive: bool,
+}
+
+impl Read for PooledBody<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = match &mut self.body {
+            None => return Ok(0),
+            Some(body) => body.read(buf)?,
+        };
+        if n == 0 && !buf.is_empty() {
+            if let Some(body) = self.body.take() {
+                // the body is framed, so a keep-alive connection needn't be
+                // closed first, unless the server sent more than it should
+                let conn = body.into_inner();
+                if self.keep_alive && conn.buffer().is_empty() {
+                    conn.into_inner().lease.release();
+                }
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Default for TCPReader {
+    fn default() -> Self {
+        TCPReader::new()
+    }
+}
 
 impl PageReader for TCPReader {
-    fn read_page(&self, url: &str) -> Result<String, PageError> {
-        let mut conn = TcpStream::connect(format!("{}:80", url))?;
-        conn.write_all(b"GET / HTTP/1.0")?;
-        conn.write_all(b"\r\n")?;
-        conn.write_all(format!("Host: {}", url).as_bytes())?;
-        conn.write(b"\r\n\r\n")?;
-
-        let mut buf = vec![];
-        let mut content = std::io::Cursor::new(&mut buf);
-        std::io::copy(&mut conn, &mut content)?;
-
-        Ok(String::from_utf8(buf)?)
+    fn fetch(&self, request: &Request) -> Result<Response, PageError> {
+        let url = &request.url;
+        if !matches!(url.scheme.as_str(), "http" | "https" | "tcp") {
+            return Err(format!("TCPReader can't fetch {} URLs", url.scheme).into());
+        }
+        options::retry(&self.options, request, |deadline| Ok(self.open_once(request, deadline)?.into_response()?))
+    }
+
+    fn fetch_stream(&self, request: &Request) -> Result<StreamingResponse<'_>, PageError> {
+        let url = &request.url;
+        if !matches!(url.scheme.as_str(), "http" | "https" | "tcp") {
+            return Err(format!("TCPReader can't fetch {} URLs", url.scheme).into());
+        }
+        options::retry(&self.options, request, |deadline| self.open_once(request, deadline))
+    }
+
+    fn redirect_policy(&self) -> RedirectPolicy {
+        self.redirects
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.options.deadline()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{response, StandIn};
+
+    #[test]
+    fn test_tcp_reader_requests_path() {
+        let server = StandIn::serve(vec![response("200 OK", &[], "hello")]);
+
+        let url = format!("tcp://127.0.0.1:{}/docs/index.html?lang=en", server.port);
+        let page = TCPReader::new().read_page(&url).unwrap();
+
+        assert_eq!(page.status, 200);
+        assert_eq!(page.text(), "hello");
+        assert!(server.requests()[0].starts_with("GET /docs/index.html?lang=en HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_tcp_reader_over_userspace_stack() {
+        let (a, b) = tcp::Pipe::pair();
+        let client = Interface::new(a, "10.0.0.1".parse().unwrap());
+        let server = Interface::new(b, "10.0.0.2".parse().unwrap());
+        let listener = server.listen(80).unwrap();
+        let server_thread = std::thread::spawn(move || {
+            let mut conn = BufReader::new(listener.accept().unwrap());
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                conn.read_line(&mut head).unwrap();
+            }
+            let mut conn = conn.into_inner();
+            conn.write_all(&response("200 OK", &[("Connection", "close")], "over userspace")).unwrap();
+            conn.flush().unwrap();
+            head
+        });
+
+        let reader = TCPReader::builder().userspace(client).build_tcp();
+        let page = reader.read_page("tcp://10.0.0.2/hello").unwrap();
+
+        assert_eq!(page.text(), "over userspace");
+        assert!(server_thread.join().unwrap().starts_with("GET /hello HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_http_reader_requests_path() {
+        let server = StandIn::serve(vec![response("404 Not Found", &[], "nope")]);
+
+        let page = HTTPReader::new().read_page(&server.url("/missing")).unwrap();
+
+        assert_eq!(page.status, 404);
+        assert_eq!(page.body, b"nope");
+        assert!(server.requests()[0].starts_with("GET /missing HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_decodes_content() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let mut gzip = GzEncoder::new(vec![], Compression::default());
+        gzip.write_all(b"<p>Cr\xe8me br\xfbl\xe9e</p>").unwrap();
+        let body = gzip.finish().unwrap();
+        let mut raw = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=iso-8859-1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+            body.len()).into_bytes();
+        raw.extend_from_slice(&body);
+        let server = StandIn::serve(vec![raw.clone(), raw]);
+
+        for reader in [Box::new(TCPReader::new()) as Box<dyn PageReader>, Box::new(HTTPReader::new())] {
+            let page = reader.read_page(&server.url("/")).unwrap();
+            assert_eq!(page.text(), "<p>Crème brûlée</p>");
+            assert_eq!(page.charset(), "windows-1252");
+            assert_eq!(page.headers.get("Content-Encoding"), None);
+        }
+        for head in server.requests() {
+            assert!(head.to_ascii_lowercase().contains("accept-encoding: gzip"), "{}", head);
+        }
+    }
+
+    #[test]
+    fn test_streams_body() {
+        let body = "chunk ".repeat(10_000);
+        let server = StandIn::serve(vec![
+            response("302 Found", &[("Location", "/big")], ""),
+            response("200 OK", &[], &body),
+            response("200 OK", &[], &body),
+            response("200 OK", &[], "after"),
+        ]);
+        let url = Url::parse(&server.url("/")).unwrap();
+
+        let reader = TCPReader::new();
+        let mut response = reader.stream(Request::get(url.clone())).unwrap();
+        assert_eq!(response.content_length(), Some(body.len() as u64));
+        let mut text = String::new();
+        response.read_to_string(&mut text).unwrap();
+        assert_eq!(text, body);
+        drop(response);
+        // the connection went back once the body was read to the end
+        assert_eq!(reader.pool.idle(&url), 1);
+
+        let http = HTTPReader::new();
+        let mut response = http.stream(Request::get(url.join("/big").unwrap())).unwrap();
+        let mut text = String::new();
+        response.read_to_string(&mut text).unwrap();
+        assert_eq!(text, body);
+
+        assert_eq!(reader.read_page(&server.url("/")).unwrap().text(), "after");
+        assert_eq!(server.requests()[1].lines().next(), Some("GET /big HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_sends_method_and_body() {
+        let head = b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\nContent-Encoding: gzip\r\n\r\n".to_vec();
+        let server = StandIn::serve(vec![
+            response("201 Created", &[], "made"),
+            head.clone(),
+            response("201 Created", &[], "made"),
+            head.clone(),
+            b"HTTP/1.1 204 No Content\r\nContent-Encoding: gzip\r\n\r\n".to_vec(),
+            head,
+        ]);
+        let url = Url::parse(&server.url("/items")).unwrap();
+
+        for reader in [Box::new(TCPReader::new()) as Box<dyn PageReader>, Box::new(HTTPReader::new())] {
+            let page = reader.send(Request::post(url.clone()).with_json(r#"{"name":"x"}"#)).unwrap();
+            assert_eq!((page.status, page.text()), (201, "made".to_string()));
+
+            // the body of a HEAD response is never sent or decoded, whatever
+            // the length and encoding say
+            let page = reader.send(Request::head(url.clone())).unwrap();
+            assert_eq!((page.status, page.body.len()), (200, 0));
+        }
+
+        // neither is a 204's, and the headers still describe the encoded
+        // body (reqwest drops them for gzip, so only ours is checked)
+        let reader = TCPReader::new();
+        let page = reader.send(Request::delete(url.clone())).unwrap();
+        assert_eq!((page.status, page.body.len()), (204, 0));
+        let page = reader.send(Request::head(url)).unwrap();
+        assert_eq!(page.headers.get("Content-Length"), Some("1000"));
+        assert_eq!(page.headers.get("Content-Encoding"), Some("gzip"));
+
+        for (i, request) in server.requests().iter().take(4).enumerate() {
+            let request = request.to_ascii_lowercase();
+            if i % 2 == 0 {
+                assert!(request.starts_with("post /items http/1.1\r\n"), "{}", request);
+                assert!(request.contains("content-type: application/json\r\n"), "{}", request);
+                assert!(request.ends_with("\r\n{\"name\":\"x\"}"), "{}", request);
+            } else {
+                assert!(request.starts_with("head /items http/1.1\r\n"), "{}", request);
+            }
+        }
+    }
+
+    #[test]
+    fn test_protocol_from_str() {
+        assert_eq!("TCP".parse::<PageReaderProtocol>(), Ok(PageReaderProtocol::TCP));
+        let err = "gopher".parse::<PageReaderProtocol>().unwrap_err();
+        assert_eq!(err.to_string(), r#"unknown protocol "gopher", expected "http" or "tcp""#);
+    }
+
+    #[test]
+    fn test_protocol_for_url() {
+        let url = Url::parse("tcp://example.com/").unwrap();
+        assert!(matches!(PageReaderProtocol::for_url(&url), Some(PageReaderProtocol::TCP)));
+
+        let url = Url::parse("example.com").unwrap();
+        assert!(matches!(PageReaderProtocol::for_url(&url), Some(PageReaderProtocol::HTTP)));
     }
 }
@@ -1,18 +1,269 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use libnet::PageReaderProtocol;
+use clap::{App, AppSettings, Arg, SubCommand};
+
+use libnet::{
+    download, Crawler, Handler, Method, PageReaderProtocol, Progress, ProxyConfig, Request, Resolver, Server,
+    ServerRequest, StaticFiles, Trace, Url,
+};
+
+const USAGE: &str = "
+    net [http|tcp] URL [-X METHOD] [-H 'NAME: VALUE']... [-d DATA | --json DATA]
+                       [-u USER:PASSWORD] [-i] [--trace FILE]
+    net resolve NAME [--server ADDR]
+    net batch [FILE] [--protocol http|tcp] [--workers N] [--depth N] [--delay MS]
+                     [--ignore-robots] [--any-host]
+    net serve DIR [--addr ADDR] [--workers N]
+    net download URL [--output FILE] [--continue] [--protocol http|tcp]
+";
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let arg = std::env::args().nth(1);
+    let args =
+        App::new("net")
+            .usage(USAGE)
+            .setting(AppSettings::SubcommandsNegateReqs)
+            .setting(AppSettings::ArgsNegateSubcommands)
+            .arg(Arg::with_name("target")
+                .multiple(true)
+                .min_values(1)
+                .max_values(2)
+                .required(true))
+            .arg(Arg::with_name("method").short("X").long("request").takes_value(true)
+                .help("The request method; POST when sending data, GET otherwise"))
+            .arg(Arg::with_name("header").short("H").long("header").takes_value(true).multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("data").short("d").long("data").takes_value(true).conflicts_with("json")
+                .help("Sends DATA as a form body, or the contents of FILE given @FILE"))
+            .arg(Arg::with_name("json").long("json").takes_value(true)
+                .help("Sends DATA as a JSON body, or the contents of FILE given @FILE"))
+            .arg(Arg::with_name("user").short("u").long("user").takes_value(true)
+                .help("USER:PASSWORD for basic authentication"))
+            .arg(Arg::with_name("include").short("i").long("include")
+                .help("Print the status line and headers too"))
+            .arg(Arg::with_name("trace").long("trace").takes_value(true)
+                .help("Record the bytes sent and received in FILE, as a capture if it ends in .pcap, \
+                       or as a hex dump otherwise"))
+            .subcommand(SubCommand::with_name("resolve")
+                .about("Looks up the addresses of NAME over DNS")
+                .arg(Arg::with_name("name").takes_value(true).required(true))
+                .arg(Arg::with_name("server").long("server").takes_value(true)
+                    .help("The DNS server to ask, as IP or IP:PORT; the system's by default")))
+            .subcommand(SubCommand::with_name("batch")
+                .about("Fetches the URLs listed in FILE, or on stdin, and optionally the pages they link to")
+                .arg(Arg::with_name("file").takes_value(true))
+                .arg(Arg::with_name("protocol").long("protocol").takes_value(true)
+                    .possible_values(&["http", "tcp"]).default_value("tcp"))
+                .arg(Arg::with_name("workers").long("workers").takes_value(true).default_value("8"))
+                .arg(Arg::with_name("depth").long("depth").takes_value(true).default_value("0")
+                    .help("How many links deep to crawl from each URL"))
+                .arg(Arg::with_name("delay").long("delay").takes_value(true).default_value("250")
+                    .help("Milliseconds between requests to the same host"))
+                .arg(Arg::with_name("ignore-robots").long("ignore-robots"))
+                .arg(Arg::with_name("any-host").long("any-host")
+                    .help("Follow links to hosts other than those of the listed URLs")))
+            .subcommand(SubCommand::with_name("serve")
+                .about("Serves the files under DIR over HTTP")
+                .arg(Arg::with_name("dir").takes_value(true).required(true))
+                .arg(Arg::with_name("addr").long("addr").takes_value(true).default_value("127.0.0.1:8000"))
+                .arg(Arg::with_name("workers").long("workers").takes_value(true).default_value("8")))
+            .subcommand(SubCommand::with_name("download")
+                .about("Saves URL to a file, showing progress on stderr")
+                .arg(Arg::with_name("url").takes_value(true).required(true))
+                .arg(Arg::with_name("output").short("o").long("output").takes_value(true)
+                    .help("Where to save it; defaults to the last segment of the URL's path"))
+                .arg(Arg::with_name("continue").short("c").long("continue")
+                    .help("Resume a partial download instead of starting over"))
+                .arg(Arg::with_name("protocol").long("protocol").takes_value(true)
+                    .possible_values(&["http", "tcp"])))
+            .get_matches();
+
+    if let Some(matched) = args.subcommand_matches("resolve") {
+        let name = matched.value_of("name").expect("name is missing");
+        let resolver = match matched.value_of("server") {
+            None => Resolver::from_system()?,
+            Some(server) => {
+                let server = SocketAddr::from_str(server)
+                    .or_else(|_| server.parse().map(|ip| SocketAddr::new(ip, 53)))?;
+                Resolver::new(server)
+            },
+        };
+        for addr in resolver.resolve(name)? {
+            println!("{}", addr);
+        }
+        return Ok(());
+    }
+
+    if let Some(matched) = args.subcommand_matches("batch") {
+        let input: Box<dyn BufRead> = match matched.value_of("file") {
+            None | Some("-") => Box::new(BufReader::new(io::stdin())),
+            Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        };
+        let mut urls = vec![];
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                urls.push(Url::parse(line)?);
+            }
+        }
+
+        let crawler = Crawler {
+            workers: matched.value_of("workers").unwrap_or_default().parse()?,
+            max_depth: matched.value_of("depth").unwrap_or_default().parse()?,
+            same_host: !matched.is_present("any-host"),
+            robots: !matched.is_present("ignore-robots"),
+            host_delay: Duration::from_millis(matched.value_of("delay").unwrap_or_default().parse()?),
+            ..Crawler::new()
+        };
+        let protocol = PageReaderProtocol::from_str(matched.value_of("protocol").unwrap_or_default())?;
+        let reader = protocol.builder()
+            .user_agent(&crawler.user_agent)
+            .proxy(proxy_from_env())
+            .build();
+        crawler.run(&reader, urls, |page| match page.result {
+            Ok(response) => println!("{} {} ({} bytes)", response.status, page.url, response.body.len()),
+            Err(err) => println!("ERR {} ({})", page.url, err),
+        });
+        return Ok(());
+    }
+
+    if let Some(matched) = args.subcommand_matches("serve") {
+        let dir = matched.value_of("dir").expect("dir is missing");
+        let server = Server::bind(matched.value_of("addr").unwrap_or_default())?
+            .workers(matched.value_of("workers").unwrap_or_default().parse()?);
+        println!("Serving {} on http://{}/", dir, server.local_addr()?);
+        let files = StaticFiles::new(dir);
+        server.run(move |request: &ServerRequest| {
+            let response = files.handle(request);
+            eprintln!("{} {} {}", request.method, request.target, response.status);
+            response
+        })?;
+        return Ok(());
+    }
+
+    if let Some(matched) = args.subcommand_matches("download") {
+        let url = Url::parse(matched.value_of("url").expect("url is missing"))?;
+        let protocol = match matched.value_of("protocol") {
+            Some(name) => PageReaderProtocol::from_str(name)?,
+            None => PageReaderProtocol::for_url(&url).ok_or_else(|| format!("no reader for {} URLs", url.scheme))?,
+        };
+        let output = match matched.value_of("output") {
+            Some(path) => PathBuf::from(path),
+            None => match url.path.rsplit('/').next() {
+                Some(name) if !name.is_empty() => PathBuf::from(name),
+                _ => PathBuf::from("index.html"),
+            },
+        };
+
+        let reader = protocol.builder().proxy(proxy_from_env()).build();
+        let mut shown = None::<Instant>;
+        let size = download(&reader, &url, &output, matched.is_present("continue"), |progress| {
+            if shown.is_none_or(|at| at.elapsed() >= Duration::from_millis(100)) {
+                show_progress(progress);
+                shown = Some(Instant::now());
+            }
+        })?;
+        show_progress(Progress { downloaded: size, total: Some(size) });
+        eprintln!();
+        println!("Saved {} ({} bytes)", output.display(), size);
+        return Ok(());
+    }
 
-    let name = arg.expect("usage: net <protocol>");
+    let target: Vec<&str> = args.values_of("target").expect("url is missing").collect();
+    let (protocol, url) = match target.as_slice() {
+        // only the tcp reader can be traced
+        [url] if args.is_present("trace") => (PageReaderProtocol::TCP, *url),
+        [url] => {
+            let parsed = Url::parse(url)?;
+            let protocol = PageReaderProtocol::for_url(&parsed)
+                .ok_or_else(|| format!("no reader for {} URLs", parsed.scheme))?;
+            (protocol, *url)
+        },
+        [name, url] => {
+            let protocol = PageReaderProtocol::from_str(name)?;
+            (protocol, *url)
+        },
+        _ => return Err(USAGE.into()),
+    };
 
-    if let Ok(protocol) = PageReaderProtocol::from_str(name.as_ref()) {
-        let reader = protocol.create_reader();
-        let url = "www.rustinaction.com";
-        let content = reader.read_page(url)?;
-        println!("{}", content);
+    let mut request = Request::get(Url::parse(url)?);
+    for header in args.values_of("header").into_iter().flatten() {
+        let (name, value) = header.split_once(':').ok_or_else(|| format!("malformed header {:?}", header))?;
+        request.headers.append(name.trim(), value.trim());
     }
+    // a Content-Type given with -H wins over the one implied by the body
+    let body = match (args.value_of("data"), args.value_of("json")) {
+        (Some(data), _) => Some(("application/x-www-form-urlencoded", data)),
+        (_, Some(json)) => Some(("application/json", json)),
+        _ => None,
+    };
+    if let Some((content_type, data)) = body {
+        if !request.headers.contains("Content-Type") {
+            request.headers.insert("Content-Type", content_type);
+        }
+        request.body = body_of(data)?;
+    }
+    request.method = match args.value_of("method") {
+        Some(method) => method.parse()?,
+        None if !request.body.is_empty() => Method::Post,
+        None => Method::Get,
+    };
+    if let Some(user) = args.value_of("user") {
+        let (user, password) = user.split_once(':').unwrap_or((user, ""));
+        request = request.with_basic_auth(user, password);
+    }
+
+    let mut builder = protocol.builder().proxy(proxy_from_env());
+    if let Some(path) = args.value_of("trace") {
+        if protocol != PageReaderProtocol::TCP {
+            return Err("--trace only works with the tcp reader".into());
+        }
+        builder = builder.trace(Arc::new(Trace::create(path)?));
+    }
+    let reader = builder.build();
+    let content = reader.send(request)?;
+    if args.is_present("include") {
+        println!("{} {}", content.status, content.reason);
+        for (name, value) in content.headers.iter() {
+            println!("{}: {}", name, value);
+        }
+        println!();
+    }
+    println!("{}", content.text());
 
     Ok(())
 }
+
+fn show_progress(progress: Progress) {
+    let mut stderr = io::stderr();
+    let _ = match progress.total {
+        Some(total) if total > 0 => write!(
+            stderr, "\r{} / {} bytes ({:.0}%)", progress.downloaded, total,
+            progress.downloaded as f64 * 100.0 / total as f64),
+        _ => write!(stderr, "\r{} bytes", progress.downloaded),
+    };
+    let _ = stderr.flush();
+}
+
+/// The proxies named in the environment, or none if they can't be parsed;
+/// a typo in `http_proxy` shouldn't stop requests that may not even use it.
+fn proxy_from_env() -> ProxyConfig {
+    ProxyConfig::from_env().unwrap_or_else(|err| {
+        eprintln!("warning: ignoring proxy settings: {}", err);
+        ProxyConfig::none()
+    })
+}
+
+/// The body given on the command line, read from a file for `@FILE`.
+fn body_of(data: &str) -> io::Result<Vec<u8>> {
+    match data.strip_prefix('@') {
+        Some(path) => std::fs::read(path),
+        None => Ok(data.as_bytes().to_vec()),
+    }
+}
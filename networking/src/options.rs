@@ -0,0 +1,302 @@
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::http::{Reply, Request};
+use crate::PageError;
+
+/// Timeouts and retries, shared by every reader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReaderOptions {
+    /// How long to wait for a connection to a server.
+    pub connect_timeout: Option<Duration>,
+    /// How long a single read or write may block. `HTTPReader` can't time
+    /// reads on their own, so it applies this to the whole exchange instead.
+    pub read_timeout: Option<Duration>,
+    /// An upper bound on a whole fetch, across redirects, retries and their backoff.
+    pub total_timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+}
+
+impl ReaderOptions {
+    pub const fn new() -> Self {
+        ReaderOptions {
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(30)),
+            total_timeout: None,
+            retry: RetryPolicy::new(),
+        }
+    }
+
+    /// When a fetch started now has to be finished by.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.total_timeout.map(|timeout| Instant::now() + timeout)
+    }
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        ReaderOptions::new()
+    }
+}
+
+/// How often a failed request is tried again, and how long to wait in between.
+///
+/// Only failures that may go away are retried: connection errors, timeouts
+/// and the `502`, `503` and `504` responses of overloaded servers and proxies.
+/// The delay doubles after every attempt, up to `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Waits a random part of the delay, between half and all of it, so
+    /// clients that failed together don't all come back at once.
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    pub const fn new() -> Self {
+        RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+
+    /// Gives up after the first attempt.
+    pub const fn none() -> Self {
+        RetryPolicy { max_retries: 0, ..RetryPolicy::new() }
+    }
+
+    /// The delay before retry number `retry`, counting from zero.
+    pub fn backoff<R: Rng>(&self, retry: u32, rng: &mut R) -> Duration {
+        let delay = self.initial_backoff
+            .checked_mul(1 << retry.min(31))
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff));
+        if self.jitter {
+            delay.mul_f64(rng.gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new()
+    }
+}
+
+/// Runs `attempt` until it succeeds, fails for good or runs out of retries or
+/// time, passing it the deadline for the whole fetch: the request's own, set
+/// once for a chain of redirects, or else one from `options`. Requests that
+/// aren't idempotent are only tried once.
+pub(crate) fn retry<T, F>(options: &ReaderOptions, request: &Request, mut attempt: F) -> Result<T, PageError>
+where
+    T: Reply,
+    F: FnMut(Option<Instant>) -> Result<T, PageError>,
+{
+    let deadline = request.deadline.or_else(|| options.deadline());
+    let mut rng = rand::thread_rng();
+    let mut retries = 0;
+    loop {
+        let result = attempt(deadline);
+        let transient = match &result {
+            Ok(response) => matches!(response.status(), 502..=504),
+            Err(err) => is_transient(err.as_ref()),
+        };
+        if !transient || !request.method.is_idempotent() || retries >= options.retry.max_retries {
+            return result;
+        }
+
+        let delay = options.retry.backoff(retries, &mut rng);
+        if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+            return result;
+        }
+        thread::sleep(delay);
+        retries += 1;
+    }
+}
+
+fn is_transient(err: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(err) = err.downcast_ref::<io::Error>() {
+        return matches!(err.kind(),
+            io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset |
+            io::ErrorKind::ConnectionAborted | io::ErrorKind::NotConnected |
+            io::ErrorKind::BrokenPipe | io::ErrorKind::TimedOut |
+            io::ErrorKind::WouldBlock | io::ErrorKind::UnexpectedEof |
+            io::ErrorKind::Interrupted);
+    }
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return err.is_timeout() || err.is_http();
+    }
+    false
+}
+
+/// What's left of `timeout` before `deadline`, failing once it has passed.
+pub(crate) fn remaining(timeout: Option<Duration>, deadline: Option<Instant>) -> io::Result<Option<Duration>> {
+    let deadline = match deadline {
+        None => return Ok(timeout),
+        Some(deadline) => deadline,
+    };
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "total timeout elapsed"));
+    }
+    Ok(Some(timeout.map_or(left, |timeout| timeout.min(left))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    use crate::testing::{response, StandIn};
+    use crate::{HTTPReader, PageReader, TCPReader};
+
+    fn quick() -> ReaderOptions {
+        ReaderOptions {
+            read_timeout: Some(Duration::from_millis(100)),
+            retry: RetryPolicy {
+                initial_backoff: Duration::from_millis(10),
+                ..RetryPolicy::new()
+            },
+            ..ReaderOptions::new()
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_backoff: Duration::from_millis(500),
+            jitter: false,
+            ..RetryPolicy::new()
+        };
+        let mut rng = rand::thread_rng();
+
+        let delays: Vec<u128> = (0..5).map(|n| policy.backoff(n, &mut rng).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+        assert_eq!(policy.backoff(u32::MAX, &mut rng), policy.max_backoff);
+
+        let policy = RetryPolicy { jitter: true, ..policy };
+        for _ in 0..100 {
+            let delay = policy.backoff(2, &mut rng);
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        // connections queue up in the backlog, but nothing ever answers them
+        let stalled = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("tcp://127.0.0.1:{}/", stalled.local_addr().unwrap().port());
+        let mut reader = TCPReader::new();
+        reader.options = ReaderOptions { retry: RetryPolicy::none(), ..quick() };
+
+        let started = Instant::now();
+        let err = reader.read_page(&url).unwrap_err();
+
+        let err = err.downcast::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_total_timeout() {
+        let stalled = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://127.0.0.1:{}/", stalled.local_addr().unwrap().port());
+        let options = ReaderOptions {
+            total_timeout: Some(Duration::from_millis(250)),
+            retry: RetryPolicy { max_retries: 100, ..quick().retry },
+            ..quick()
+        };
+        let mut tcp = TCPReader::new();
+        tcp.options = options;
+        let mut http = HTTPReader::new();
+        http.options = options;
+
+        for reader in [&tcp as &dyn PageReader, &http] {
+            let started = Instant::now();
+            assert!(reader.read_page(&url).is_err());
+            assert!(started.elapsed() < Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn test_retries_unavailable() {
+        let server = StandIn::serve(vec![
+            response("503 Service Unavailable", &[], "busy"),
+            response("502 Bad Gateway", &[], "busy"),
+            response("200 OK", &[], "done"),
+            response("503 Service Unavailable", &[], "busy"),
+            response("200 OK", &[], "done"),
+        ]);
+        let mut tcp = TCPReader::new();
+        tcp.options = quick();
+        let mut http = HTTPReader::new();
+        http.options = quick();
+
+        assert_eq!(tcp.read_page(&server.url("/")).unwrap().text(), "done");
+        assert_eq!(http.read_page(&server.url("/")).unwrap().text(), "done");
+        assert_eq!(server.requests().len(), 5);
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let server = StandIn::serve(vec![
+            response("503 Service Unavailable", &[], "busy"),
+            response("503 Service Unavailable", &[], "still busy"),
+            response("404 Not Found", &[], "gone"),
+        ]);
+        let mut reader = TCPReader::new();
+        reader.options = ReaderOptions {
+            retry: RetryPolicy { max_retries: 1, ..quick().retry },
+            ..quick()
+        };
+
+        let page = reader.read_page(&server.url("/")).unwrap();
+        assert_eq!(page.text(), "still busy");
+
+        // client errors aren't worth another try
+        reader.options.retry = quick().retry;
+        assert_eq!(reader.read_page(&server.url("/")).unwrap().status, 404);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_total_timeout_spans_redirects() {
+        // each hop answers in time on its own, but not both together
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://127.0.0.1:{}/1", listener.local_addr().unwrap().port());
+        thread::spawn(move || {
+            let (conn, _) = listener.accept().unwrap();
+            let mut conn = BufReader::new(conn);
+            for reply in [response("302 Found", &[("Location", "/2")], ""), response("200 OK", &[], "done")] {
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    if conn.read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                }
+                thread::sleep(Duration::from_millis(200));
+                let _ = conn.get_mut().write_all(&reply);
+            }
+        });
+        let mut reader = TCPReader::new();
+        reader.options = ReaderOptions {
+            total_timeout: Some(Duration::from_millis(300)),
+            retry: RetryPolicy::none(),
+            ..ReaderOptions::new()
+        };
+
+        let err = reader.read_page(&url).unwrap_err();
+        assert_eq!(err.downcast::<io::Error>().unwrap().kind(), io::ErrorKind::TimedOut);
+    }
+}
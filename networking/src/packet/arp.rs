@@ -0,0 +1,84 @@
+use std::io;
+use std::net::Ipv4Addr;
+
+use super::{invalid, MacAddress, ETHERTYPE_IPV4};
+
+/// Ethernet, the only hardware type supported.
+const HARDWARE_ETHERNET: u16 = 1;
+const PACKET_LEN: usize = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOperation {
+    Request,
+    Reply,
+}
+
+/// An ARP packet mapping IPv4 addresses to Ethernet ones (RFC 826).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: ArpOperation,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Addr,
+    /// Unknown, and so all zeroes, in a request.
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// Asks who has `target_ip`.
+    pub fn request(sender_mac: MacAddress, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+        ArpPacket { operation: ArpOperation::Request, sender_mac, sender_ip, target_mac: MacAddress::ZERO, target_ip }
+    }
+
+    /// Answers this request on behalf of the host with `mac`.
+    pub fn reply(&self, mac: MacAddress) -> Self {
+        ArpPacket {
+            operation: ArpOperation::Reply,
+            sender_mac: mac,
+            sender_ip: self.target_ip,
+            target_mac: self.sender_mac,
+            target_ip: self.sender_ip,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(PACKET_LEN);
+        packet.extend_from_slice(&HARDWARE_ETHERNET.to_be_bytes());
+        packet.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        packet.extend_from_slice(&[6, 4]);
+        let operation: u16 = match self.operation {
+            ArpOperation::Request => 1,
+            ArpOperation::Reply => 2,
+        };
+        packet.extend_from_slice(&operation.to_be_bytes());
+        packet.extend_from_slice(&self.sender_mac.octets());
+        packet.extend_from_slice(&self.sender_ip.octets());
+        packet.extend_from_slice(&self.target_mac.octets());
+        packet.extend_from_slice(&self.target_ip.octets());
+        packet
+    }
+
+    /// Parses an ARP packet for Ethernet and IPv4, ignoring any padding after it.
+    pub fn decode(packet: &[u8]) -> io::Result<ArpPacket> {
+        if packet.len() < PACKET_LEN {
+            return Err(invalid("truncated ARP packet"));
+        }
+        let half = |at: usize| u16::from_be_bytes([packet[at], packet[at + 1]]);
+        if half(0) != HARDWARE_ETHERNET || half(2) != ETHERTYPE_IPV4 || packet[4] != 6 || packet[5] != 4 {
+            return Err(invalid("ARP for something other than Ethernet and IPv4"));
+        }
+        let operation = match half(6) {
+            1 => ArpOperation::Request,
+            2 => ArpOperation::Reply,
+            _ => return Err(invalid("unknown ARP operation")),
+        };
+        let ip = |at: usize| Ipv4Addr::new(packet[at], packet[at + 1], packet[at + 2], packet[at + 3]);
+        Ok(ArpPacket {
+            operation,
+            sender_mac: MacAddress(packet[8..14].try_into().unwrap()),
+            sender_ip: ip(14),
+            target_mac: MacAddress(packet[18..24].try_into().unwrap()),
+            target_ip: ip(24),
+        })
+    }
+}
@@ -0,0 +1,74 @@
+use std::io;
+
+use super::{invalid, MacAddress};
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+const HEADER_LEN: usize = 14;
+/// The shortest frame allowed on the wire, not counting its checksum.
+const MIN_FRAME_LEN: usize = 60;
+
+/// An Ethernet II frame, without the checksum that network cards check and
+/// strip, as packet captures show it. VLAN tags aren't interpreted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EthernetFrame {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ethertype: u16,
+    pub payload: Vec<u8>,
+}
+
+impl EthernetFrame {
+    pub fn new(dst: MacAddress, src: MacAddress, ethertype: u16, payload: Vec<u8>) -> Self {
+        EthernetFrame { dst, src, ethertype, payload }
+    }
+
+    /// The frame, padded with zeroes to the minimum length.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity((HEADER_LEN + self.payload.len()).max(MIN_FRAME_LEN));
+        frame.extend_from_slice(&self.dst.octets());
+        frame.extend_from_slice(&self.src.octets());
+        frame.extend_from_slice(&self.ethertype.to_be_bytes());
+        frame.extend_from_slice(&self.payload);
+        frame.resize(frame.len().max(MIN_FRAME_LEN), 0);
+        frame
+    }
+
+    /// Parses a frame. Padding can't be told apart from the payload here, so
+    /// it's left for the protocol inside to ignore.
+    pub fn decode(frame: &[u8]) -> io::Result<EthernetFrame> {
+        if frame.len() < HEADER_LEN {
+            return Err(invalid("truncated Ethernet header"));
+        }
+        let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+        if ethertype < 0x0600 {
+            return Err(invalid("802.3 length field instead of an EtherType"));
+        }
+        Ok(EthernetFrame {
+            dst: MacAddress(frame[..6].try_into().unwrap()),
+            src: MacAddress(frame[6..12].try_into().unwrap()),
+            ethertype,
+            payload: frame[HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padding() {
+        let frame = EthernetFrame::new(MacAddress::BROADCAST, MacAddress([2, 0, 0, 0, 0, 1]), ETHERTYPE_ARP, vec![1, 2, 3]);
+
+        let encoded = frame.encode();
+
+        assert_eq!(encoded.len(), 60);
+        assert_eq!(&encoded[12..17], &[0x08, 0x06, 1, 2, 3]);
+        assert!(encoded[17..].iter().all(|&byte| byte == 0));
+        assert_eq!(EthernetFrame::decode(&encoded).unwrap().payload.len(), 46);
+        assert!(EthernetFrame::decode(&encoded[..13]).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::io;
+
+use super::{checksum, invalid};
+
+pub const ECHO_REPLY: u8 = 0;
+pub const DESTINATION_UNREACHABLE: u8 = 3;
+pub const ECHO_REQUEST: u8 = 8;
+pub const TIME_EXCEEDED: u8 = 11;
+
+const HEADER_LEN: usize = 8;
+
+/// An ICMP message (RFC 792).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcmpPacket {
+    pub icmp_type: u8,
+    pub code: u8,
+    /// The rest of the header, whose meaning depends on the type: the
+    /// identifier and sequence number of echoes, for one.
+    pub rest: [u8; 4],
+    pub payload: Vec<u8>,
+}
+
+impl IcmpPacket {
+    /// A ping.
+    pub fn echo_request(identifier: u16, sequence: u16, data: Vec<u8>) -> Self {
+        let mut rest = [0; 4];
+        rest[..2].copy_from_slice(&identifier.to_be_bytes());
+        rest[2..].copy_from_slice(&sequence.to_be_bytes());
+        IcmpPacket { icmp_type: ECHO_REQUEST, code: 0, rest, payload: data }
+    }
+
+    /// The answer to this echo request, carrying back its identifier,
+    /// sequence number and data.
+    pub fn echo_reply(&self) -> Self {
+        IcmpPacket { icmp_type: ECHO_REPLY, ..self.clone() }
+    }
+
+    pub fn identifier(&self) -> u16 {
+        u16::from_be_bytes([self.rest[0], self.rest[1]])
+    }
+
+    pub fn sequence(&self) -> u16 {
+        u16::from_be_bytes([self.rest[2], self.rest[3]])
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        packet.extend_from_slice(&[self.icmp_type, self.code, 0, 0]);
+        packet.extend_from_slice(&self.rest);
+        packet.extend_from_slice(&self.payload);
+        let sum = checksum(&[&packet]);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+        packet
+    }
+
+    /// Parses a message, checking its checksum.
+    pub fn decode(packet: &[u8]) -> io::Result<IcmpPacket> {
+        if packet.len() < HEADER_LEN {
+            return Err(invalid("truncated ICMP header"));
+        }
+        if checksum(&[packet]) != 0 {
+            return Err(invalid("bad ICMP checksum"));
+        }
+        Ok(IcmpPacket {
+            icmp_type: packet[0],
+            code: packet[1],
+            rest: packet[4..8].try_into().unwrap(),
+            payload: packet[HEADER_LEN..].to_vec(),
+        })
+    }
+}
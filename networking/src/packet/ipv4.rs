@@ -0,0 +1,152 @@
+use std::io;
+use std::net::Ipv4Addr;
+
+use super::{checksum, invalid};
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+const HEADER_LEN: usize = 20;
+/// The most the 4-bit header length field can describe.
+const MAX_HEADER_LEN: usize = 60;
+
+/// An IPv4 packet (RFC 791). Fragments are taken as they are, not reassembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv4Packet {
+    /// The DSCP and ECN bits, once the type of service.
+    pub tos: u8,
+    pub identification: u16,
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+    /// Where the fragment goes in the original payload, in units of 8 bytes.
+    pub fragment_offset: u16,
+    pub ttl: u8,
+    pub protocol: u8,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    /// Raw options; `encode` pads them to a multiple of 4 bytes.
+    pub options: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+impl Ipv4Packet {
+    /// An unfragmented packet, with a TTL of 64 and no options.
+    pub fn new(protocol: u8, src: Ipv4Addr, dst: Ipv4Addr, payload: Vec<u8>) -> Self {
+        Ipv4Packet {
+            tos: 0,
+            identification: 0,
+            dont_fragment: true,
+            more_fragments: false,
+            fragment_offset: 0,
+            ttl: 64,
+            protocol,
+            src,
+            dst,
+            options: vec![],
+            payload,
+        }
+    }
+
+    /// Fails if the options don't fit the 60-byte header or the packet is
+    /// longer than 65535 bytes.
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let header_len = HEADER_LEN + self.options.len().div_ceil(4) * 4;
+        if header_len > MAX_HEADER_LEN {
+            let error_msg = format!("{} bytes of IPv4 options, at most 40 fit", self.options.len());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error_msg));
+        }
+        let total_len = header_len + self.payload.len();
+        if total_len > u16::MAX as usize {
+            let error_msg = format!("IPv4 packet of {} bytes, at most 65535 fit", total_len);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error_msg));
+        }
+        let mut packet = Vec::with_capacity(total_len);
+
+        packet.extend_from_slice(&[0x40 | (header_len / 4) as u8, self.tos]);
+        packet.extend_from_slice(&(total_len as u16).to_be_bytes());
+        packet.extend_from_slice(&self.identification.to_be_bytes());
+        let flags = (self.dont_fragment as u16) << 14 | (self.more_fragments as u16) << 13;
+        packet.extend_from_slice(&(flags | self.fragment_offset & 0x1fff).to_be_bytes());
+        packet.extend_from_slice(&[self.ttl, self.protocol, 0, 0]);
+        packet.extend_from_slice(&self.src.octets());
+        packet.extend_from_slice(&self.dst.octets());
+        packet.extend_from_slice(&self.options);
+        packet.resize(header_len, 0);
+        let sum = checksum(&[&packet]);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+
+        packet.extend_from_slice(&self.payload);
+        Ok(packet)
+    }
+
+    /// Parses a packet, checking the header checksum. Anything past the
+    /// total length, such as Ethernet padding, is ignored.
+    pub fn decode(packet: &[u8]) -> io::Result<Ipv4Packet> {
+        if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+            return Err(invalid("not an IPv4 packet"));
+        }
+        let header_len = (packet[0] & 0x0f) as usize * 4;
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        if header_len < HEADER_LEN || total_len < header_len || total_len > packet.len() {
+            return Err(invalid("malformed IPv4 header"));
+        }
+        if checksum(&[&packet[..header_len]]) != 0 {
+            return Err(invalid("bad IPv4 header checksum"));
+        }
+
+        let flags = u16::from_be_bytes([packet[6], packet[7]]);
+        Ok(Ipv4Packet {
+            tos: packet[1],
+            identification: u16::from_be_bytes([packet[4], packet[5]]),
+            dont_fragment: flags & 0x4000 != 0,
+            more_fragments: flags & 0x2000 != 0,
+            fragment_offset: flags & 0x1fff,
+            ttl: packet[8],
+            protocol: packet[9],
+            src: Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]),
+            dst: Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]),
+            options: packet[HEADER_LEN..header_len].to_vec(),
+            payload: packet[header_len..total_len].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_and_checksum() {
+        let mut packet = Ipv4Packet::new(PROTOCOL_UDP, Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), b"data".to_vec());
+        // record route, with room for one address
+        packet.options = vec![7, 7, 4, 0, 0, 0, 0];
+        packet.more_fragments = true;
+        packet.fragment_offset = 185;
+
+        let mut encoded = packet.encode().unwrap();
+        assert_eq!(encoded[0], 0x47);
+        assert_eq!(encoded.len(), 32);
+        packet.options.push(0);
+        assert_eq!(Ipv4Packet::decode(&encoded).unwrap(), packet);
+
+        encoded[8] -= 1;
+        assert_eq!(Ipv4Packet::decode(&encoded).unwrap_err().to_string(), "bad IPv4 header checksum");
+        assert!(Ipv4Packet::decode(&encoded[..30]).is_err());
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let mut packet = Ipv4Packet::new(PROTOCOL_UDP, Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), vec![]);
+        packet.options = vec![1; 40];
+        assert_eq!(packet.encode().unwrap()[0], 0x4f);
+        packet.options.push(1);
+        assert_eq!(packet.encode().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        packet.options.clear();
+        packet.payload = vec![0; 65535 - HEADER_LEN];
+        assert_eq!(packet.encode().unwrap().len(), 65535);
+        packet.payload.push(0);
+        assert_eq!(packet.encode().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
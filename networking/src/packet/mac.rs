@@ -0,0 +1,228 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use rand::Rng;
+
+/// Vendors of some organizationally unique identifiers, sorted by OUI.
+const OUI_TABLE: &str = include_str!("oui.txt");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacAddressError(String);
+
+impl fmt::Display for MacAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid MAC address {:?}", self.0)
+    }
+}
+
+impl std::error::Error for MacAddressError {}
+
+/// How a MAC address is written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacNotation {
+    /// `3c:2c:30:a1:b2:c4`, as Linux and macOS write them.
+    Colon,
+    /// `3C-2C-30-A1-B2-C4`, as Windows and the IEEE write them.
+    Hyphen,
+    /// `3c2c.30a1.b2c4`, as Cisco writes them.
+    Dot,
+    /// `3c2c30a1b2c4`.
+    Bare,
+}
+
+/// A 48-bit Ethernet address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+    pub const ZERO: MacAddress = MacAddress([0; 6]);
+
+    pub const fn new(octets: [u8; 6]) -> Self {
+        MacAddress(octets)
+    }
+
+    pub const fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// A random unicast address with the locally administered bit set, so
+    /// it can't clash with one a manufacturer assigned.
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
+        let mut octets: [u8; 6] = rng.gen();
+        octets[0] = (octets[0] | 0x02) & !0x01;
+        MacAddress(octets)
+    }
+
+    /// The multicast address IPv4 multicast group `group` is sent to
+    /// (RFC 1112): `01:00:5e` and the low 23 bits of the group.
+    pub fn for_ipv4_multicast(group: Ipv4Addr) -> Option<Self> {
+        if !group.is_multicast() {
+            return None;
+        }
+        let [_, b, c, d] = group.octets();
+        Some(MacAddress([0x01, 0x00, 0x5e, b & 0x7f, c, d]))
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == MacAddress::BROADCAST
+    }
+
+    /// Whether frames to the address go to a group of hosts; broadcast is
+    /// one such group.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    pub fn is_unicast(&self) -> bool {
+        !self.is_multicast()
+    }
+
+    /// Whether the address was made up locally rather than assigned by a
+    /// manufacturer, as virtual machines' and containers' usually are.
+    pub fn is_locally_administered(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+
+    /// The organizationally unique identifier: the first three octets,
+    /// which identify the manufacturer of a universally administered address.
+    pub fn oui(&self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+
+    /// Who the address's OUI is registered to, if the bundled table knows.
+    /// Locally administered addresses have no vendor.
+    pub fn vendor(&self) -> Option<&'static str> {
+        if self.is_locally_administered() {
+            return None;
+        }
+        let oui = format!("{:02X}{:02X}{:02X}", self.0[0], self.0[1], self.0[2]);
+        OUI_TABLE.lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('\t'))
+            .find(|(prefix, _)| *prefix == oui)
+            .map(|(_, vendor)| vendor)
+    }
+
+    pub fn format(&self, notation: MacNotation) -> String {
+        let [a, b, c, d, e, f] = self.0;
+        match notation {
+            MacNotation::Colon => format!("{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, f),
+            MacNotation::Hyphen => format!("{:02X}-{:02X}-{:02X}-{:02X}-{:02X}-{:02X}", a, b, c, d, e, f),
+            MacNotation::Dot => format!("{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}", a, b, c, d, e, f),
+            MacNotation::Bare => format!("{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}", a, b, c, d, e, f),
+        }
+    }
+}
+
+/// Writes the address in colon notation.
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(MacNotation::Colon))
+    }
+}
+
+/// Reads any of the notations, in either case.
+impl std::str::FromStr for MacAddress {
+    type Err = MacAddressError;
+
+    fn from_str(input: &str) -> Result<MacAddress, Self::Err> {
+        let err = || MacAddressError(input.to_string());
+        let trimmed = input.trim();
+        let digits: String = match trimmed.len() {
+            // separators have to sit between every pair of digits, or every four for dots
+            17 => {
+                let separator = trimmed.as_bytes()[2];
+                if !matches!(separator, b':' | b'-') || trimmed.bytes().skip(2).step_by(3).any(|byte| byte != separator) {
+                    return Err(err());
+                }
+                trimmed.split(separator as char).collect()
+            },
+            14 => {
+                if trimmed.bytes().skip(4).step_by(5).any(|byte| byte != b'.') {
+                    return Err(err());
+                }
+                trimmed.split('.').collect()
+            },
+            12 => trimmed.to_string(),
+            _ => return Err(err()),
+        };
+        if digits.len() != 12 || !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+
+        let mut octets = [0; 6];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            *octet = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| err())?;
+        }
+        Ok(MacAddress(octets))
+    }
+}
+
+impl From<[u8; 6]> for MacAddress {
+    fn from(octets: [u8; 6]) -> Self {
+        MacAddress(octets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_notations() {
+        let mac = MacAddress::new([0x3c, 0x2c, 0x30, 0xa1, 0xb2, 0xc4]);
+        for (notation, text) in [
+            (MacNotation::Colon, "3c:2c:30:a1:b2:c4"),
+            (MacNotation::Hyphen, "3C-2C-30-A1-B2-C4"),
+            (MacNotation::Dot, "3c2c.30a1.b2c4"),
+            (MacNotation::Bare, "3c2c30a1b2c4"),
+        ] {
+            assert_eq!(mac.format(notation), text);
+            assert_eq!(text.parse::<MacAddress>(), Ok(mac));
+            assert_eq!(text.to_uppercase().parse::<MacAddress>(), Ok(mac));
+        }
+        assert_eq!(mac.to_string(), "3c:2c:30:a1:b2:c4");
+
+        for bad in ["3c:2c:30:a1:b2", "3c:2c:30-a1:b2:c4", "3c2c:30a1:b2c4", "3c:2c:30:a1:b2:cg", "+c2c30a1b2c4", ""] {
+            assert_eq!(bad.parse::<MacAddress>(), Err(MacAddressError(bad.to_string())), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_address_kinds() {
+        assert!(MacAddress::BROADCAST.is_broadcast() && MacAddress::BROADCAST.is_multicast());
+        let multicast = MacAddress::for_ipv4_multicast(Ipv4Addr::new(224, 0, 0, 251)).unwrap();
+        assert_eq!(multicast.to_string(), "01:00:5e:00:00:fb");
+        assert!(multicast.is_multicast() && !multicast.is_broadcast());
+        assert_eq!(MacAddress::for_ipv4_multicast(Ipv4Addr::new(239, 255, 255, 250)).unwrap().to_string(), "01:00:5e:7f:ff:fa");
+        assert_eq!(MacAddress::for_ipv4_multicast(Ipv4Addr::new(10, 0, 0, 1)), None);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            let mac = MacAddress::random(&mut rng);
+            assert!(mac.is_unicast() && mac.is_locally_administered(), "{}", mac);
+        }
+    }
+
+    #[test]
+    fn test_vendor() {
+        assert_eq!("08:00:27:12:34:56".parse::<MacAddress>().unwrap().vendor(), Some("PCS Systemtechnik (VirtualBox)"));
+        assert_eq!("b8:27:eb:00:00:01".parse::<MacAddress>().unwrap().oui(), [0xb8, 0x27, 0xeb]);
+        assert_eq!("b8:27:eb:00:00:01".parse::<MacAddress>().unwrap().vendor(), Some("Raspberry Pi Foundation"));
+        // the same digits with the locally administered bit set belong to no one
+        assert_eq!("0a:00:27:12:34:56".parse::<MacAddress>().unwrap().vendor(), None);
+        assert_eq!("00:00:00:00:00:01".parse::<MacAddress>().unwrap().vendor(), None);
+
+        // the table stays sorted and well-formed
+        let ouis: Vec<&str> = OUI_TABLE.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split_once('\t').unwrap().0)
+            .collect();
+        assert!(ouis.iter().all(|oui| oui.len() == 6 && oui.bytes().all(|byte| byte.is_ascii_hexdigit())));
+        assert!(ouis.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
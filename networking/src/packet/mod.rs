@@ -0,0 +1,139 @@
+//! The packets below TCP: Ethernet frames, ARP, IPv4, ICMP and UDP.
+//!
+//! Every type has an `encode` that fills in lengths and checksums and a
+//! `decode` that checks them, so what one produces the other reads back
+//! byte for byte.
+
+use std::io;
+
+mod arp;
+mod ethernet;
+mod icmp;
+mod ipv4;
+mod mac;
+mod udp;
+
+pub use arp::{ArpOperation, ArpPacket};
+pub use ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+pub use icmp::{IcmpPacket, DESTINATION_UNREACHABLE, ECHO_REPLY, ECHO_REQUEST, TIME_EXCEEDED};
+pub use ipv4::{Ipv4Packet, PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+pub use mac::{MacAddress, MacAddressError, MacNotation};
+pub use udp::UdpDatagram;
+
+pub use crate::tcp::checksum;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    // Frames as they appear on the wire between a laptop, 192.168.1.23, and
+    // its router, 192.168.1.1: ARP for the router, a ping of 8.8.8.8 and its
+    // answer, and a DNS query for example.com.
+    const ARP_REQUEST: [u8; 60] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x3c, 0x2c, 0x30, 0xa1, 0xb2, 0xc4, 0x08, 0x06, 0x00, 0x01,
+        0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x3c, 0x2c, 0x30, 0xa1, 0xb2, 0xc4, 0xc0, 0xa8, 0x01, 0x17,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0xa8, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    const ARP_REPLY: [u8; 60] = [
+        0x3c, 0x2c, 0x30, 0xa1, 0xb2, 0xc4, 0xf4, 0xf2, 0x6d, 0x0a, 0x1b, 0x2c, 0x08, 0x06, 0x00, 0x01,
+        0x08, 0x00, 0x06, 0x04, 0x00, 0x02, 0xf4, 0xf2, 0x6d, 0x0a, 0x1b, 0x2c, 0xc0, 0xa8, 0x01, 0x01,
+        0x3c, 0x2c, 0x30, 0xa1, 0xb2, 0xc4, 0xc0, 0xa8, 0x01, 0x17, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    const PING_REQUEST: [u8; 90] = [
+        0xf4, 0xf2, 0x6d, 0x0a, 0x1b, 0x2c, 0x3c, 0x2c, 0x30, 0xa1, 0xb2, 0xc4, 0x08, 0x00, 0x45, 0x00,
+        0x00, 0x4c, 0x8f, 0x4e, 0x40, 0x00, 0x40, 0x01, 0xd9, 0x93, 0xc0, 0xa8, 0x01, 0x17, 0x08, 0x08,
+        0x08, 0x08, 0x08, 0x00, 0x50, 0x5f, 0x1d, 0x2c, 0x00, 0x01, 0x6f, 0x3a, 0x5c, 0x66, 0x00, 0x00,
+        0x00, 0x00, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
+        0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d,
+        0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37,
+    ];
+    const PING_REPLY: [u8; 90] = [
+        0x3c, 0x2c, 0x30, 0xa1, 0xb2, 0xc4, 0xf4, 0xf2, 0x6d, 0x0a, 0x1b, 0x2c, 0x08, 0x00, 0x45, 0x00,
+        0x00, 0x4c, 0x00, 0x00, 0x00, 0x00, 0x75, 0x01, 0x73, 0xe2, 0x08, 0x08, 0x08, 0x08, 0xc0, 0xa8,
+        0x01, 0x17, 0x00, 0x00, 0x58, 0x5f, 0x1d, 0x2c, 0x00, 0x01, 0x6f, 0x3a, 0x5c, 0x66, 0x00, 0x00,
+        0x00, 0x00, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
+        0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d,
+        0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37,
+    ];
+    const DNS_QUERY: [u8; 82] = [
+        0xf4, 0xf2, 0x6d, 0x0a, 0x1b, 0x2c, 0x3c, 0x2c, 0x30, 0xa1, 0xb2, 0xc4, 0x08, 0x00, 0x45, 0x00,
+        0x00, 0x44, 0x3e, 0x1a, 0x40, 0x00, 0x40, 0x11, 0x79, 0x26, 0xc0, 0xa8, 0x01, 0x17, 0xc0, 0xa8,
+        0x01, 0x01, 0xc8, 0x22, 0x00, 0x35, 0x00, 0x30, 0x1c, 0x54, 0xb7, 0xc1, 0x01, 0x20, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63,
+        0x6f, 0x6d, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x29, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00,
+    ];
+
+    const LAPTOP: MacAddress = MacAddress([0x3c, 0x2c, 0x30, 0xa1, 0xb2, 0xc4]);
+    const ROUTER: MacAddress = MacAddress([0xf4, 0xf2, 0x6d, 0x0a, 0x1b, 0x2c]);
+
+    #[test]
+    fn test_arp_fixtures() {
+        let frame = EthernetFrame::decode(&ARP_REQUEST).unwrap();
+        assert_eq!((frame.dst, frame.src, frame.ethertype), (MacAddress::BROADCAST, LAPTOP, ETHERTYPE_ARP));
+        let request = ArpPacket::decode(&frame.payload).unwrap();
+        assert_eq!(request, ArpPacket::request(LAPTOP, Ipv4Addr::new(192, 168, 1, 23), Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(EthernetFrame::new(frame.dst, frame.src, frame.ethertype, request.encode()).encode(), ARP_REQUEST);
+
+        let reply = request.reply(ROUTER);
+        assert_eq!(EthernetFrame::new(LAPTOP, ROUTER, ETHERTYPE_ARP, reply.encode()).encode(), ARP_REPLY);
+        assert_eq!(ArpPacket::decode(&EthernetFrame::decode(&ARP_REPLY).unwrap().payload).unwrap(), reply);
+    }
+
+    #[test]
+    fn test_ping_fixtures() {
+        let frame = EthernetFrame::decode(&PING_REQUEST).unwrap();
+        assert_eq!(frame.ethertype, ETHERTYPE_IPV4);
+        let ip = Ipv4Packet::decode(&frame.payload).unwrap();
+        assert_eq!((ip.src, ip.dst, ip.protocol, ip.ttl), (Ipv4Addr::new(192, 168, 1, 23), Ipv4Addr::new(8, 8, 8, 8), PROTOCOL_ICMP, 64));
+        let ping = IcmpPacket::decode(&ip.payload).unwrap();
+        assert_eq!((ping.icmp_type, ping.identifier(), ping.sequence(), ping.payload.len()), (ECHO_REQUEST, 0x1d2c, 1, 48));
+
+        let rebuilt = Ipv4Packet { payload: ping.encode(), ..ip.clone() };
+        assert_eq!(EthernetFrame { payload: rebuilt.encode().unwrap(), ..frame }.encode(), PING_REQUEST);
+
+        let frame = EthernetFrame::decode(&PING_REPLY).unwrap();
+        let ip = Ipv4Packet::decode(&frame.payload).unwrap();
+        let pong = IcmpPacket::decode(&ip.payload).unwrap();
+        assert_eq!(pong, ping.echo_reply());
+        assert!(!ip.dont_fragment);
+        assert_eq!(EthernetFrame { payload: ip.encode().unwrap(), ..frame }.encode(), PING_REPLY);
+    }
+
+    #[test]
+    fn test_udp_fixture() {
+        let frame = EthernetFrame::decode(&DNS_QUERY).unwrap();
+        let ip = Ipv4Packet::decode(&frame.payload).unwrap();
+        assert_eq!(ip.protocol, PROTOCOL_UDP);
+        let query = UdpDatagram::decode(&ip.payload, ip.src, ip.dst).unwrap();
+        assert_eq!((query.src_port, query.dst_port), (51234, 53));
+        assert_eq!(&query.payload[12..24], b"\x07example\x03com");
+
+        let rebuilt = Ipv4Packet { payload: query.encode(ip.src, ip.dst).unwrap(), ..ip };
+        assert_eq!(EthernetFrame { payload: rebuilt.encode().unwrap(), ..frame }.encode(), DNS_QUERY);
+    }
+
+    #[test]
+    fn test_corruption_is_caught() {
+        for (at, layer) in [(24, "IPv4 header"), (36, "ICMP")] {
+            let mut frame = PING_REQUEST;
+            frame[at] ^= 0x01;
+            let ip = Ipv4Packet::decode(&frame[14..]);
+            let err = ip.and_then(|ip| IcmpPacket::decode(&ip.payload)).unwrap_err();
+            assert_eq!(err.to_string(), format!("bad {} checksum", layer));
+        }
+
+        let mut frame = DNS_QUERY;
+        frame[60] ^= 0x20;
+        let ip = Ipv4Packet::decode(&frame[14..]).unwrap();
+        assert!(UdpDatagram::decode(&ip.payload, ip.src, ip.dst).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::io;
+use std::net::Ipv4Addr;
+
+use super::{checksum, invalid, PROTOCOL_UDP};
+
+const HEADER_LEN: usize = 8;
+
+/// A UDP datagram (RFC 768). Its checksum covers the IPv4 addresses it is
+/// sent between, so those are needed to encode and decode it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpDatagram {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: Vec<u8>,
+}
+
+impl UdpDatagram {
+    pub fn new(src_port: u16, dst_port: u16, payload: Vec<u8>) -> Self {
+        UdpDatagram { src_port, dst_port, payload }
+    }
+
+    /// Fails if the datagram is longer than its 16-bit length field allows.
+    pub fn encode(&self, src: Ipv4Addr, dst: Ipv4Addr) -> io::Result<Vec<u8>> {
+        let len = HEADER_LEN + self.payload.len();
+        if len > u16::MAX as usize {
+            let error_msg = format!("UDP datagram of {} bytes, at most 65535 fit", len);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error_msg));
+        }
+        let mut datagram = Vec::with_capacity(len);
+        datagram.extend_from_slice(&self.src_port.to_be_bytes());
+        datagram.extend_from_slice(&self.dst_port.to_be_bytes());
+        datagram.extend_from_slice(&(len as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(&self.payload);
+        // zero means "no checksum", so a sum that comes out as zero is sent as its other form
+        let sum = match checksum(&[&pseudo_header(src, dst, len), &datagram]) {
+            0 => 0xffff,
+            sum => sum,
+        };
+        datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+        Ok(datagram)
+    }
+
+    /// Parses a datagram sent from `src` to `dst`, checking its checksum
+    /// unless the sender left it out.
+    pub fn decode(datagram: &[u8], src: Ipv4Addr, dst: Ipv4Addr) -> io::Result<UdpDatagram> {
+        if datagram.len() < HEADER_LEN {
+            return Err(invalid("truncated UDP header"));
+        }
+        let half = |at: usize| u16::from_be_bytes([datagram[at], datagram[at + 1]]);
+        let len = half(4) as usize;
+        if len < HEADER_LEN || len > datagram.len() {
+            return Err(invalid("malformed UDP length"));
+        }
+        let datagram = &datagram[..len];
+        if half(6) != 0 && checksum(&[&pseudo_header(src, dst, len), datagram]) != 0 {
+            return Err(invalid("bad UDP checksum"));
+        }
+        Ok(UdpDatagram { src_port: half(0), dst_port: half(2), payload: datagram[HEADER_LEN..].to_vec() })
+    }
+}
+
+fn pseudo_header(src: Ipv4Addr, dst: Ipv4Addr, udp_len: usize) -> [u8; 12] {
+    let mut header = [0; 12];
+    header[..4].copy_from_slice(&src.octets());
+    header[4..8].copy_from_slice(&dst.octets());
+    header[9] = PROTOCOL_UDP;
+    header[10..].copy_from_slice(&(udp_len as u16).to_be_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        let (src, dst) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let datagram = UdpDatagram::new(5000, 53, b"query".to_vec());
+
+        let mut encoded = datagram.encode(src, dst).unwrap();
+        assert_eq!(UdpDatagram::decode(&encoded, src, dst).unwrap(), datagram);
+        // the checksum covers the addresses
+        assert!(UdpDatagram::decode(&encoded, src, Ipv4Addr::new(10, 0, 0, 3)).is_err());
+
+        // senders may leave the checksum out
+        encoded[6..8].copy_from_slice(&[0, 0]);
+        assert_eq!(UdpDatagram::decode(&encoded, src, Ipv4Addr::new(10, 0, 0, 3)).unwrap(), datagram);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let (src, dst) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let mut datagram = UdpDatagram::new(5000, 53, vec![0; 65535 - HEADER_LEN]);
+        assert_eq!(datagram.encode(src, dst).unwrap().len(), 65535);
+
+        datagram.payload.push(0);
+        assert_eq!(datagram.encode(src, dst).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
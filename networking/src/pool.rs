@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{Stream, Url};
+
+/// Where a connection goes: pooled connections are only shared between
+/// requests with the same scheme, host and port.
+type Key = (String, String, u16);
+
+#[derive(Default)]
+struct Host {
+    idle: Vec<(Stream, Instant)>,
+    /// Connections in use plus idle ones.
+    open: usize,
+}
+
+/// Keeps HTTP/1.1 keep-alive connections open for reuse, per host.
+///
+/// At most `max_per_host` connections are open to a host at once; callers
+/// asking for another one wait until one is returned or closed. Connections
+/// left idle for longer than `idle_timeout` are closed rather than reused.
+pub struct ConnectionPool {
+    idle_timeout: Duration,
+    max_per_host: usize,
+    hosts: Mutex<BTreeMap<Key, Host>>,
+    released: Condvar,
+}
+
+impl ConnectionPool {
+    pub const fn new(idle_timeout: Duration, max_per_host: usize) -> Self {
+        ConnectionPool {
+            idle_timeout,
+            max_per_host,
+            hosts: Mutex::new(BTreeMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// How many connections to `url`'s host are idle in the pool.
+    pub fn idle(&self, url: &Url) -> usize {
+        self.lock().get(&key(url)).map_or(0, |host| host.idle.len())
+    }
+
+    /// Hands out an idle connection to `url`'s host, or opens a new one with
+    /// `connect` once there's room for it.
+    pub(crate) fn checkout<F>(&self, url: &Url, deadline: Option<Instant>, connect: F) -> io::Result<Lease<'_>>
+    where
+        F: FnOnce() -> io::Result<Stream>,
+    {
+        let key = key(url);
+        let mut hosts = self.lock();
+        loop {
+            let host = hosts.entry(key.clone()).or_default();
+            while let Some((stream, since)) = host.idle.pop() {
+                if since.elapsed() < self.idle_timeout && stream.is_open() {
+                    return Ok(Lease { pool: self, key, stream: Some(stream), reused: true, released: false });
+                }
+                host.open -= 1;
+            }
+            if host.open < self.max_per_host.max(1) {
+                host.open += 1;
+                break;
+            }
+
+            hosts = match deadline {
+                None => self.released.wait(hosts).unwrap_or_else(|err| err.into_inner()),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "no free connection to host"));
+                    }
+                    self.released.wait_timeout(hosts, left).unwrap_or_else(|err| err.into_inner()).0
+                },
+            };
+        }
+        drop(hosts);
+
+        // connect without holding the lock; the slot is already taken
+        let mut lease = Lease { pool: self, key, stream: None, reused: false, released: false };
+        lease.stream = Some(connect()?);
+        Ok(lease)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<Key, Host>> {
+        self.hosts.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        ConnectionPool::new(Duration::from_secs(90), 6)
+    }
+}
+
+/// A connection taken from the pool. It's closed when dropped unless it is
+/// handed back with `release`.
+pub(crate) struct Lease<'a> {
+    pool: &'a ConnectionPool,
+    key: Key,
+    stream: Option<Stream>,
+    /// Whether the connection has served an earlier request.
+    pub reused: bool,
+    released: bool,
+}
+
+impl Lease<'_> {
+    pub fn stream(&mut self) -> &mut Stream {
+        self.stream.as_mut().expect("leased connection")
+    }
+
+    /// Returns the connection to the pool for the next request to its host.
+    pub fn release(mut self) {
+        if let Some(stream) = self.stream.take() {
+            let mut hosts = self.pool.lock();
+            if let Some(host) = hosts.get_mut(&self.key) {
+                host.idle.push((stream, Instant::now()));
+                self.released = true;
+            }
+            self.pool.released.notify_one();
+        }
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        // closed, or never connected: either way the slot is free again
+        let mut hosts = self.pool.lock();
+        if let Some(host) = hosts.get_mut(&self.key) {
+            host.open -= 1;
+        }
+        self.pool.released.notify_one();
+    }
+}
+
+fn key(url: &Url) -> Key {
+    (url.scheme.clone(), url.host.clone(), url.port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::testing::{response, StandIn};
+    use crate::{PageReader, Request, RetryPolicy, TCPReader};
+
+    #[test]
+    fn test_reuses_connection() {
+        let server = StandIn::serve(vec![
+            response("200 OK", &[], "one"),
+            response("200 OK", &[], "two"),
+            response("200 OK", &[("Connection", "close")], "three"),
+            response("200 OK", &[], "four"),
+        ]);
+        let reader = TCPReader::new();
+        let url = Url::parse(&server.url("/")).unwrap();
+
+        for body in ["one", "two", "three"] {
+            assert_eq!(reader.read_page(&server.url("/")).unwrap().text(), body);
+        }
+        assert_eq!(server.connections(), 1);
+        assert_eq!(reader.pool.idle(&url), 0);
+
+        assert_eq!(reader.read_page(&server.url("/")).unwrap().text(), "four");
+        assert_eq!(server.connections(), 2);
+        assert_eq!(reader.pool.idle(&url), 1);
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let server = StandIn::serve(vec![response("200 OK", &[], "one"), response("200 OK", &[], "two")]);
+        let mut reader = TCPReader::new();
+        reader.pool = ConnectionPool::new(Duration::from_millis(50), 6);
+
+        reader.read_page(&server.url("/")).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        reader.read_page(&server.url("/")).unwrap();
+
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[test]
+    fn test_max_per_host() {
+        let server = StandIn::serve((0..8).map(|i| response("200 OK", &[], &i.to_string())).collect());
+        let mut reader = TCPReader::new();
+        reader.pool = ConnectionPool::new(Duration::from_secs(60), 2);
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| assert!(reader.read_page(&server.url("/")).unwrap().is_success()));
+            }
+        });
+
+        assert!(server.connections() <= 2);
+        assert_eq!(server.requests().len(), 8);
+    }
+
+    #[test]
+    fn test_server_closed_idle_connection() {
+        // answers one request per connection, without saying it will close them
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://127.0.0.1:{}/", listener.local_addr().unwrap().port());
+        let server = thread::spawn(move || {
+            for body in ["first", "second"] {
+                let (conn, _) = listener.accept().unwrap();
+                let mut conn = BufReader::new(conn);
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    conn.read_line(&mut line).unwrap();
+                }
+                conn.get_mut().write_all(&response("200 OK", &[], body)).unwrap();
+            }
+        });
+        let reader = TCPReader::new();
+
+        assert_eq!(reader.read_page(&url).unwrap().text(), "first");
+        assert_eq!(reader.read_page(&url).unwrap().text(), "second");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_timeout_on_reused_connection_is_not_retried() {
+        // answers the first request, then leaves the second one hanging
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://127.0.0.1:{}/", listener.local_addr().unwrap().port());
+        let server = thread::spawn(move || {
+            let (conn, _) = listener.accept().unwrap();
+            let mut conn = BufReader::new(conn);
+            for body in ["first", ""] {
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    conn.read_line(&mut line).unwrap();
+                }
+                if !body.is_empty() {
+                    conn.get_mut().write_all(&response("200 OK", &[], body)).unwrap();
+                }
+            }
+            thread::sleep(Duration::from_millis(300));
+            listener.set_nonblocking(true).unwrap();
+            listener.accept().is_ok()
+        });
+        let mut reader = TCPReader::new();
+        reader.options.read_timeout = Some(Duration::from_millis(100));
+        reader.options.retry = RetryPolicy::none();
+
+        assert_eq!(reader.read_page(&url).unwrap().text(), "first");
+        let err = reader.read_page(&url).unwrap_err();
+        assert_eq!(err.downcast_ref::<io::Error>().map(io::Error::kind), Some(io::ErrorKind::TimedOut));
+        // the request may have reached the server, so it isn't sent on a new connection
+        assert!(!server.join().unwrap());
+    }
+
+    #[test]
+    fn test_post_on_closed_connection_is_not_resent() {
+        // answers the first request, then closes the connection on the second
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://127.0.0.1:{}/", listener.local_addr().unwrap().port());
+        let server = thread::spawn(move || {
+            let (conn, _) = listener.accept().unwrap();
+            let mut conn = BufReader::new(conn);
+            for body in ["first", ""] {
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    conn.read_line(&mut line).unwrap();
+                }
+                if !body.is_empty() {
+                    conn.get_mut().write_all(&response("200 OK", &[], body)).unwrap();
+                }
+            }
+            drop(conn);
+            thread::sleep(Duration::from_millis(300));
+            listener.set_nonblocking(true).unwrap();
+            listener.accept().is_ok()
+        });
+        let reader = TCPReader::new();
+
+        assert_eq!(reader.read_page(&url).unwrap().text(), "first");
+        let request = Request::post(Url::parse(&url).unwrap()).with_body(b"order=1".to_vec());
+        assert!(reader.send(request).is_err());
+        assert!(!server.join().unwrap());
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Instant;
+
+use crate::http::{Method, Reply, Request, Response};
+use crate::{PageError, PageReader, Url};
+
+/// Headers that carry credentials and mustn't leak to another origin.
+const AUTH_HEADERS: [&str; 3] = ["Authorization", "Proxy-Authorization", "Cookie"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    /// How many redirects to follow; with `0` the redirect itself is returned.
+    pub max_hops: usize,
+    /// Refuse redirects that lead to a different host.
+    pub same_host_only: bool,
+    /// Drop credentials from requests redirected to a different scheme, host
+    /// or port, so they don't leak to another server or over plain HTTP.
+    pub strip_auth_cross_host: bool,
+}
+
+impl RedirectPolicy {
+    pub const fn new() -> Self {
+        RedirectPolicy { max_hops: 10, same_host_only: false, strip_auth_cross_host: true }
+    }
+
+    pub const fn none() -> Self {
+        RedirectPolicy { max_hops: 0, ..RedirectPolicy::new() }
+    }
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        RedirectPolicy::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedirectError {
+    TooManyRedirects(usize),
+    Loop(Url),
+    CrossHost(Url),
+}
+
+impl fmt::Display for RedirectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedirectError::TooManyRedirects(n) => write!(f, "gave up after {} redirects", n),
+            RedirectError::Loop(url) => write!(f, "redirect loop through {}", url),
+            RedirectError::CrossHost(url) => write!(f, "refused redirect to another host: {}", url),
+        }
+    }
+}
+
+impl std::error::Error for RedirectError {}
+
+/// Sends `request` through `reader`, following redirects as `policy` allows.
+pub fn follow<R: PageReader + ?Sized>(reader: &R, request: Request, policy: &RedirectPolicy) -> Result<Response, PageError> {
+    follow_with(request, policy, reader.deadline(), |request| reader.fetch(request))
+}
+
+/// Like `follow`, with `send` making each request, all of them by `deadline`.
+pub(crate) fn follow_with<T, F>(
+    mut request: Request,
+    policy: &RedirectPolicy,
+    deadline: Option<Instant>,
+    mut send: F,
+) -> Result<T, PageError>
+where
+    T: Reply,
+    F: FnMut(&Request) -> Result<T, PageError>,
+{
+    request.deadline = request.deadline.into_iter().chain(deadline).min();
+    let mut visited = HashSet::new();
+    visited.insert((request.method, request.url.clone()));
+
+    for hops in 0.. {
+        let response = send(&request)?;
+        if !response.is_redirect() || policy.max_hops == 0 {
+            return Ok(response);
+        }
+        let location = match response.headers().get("Location") {
+            None => return Ok(response),
+            Some(location) => location,
+        };
+        if hops == policy.max_hops {
+            return Err(RedirectError::TooManyRedirects(hops).into());
+        }
+
+        let target = request.url.join(location)?;
+        if policy.same_host_only && target.host != request.url.host {
+            return Err(RedirectError::CrossHost(target).into());
+        }
+        let same_origin = (&target.scheme, &target.host, target.port) == (&request.url.scheme, &request.url.host, request.url.port);
+        if policy.strip_auth_cross_host && !same_origin {
+            for name in AUTH_HEADERS {
+                request.headers.remove(name);
+            }
+        }
+        // browsers turn a redirected POST into a GET, and 303 means "GET it there"
+        let status = response.status();
+        if (status == 303 && request.method != Method::Head) || (matches!(status, 301 | 302) && request.method == Method::Post) {
+            request.method = Method::Get;
+            request.body.clear();
+            for name in ["Content-Type", "Content-Length"] {
+                request.headers.remove(name);
+            }
+        }
+        if !visited.insert((request.method, target.clone())) {
+            return Err(RedirectError::Loop(target).into());
+        }
+        request.url = target;
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{response, StandIn};
+    use crate::{HTTPReader, TCPReader, TlsConnector};
+
+    fn readers(policy: RedirectPolicy) -> Vec<Box<dyn PageReader>> {
+        let mut http = HTTPReader::new();
+        http.redirects = policy;
+        let mut tcp = TCPReader::new();
+        tcp.redirects = policy;
+        vec![Box::new(http), Box::new(tcp)]
+    }
+
+    fn error_of(result: Result<Response, PageError>) -> RedirectError {
+        result.unwrap_err().downcast_ref::<RedirectError>().unwrap().clone()
+    }
+
+    #[test]
+    fn test_follows_relative_location() {
+        for reader in readers(RedirectPolicy::default()) {
+            let server = StandIn::serve(vec![
+                response("301 Moved Permanently", &[("Location", "/new")], ""),
+                response("302 Found", &[("Location", "final?x=1")], ""),
+                response("200 OK", &[], "arrived"),
+            ]);
+
+            let page = reader.read_page(&server.url("/old")).unwrap();
+
+            assert_eq!(page.text(), "arrived");
+            let lines: Vec<String> = server.requests().iter()
+                .map(|head| head.lines().next().unwrap().to_string())
+                .collect();
+            assert_eq!(lines, ["GET /old HTTP/1.1", "GET /new HTTP/1.1", "GET /final?x=1 HTTP/1.1"]);
+        }
+    }
+
+    #[test]
+    fn test_rewrites_method() {
+        for reader in readers(RedirectPolicy::default()) {
+            let server = StandIn::serve(vec![
+                response("303 See Other", &[("Location", "/form")], ""),
+                response("200 OK", &[], "thanks"),
+                response("307 Temporary Redirect", &[("Location", "/upload")], ""),
+                response("200 OK", &[], "stored"),
+            ]);
+            let url = Url::parse(&server.url("/form")).unwrap();
+
+            let page = reader.send(Request::post(url.clone()).with_form(&[("a", "1")])).unwrap();
+            assert_eq!(page.text(), "thanks");
+            let page = reader.send(Request::put(url).with_body("data")).unwrap();
+            assert_eq!(page.text(), "stored");
+
+            let requests = server.requests();
+            let lines: Vec<&str> = requests.iter().map(|head| head.lines().next().unwrap()).collect();
+            assert_eq!(lines, ["POST /form HTTP/1.1", "GET /form HTTP/1.1", "PUT /form HTTP/1.1", "PUT /upload HTTP/1.1"]);
+            assert!(!requests[1].to_ascii_lowercase().contains("content-type"), "{}", requests[1]);
+            assert!(requests[3].ends_with("\r\ndata"), "{}", requests[3]);
+        }
+    }
+
+    #[test]
+    fn test_detects_loop() {
+        for reader in readers(RedirectPolicy::default()) {
+            let server = StandIn::serve(vec![
+                response("302 Found", &[("Location", "/b")], ""),
+                response("302 Found", &[("Location", "/a")], ""),
+            ]);
+
+            let err = error_of(reader.read_page(&server.url("/a")));
+
+            assert_eq!(err, RedirectError::Loop(Url::parse(&server.url("/a")).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_max_hops() {
+        let policy = RedirectPolicy { max_hops: 1, ..RedirectPolicy::default() };
+        for reader in readers(policy) {
+            let server = StandIn::serve(vec![
+                response("307 Temporary Redirect", &[("Location", "/2")], ""),
+                response("307 Temporary Redirect", &[("Location", "/3")], ""),
+            ]);
+
+            assert_eq!(error_of(reader.read_page(&server.url("/1"))), RedirectError::TooManyRedirects(1));
+        }
+
+        for reader in readers(RedirectPolicy::none()) {
+            let server = StandIn::serve(vec![response("307 Temporary Redirect", &[("Location", "/2")], "")]);
+
+            assert_eq!(reader.read_page(&server.url("/1")).unwrap().status, 307);
+        }
+    }
+
+    #[test]
+    fn test_cross_host() {
+        for reader in readers(RedirectPolicy::default()) {
+            let other = StandIn::serve(vec![response("200 OK", &[], "elsewhere")]);
+            let target = format!("http://localhost:{}/landing", other.port);
+            let server = StandIn::serve(vec![response("302 Found", &[("Location", &target)], "")]);
+
+            let mut request = Request::get(Url::parse(&server.url("/")).unwrap());
+            request.headers.insert("Authorization", "Bearer secret");
+            let page = follow(reader.as_ref(), request, &RedirectPolicy::default()).unwrap();
+
+            assert_eq!(page.text(), "elsewhere");
+            // reqwest sends header names in lower case
+            assert!(server.requests()[0].to_lowercase().contains("authorization: bearer secret"));
+            assert!(!other.requests()[0].to_lowercase().contains("authorization"));
+        }
+
+        let policy = RedirectPolicy { same_host_only: true, ..RedirectPolicy::default() };
+        for reader in readers(policy) {
+            let server = StandIn::serve(vec![response("302 Found", &[("Location", "http://localhost/")], "")]);
+
+            let err = error_of(reader.read_page(&server.url("/")));
+
+            assert_eq!(err, RedirectError::CrossHost(Url::parse("http://localhost/").unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_strips_auth_on_downgrade() {
+        let plain = StandIn::serve(vec![response("200 OK", &[], "insecure")]);
+        let target = format!("http://localhost:{}/landing", plain.port);
+        let (secure, ca) = StandIn::serve_tls(vec![response("302 Found", &[("Location", &target)], "")]);
+        let mut reader = TCPReader::new();
+        reader.tls = Some(TlsConnector::with_ca_pem(ca.as_bytes()).unwrap());
+
+        // same host, but another scheme and port
+        let mut request = Request::get(Url::parse(&format!("https://localhost:{}/", secure.port)).unwrap());
+        request.headers.insert("Authorization", "Bearer secret");
+        request.headers.insert("Cookie", "session=1");
+        let page = follow(&reader, request, &RedirectPolicy::default()).unwrap();
+
+        assert_eq!(page.text(), "insecure");
+        assert!(secure.requests()[0].contains("Authorization: Bearer secret"));
+        let landed = plain.requests()[0].to_lowercase();
+        assert!(!landed.contains("authorization") && !landed.contains("cookie"), "{}", landed);
+    }
+}
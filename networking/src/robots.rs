@@ -0,0 +1,206 @@
+use std::time::Duration;
+
+/// The rules in a site's `robots.txt` that apply to one crawler.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Robots {
+    rules: Vec<Rule>,
+    /// How long the site asks crawlers to wait between requests.
+    pub crawl_delay: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+impl Robots {
+    /// Rules for a site without a `robots.txt`.
+    pub fn allow_all() -> Self {
+        Robots::default()
+    }
+
+    /// Picks out the groups of `text` addressed to `user_agent`, or to every
+    /// crawler (`*`) if none are.
+    pub fn parse(text: &str, user_agent: &str) -> Self {
+        let token = user_agent.split('/').next().unwrap_or_default().trim();
+        let mut groups: Vec<Group> = vec![];
+        let mut open_group = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let (field, value) = match line.split_once(':') {
+                None => continue,
+                Some((field, value)) => (field.trim().to_ascii_lowercase(), value.trim()),
+            };
+            if field == "user-agent" {
+                // consecutive user-agent lines share the rules that follow them
+                if !open_group {
+                    groups.push(Group::default());
+                    open_group = true;
+                }
+                if let Some(group) = groups.last_mut() {
+                    group.agents.push(value.to_ascii_lowercase());
+                }
+                continue;
+            }
+            open_group = false;
+            let group = match groups.last_mut() {
+                None => continue,
+                Some(group) => group,
+            };
+            match field.as_str() {
+                "allow" | "disallow" if !value.is_empty() => {
+                    group.rules.push(Rule { allow: field == "allow", pattern: value.to_string() });
+                },
+                "crawl-delay" => {
+                    // negative, NaN and absurdly long delays are ignored
+                    group.crawl_delay = value.parse::<f64>().ok()
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+                },
+                _ => {},
+            }
+        }
+
+        let named = |group: &&Group| group.agents.iter().any(|agent| agent.eq_ignore_ascii_case(token));
+        let mut matching: Vec<&Group> = groups.iter().filter(named).collect();
+        if matching.is_empty() {
+            matching = groups.iter().filter(|group| group.agents.iter().any(|agent| agent == "*")).collect();
+        }
+
+        Robots {
+            rules: matching.iter().flat_map(|group| group.rules.iter().cloned()).collect(),
+            crawl_delay: matching.iter().filter_map(|group| group.crawl_delay).max(),
+        }
+    }
+
+    /// Whether `path`, with its query, may be fetched. The most specific
+    /// matching rule wins, and `Allow` wins a tie.
+    pub fn allowed(&self, path: &str) -> bool {
+        self.rules.iter()
+            .filter(|rule| matches(rule.pattern.as_bytes(), path.as_bytes()))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+/// Matches a path prefix, where `*` stands for any run of characters and a
+/// trailing `$` anchors the pattern at the end of the path.
+///
+/// On a mismatch only the last `*` seen takes one more character, which
+/// keeps this at most the pattern length times the path length rather than
+/// exponential.
+fn matches(pattern: &[u8], path: &[u8]) -> bool {
+    let (pattern, anchored) = match pattern.split_last() {
+        Some((b'$', rest)) => (rest, true),
+        _ => (pattern, false),
+    };
+    let (mut p, mut s) = (0, 0);
+    // where matching resumes after the last `*`, and the path it took so far
+    let mut star: Option<(usize, usize)> = None;
+    loop {
+        match pattern.get(p) {
+            None if !anchored || s == path.len() => return true,
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, s));
+                continue;
+            },
+            Some(c) if path.get(s) == Some(c) => {
+                p += 1;
+                s += 1;
+                continue;
+            },
+            _ => {},
+        }
+        match star {
+            Some((after, taken)) if taken < path.len() => {
+                star = Some((after, taken + 1));
+                p = after;
+                s = taken + 1;
+            },
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "
+        # keep everyone out of the admin pages
+        User-agent: *
+        Disallow: /admin
+        Disallow: /*.pdf$
+        Allow: /admin/public
+
+        User-agent: net
+        User-agent: other-bot
+        Disallow: /private/
+        Allow: /private/readme
+        Crawl-delay: 1.5
+    ";
+
+    #[test]
+    fn test_everyone() {
+        let robots = Robots::parse(ROBOTS, "somebot/2.0");
+
+        assert!(robots.allowed("/"));
+        assert!(!robots.allowed("/admin"));
+        assert!(!robots.allowed("/admin/users?id=1"));
+        assert!(robots.allowed("/admin/public/index.html"));
+        assert!(!robots.allowed("/docs/manual.pdf"));
+        assert!(robots.allowed("/docs/manual.pdf.html"));
+        assert_eq!(robots.crawl_delay, None);
+    }
+
+    #[test]
+    fn test_named_agent() {
+        let robots = Robots::parse(ROBOTS, "net/0.1");
+
+        // a named group replaces the rules for everyone
+        assert!(robots.allowed("/admin"));
+        assert!(!robots.allowed("/private/keys"));
+        assert!(robots.allowed("/private/readme"));
+        assert_eq!(robots.crawl_delay, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(Robots::parse("", "net").allowed("/anything"));
+        assert!(Robots::parse("User-agent: *\nDisallow:\n", "net").allowed("/anything"));
+        assert!(!Robots::parse("User-agent: *\nDisallow: /\n", "net").allowed("/anything"));
+        assert!(Robots::allow_all().allowed("/"));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches(b"/*.php$", b"/index.php"));
+        assert!(!matches(b"/*.php$", b"/index.php5"));
+        assert!(matches(b"/a*b*c", b"/aXbYcZ"));
+        assert!(!matches(b"/a*b*c", b"/aXcYb"));
+        assert!(matches(b"$", b""));
+        assert!(matches(b"/fish*", b"/fish"));
+
+        // would take ages if every `*` tried every split of the path
+        let path = format!("/{}", "a".repeat(200));
+        assert!(!matches(b"/*a*a*a*a*a*a*a*a*b", path.as_bytes()));
+        assert!(matches(b"/*a*a*a*a*a*a*a*a*a$", path.as_bytes()));
+    }
+
+    #[test]
+    fn test_bad_crawl_delay() {
+        for delay in ["1e300", "-1", "NaN", "inf", "soon"] {
+            let robots = Robots::parse(&format!("User-agent: *\nCrawl-delay: {}\n", delay), "net");
+            assert_eq!(robots.crawl_delay, None, "{}", delay);
+        }
+    }
+}
@@ -0,0 +1,527 @@
+//! A small multi-threaded HTTP/1.1 server: a fixed pool of workers serving
+//! keep-alive connections with a `Handler`, such as `StaticFiles`.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::http::{self, Body, BodyLength, Headers, Response};
+use crate::proxy::percent_decode;
+
+/// Request bodies larger than this are refused.
+const MAX_BODY: u64 = 16 * 1024 * 1024;
+
+/// A request as received by the server, body included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerRequest {
+    pub method: String,
+    /// The target from the request line, e.g. `/search?q=rust`.
+    pub target: String,
+    pub version: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl ServerRequest {
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Whether the client wants the connection kept open afterwards.
+    pub fn keep_alive(&self) -> bool {
+        if self.version == "HTTP/1.0" {
+            self.headers.has_token("Connection", "keep-alive")
+        } else {
+            !self.headers.has_token("Connection", "close")
+        }
+    }
+}
+
+/// Answers requests. Called from several worker threads at once.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &ServerRequest) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&ServerRequest) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &ServerRequest) -> Response {
+        self(request)
+    }
+}
+
+/// Serves the files under a directory, with `index.html` or a listing for
+/// directories. Paths can't escape the directory.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+}
+
+impl StaticFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        StaticFiles { root: root.into() }
+    }
+
+    /// Maps a request path onto the file system, refusing `..`, anything
+    /// that could be read as a drive or separator on some platform, and
+    /// symlinks that lead out of the root.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.root.clone();
+        for segment in percent_decode(path).split('/') {
+            match segment {
+                "" | "." => {},
+                ".." => return None,
+                segment if segment.contains(['\\', ':', '\0']) => return None,
+                segment => resolved.push(segment),
+            }
+        }
+        self.contains(&resolved).then_some(resolved)
+    }
+
+    /// Whether `path` exists and, with its symlinks followed, is under the root.
+    fn contains(&self, path: &Path) -> bool {
+        match (self.root.canonicalize(), path.canonicalize()) {
+            (Ok(root), Ok(path)) => path.starts_with(root),
+            _ => false,
+        }
+    }
+
+    fn listing(&self, path: &str, dir: &Path) -> io::Result<Response> {
+        let mut names: Vec<String> = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                match entry.file_type() {
+                    Ok(kind) if kind.is_dir() => format!("{}/", name),
+                    _ => name,
+                }
+            })
+            .collect();
+        names.sort();
+
+        let title = escape(&percent_decode(path));
+        let mut html = format!("<!DOCTYPE html>\n<title>Index of {0}</title>\n<h1>Index of {0}</h1>\n<ul>\n", title);
+        for name in names {
+            let _ = writeln!(html, "<li><a href=\"{0}\">{0}</a></li>", escape(&name));
+        }
+        html.push_str("</ul>\n");
+        Ok(Response::new(200, html).with_header("Content-Type", "text/html; charset=utf-8"))
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &ServerRequest) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            return Response::new(405, "").with_header("Allow", "GET, HEAD");
+        }
+        let path = request.path();
+        let file = match self.resolve(path) {
+            Some(file) => file,
+            None => return Response::new(404, "not found\n"),
+        };
+
+        let result = if file.is_dir() {
+            if !path.ends_with('/') {
+                return Response::new(301, "").with_header("Location", &format!("{}/", path));
+            }
+            let index = file.join("index.html");
+            if index.is_file() && self.contains(&index) {
+                fs::read(&index).map(|body| Response::new(200, body).with_header("Content-Type", content_type(&index)))
+            } else {
+                self.listing(path, &file)
+            }
+        } else {
+            fs::read(&file).map(|body| Response::new(200, body).with_header("Content-Type", content_type(&file)))
+        };
+
+        match result {
+            Ok(response) => response,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Response::new(404, "not found\n"),
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => Response::new(403, "forbidden\n"),
+            Err(err) => Response::new(500, format!("{}\n", err)),
+        }
+    }
+}
+
+/// Listens for connections and hands them to a pool of worker threads.
+pub struct Server {
+    listener: TcpListener,
+    workers: usize,
+    idle_timeout: Duration,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Server> {
+        Ok(Server { listener: TcpListener::bind(addr)?, workers: 8, idle_timeout: Duration::from_secs(5) })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// How many connections are served at once; others wait their turn.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// How long a connection may sit without a request before it is closed.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Serves requests until accepting connections fails.
+    pub fn run<H: Handler + 'static>(self, handler: H) -> io::Result<()> {
+        self.serve(Arc::new(handler), &AtomicBool::new(false))
+    }
+
+    /// Serves requests on a background thread until the handle is dropped.
+    pub fn spawn<H: Handler + 'static>(self, handler: H) -> io::Result<ServerHandle> {
+        let addr = self.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || self.serve(Arc::new(handler), &stopped));
+        Ok(ServerHandle { addr, stop, thread: Some(thread) })
+    }
+
+    fn serve(self, handler: Arc<dyn Handler>, stop: &AtomicBool) -> io::Result<()> {
+        let (tx, rx) = mpsc::channel::<TcpStream>();
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..self.workers {
+            let (rx, handler, idle_timeout) = (rx.clone(), handler.clone(), self.idle_timeout);
+            thread::spawn(move || loop {
+                let next = rx.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).recv();
+                let conn = match next {
+                    Ok(conn) => conn,
+                    Err(_) => return,
+                };
+                if conn.set_read_timeout(Some(idle_timeout)).is_ok() {
+                    // the client going away is no concern of the other workers
+                    let _ = serve_connection(conn, &*handler);
+                }
+            });
+        }
+
+        for conn in self.listener.incoming() {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            match conn {
+                Ok(conn) => {
+                    if tx.send(conn).is_err() {
+                        break;
+                    }
+                },
+                // the connection was reset before we got to it
+                Err(err) if matches!(err.kind(), io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset) => {},
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A server running on a background thread. Dropping it stops accepting
+/// connections; requests in progress are still answered.
+pub struct ServerHandle {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl ServerHandle {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Stops the server, returning the error that stopped it earlier if any.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.shut_down()
+    }
+
+    fn shut_down(&mut self) -> io::Result<()> {
+        let thread = match self.thread.take() {
+            None => return Ok(()),
+            Some(thread) => thread,
+        };
+        self.stop.store(true, Ordering::SeqCst);
+        // wake the accept loop up so it sees the flag
+        let _ = TcpStream::connect(self.addr);
+        thread.join().unwrap_or_else(|_| Err(io::Error::other("server thread panicked")))
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        let _ = self.shut_down();
+    }
+}
+
+/// Answers requests on `conn` until either side closes it.
+fn serve_connection<S: Read + Write>(conn: S, handler: &dyn Handler) -> io::Result<()> {
+    let mut conn = BufReader::new(conn);
+    loop {
+        let request = match read_request(&mut conn) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                let status = match err.kind() {
+                    io::ErrorKind::InvalidData => 400,
+                    io::ErrorKind::FileTooLarge => 413,
+                    _ => return Err(err),
+                };
+                let request = ServerRequest {
+                    method: "GET".to_string(),
+                    target: "/".to_string(),
+                    version: "HTTP/1.1".to_string(),
+                    headers: Headers::new(),
+                    body: vec![],
+                };
+                return write_response(conn.get_mut(), &request, Response::new(status, format!("{}\n", err)), false);
+            },
+        };
+
+        // a handler that panics costs its request, not the worker
+        let response = match panic::catch_unwind(AssertUnwindSafe(|| handler.handle(&request))) {
+            Ok(response) => response,
+            Err(_) => Response::new(500, "internal server error\n").with_header("Connection", "close"),
+        };
+        let keep_alive = request.keep_alive() && !response.headers.has_token("Connection", "close");
+        write_response(conn.get_mut(), &request, response, keep_alive)?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the next request, or `None` if the client closed the connection
+/// between requests.
+fn read_request<S: Read + Write>(conn: &mut BufReader<S>) -> io::Result<Option<ServerRequest>> {
+    let line = loop {
+        if conn.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        // some clients send a stray CRLF after a body
+        let line = http::read_line(conn)?;
+        if !line.is_empty() {
+            break line;
+        }
+    };
+    let mut parts = line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) if version.starts_with("HTTP/1.") && !method.is_empty() => {
+            (method.to_string(), target.to_string(), version.to_string())
+        },
+        _ => return Err(http::invalid_data(format!("malformed request line {:?}", line))),
+    };
+    let headers = http::read_fields(conn)?;
+
+    let length = if headers.has_token("Transfer-Encoding", "chunked") {
+        BodyLength::Chunked
+    } else {
+        match headers.get("Content-Length") {
+            None => BodyLength::Empty,
+            Some(value) => match value.trim().parse() {
+                Ok(n) if n > MAX_BODY => return Err(io::Error::new(io::ErrorKind::FileTooLarge, "request body is too large")),
+                Ok(n) => BodyLength::Fixed(n),
+                Err(_) => return Err(http::invalid_data(format!("invalid Content-Length {:?}", value))),
+            },
+        }
+    };
+    if length != BodyLength::Empty && headers.has_token("Expect", "100-continue") {
+        conn.get_mut().write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    let mut body = vec![];
+    Body::new(&mut *conn, length).take(MAX_BODY + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(io::Error::new(io::ErrorKind::FileTooLarge, "request body is too large"));
+    }
+
+    Ok(Some(ServerRequest { method, target, version, headers, body }))
+}
+
+fn write_response<W: Write>(conn: &mut W, request: &ServerRequest, response: Response, keep_alive: bool) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, response.reason);
+    for (name, value) in response.headers.iter() {
+        if !["Content-Length", "Connection", "Transfer-Encoding"].iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            let _ = write!(head, "{}: {}\r\n", name, value);
+        }
+    }
+    let bodiless = (100..200).contains(&response.status) || response.status == 204 || response.status == 304;
+    if !bodiless {
+        let _ = write!(head, "Content-Length: {}\r\n", response.body.len());
+    }
+    if !keep_alive {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+
+    conn.write_all(head.as_bytes())?;
+    if !bodiless && request.method != "HEAD" {
+        conn.write_all(&response.body)?;
+    }
+    conn.flush()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::TempDir;
+    use crate::{HTTPReader, PageReader, TCPReader};
+
+    /// Sends `raw` on a fresh connection and reads until the server closes it.
+    fn exchange(server: &ServerHandle, raw: &str) -> String {
+        let mut conn = TcpStream::connect(server.addr()).unwrap();
+        conn.write_all(raw.as_bytes()).unwrap();
+        let mut reply = String::new();
+        conn.read_to_string(&mut reply).unwrap();
+        reply
+    }
+
+    #[test]
+    fn test_static_files() {
+        let dir = TempDir::new();
+        fs::write(dir.path().join("index.html"), "<h1>home</h1>").unwrap();
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join("docs").join("read me.txt"), "plain").unwrap();
+        let server = Server::bind("127.0.0.1:0").unwrap().spawn(StaticFiles::new(dir.path())).unwrap();
+
+        for reader in [Box::new(TCPReader::new()) as Box<dyn PageReader>, Box::new(HTTPReader::new())] {
+            let page = reader.read_page(&server.url("/")).unwrap();
+            assert_eq!(page.text(), "<h1>home</h1>");
+            assert_eq!(page.headers.get("Content-Type"), Some("text/html; charset=utf-8"));
+
+            let page = reader.read_page(&server.url("/docs/read%20me.txt")).unwrap();
+            assert_eq!(page.text(), "plain");
+
+            // redirected to the directory, which has no index
+            let page = reader.read_page(&server.url("/docs")).unwrap();
+            assert!(page.text().contains(r#"<a href="read me.txt">"#), "{}", page.text());
+        }
+
+        let reply = exchange(&server, "GET /../secret HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(reply.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", reply);
+        let reply = exchange(&server, "HEAD /index.html HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(reply.contains("Content-Length: 13\r\n") && reply.ends_with("\r\n\r\n"), "{}", reply);
+        let reply = exchange(&server, "DELETE /index.html HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(reply.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", reply);
+        assert!(reply.contains("Allow: GET, HEAD\r\n"), "{}", reply);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_stay_inside_root() {
+        use std::os::unix::fs::symlink;
+
+        let outside = TempDir::new();
+        fs::write(outside.path().join("secret"), "hidden").unwrap();
+        let dir = TempDir::new();
+        fs::write(dir.path().join("page.txt"), "shown").unwrap();
+        symlink(dir.path().join("page.txt"), dir.path().join("alias.txt")).unwrap();
+        symlink(outside.path().join("secret"), dir.path().join("secret")).unwrap();
+        symlink(outside.path(), dir.path().join("elsewhere")).unwrap();
+        let server = Server::bind("127.0.0.1:0").unwrap().spawn(StaticFiles::new(dir.path())).unwrap();
+
+        let reader = TCPReader::new();
+        assert_eq!(reader.read_page(&server.url("/alias.txt")).unwrap().text(), "shown");
+        for path in ["/secret", "/elsewhere/secret", "/elsewhere/"] {
+            assert_eq!(reader.read_page(&server.url(path)).unwrap().status, 404, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_handler_and_keep_alive() {
+        let server = Server::bind("127.0.0.1:0").unwrap()
+            .spawn(|request: &ServerRequest| {
+                let body = format!("{} {} {:?} {}", request.method, request.path(), request.query(), String::from_utf8_lossy(&request.body));
+                Response::new(200, body)
+            })
+            .unwrap();
+
+        let reply = exchange(&server, "\
+            POST /echo?x=1 HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+            PUT /chunked HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n\
+            GET /last HTTP/1.0\r\n\r\n");
+
+        let expected = "\
+            HTTP/1.1 200 OK\r\nContent-Length: 28\r\n\r\nPOST /echo Some(\"x=1\") hello\
+            HTTP/1.1 200 OK\r\nContent-Length: 21\r\n\r\nPUT /chunked None abc\
+            HTTP/1.1 200 OK\r\nContent-Length: 15\r\nConnection: close\r\n\r\nGET /last None ";
+        assert_eq!(reply, expected);
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        let server = Server::bind("127.0.0.1:0").unwrap()
+            .workers(1)
+            .spawn(|request: &ServerRequest| {
+                assert_ne!(request.path(), "/panic", "handler blew up");
+                Response::new(200, "fine")
+            })
+            .unwrap();
+
+        // the one worker survives every panic
+        for _ in 0..3 {
+            let reply = exchange(&server, "GET /panic HTTP/1.1\r\n\r\n");
+            assert!(reply.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{}", reply);
+        }
+        let reply = exchange(&server, "GET /ok HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(reply.ends_with("\r\n\r\nfine"), "{}", reply);
+    }
+
+    #[test]
+    fn test_bad_request() {
+        let server = Server::bind("127.0.0.1:0").unwrap()
+            .spawn(|_: &ServerRequest| Response::new(200, "unreachable"))
+            .unwrap();
+
+        let reply = exchange(&server, "nonsense\r\n\r\n");
+        assert!(reply.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", reply);
+        assert!(reply.contains("Connection: close\r\n"), "{}", reply);
+
+        let reply = exchange(&server, "POST / HTTP/1.1\r\nContent-Length: 999999999\r\n\r\n");
+        assert!(reply.starts_with("HTTP/1.1 413 Content Too Large\r\n"), "{}", reply);
+    }
+}
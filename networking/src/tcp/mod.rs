@@ -0,0 +1,515 @@
+//! A userspace TCP stack that sends and receives raw IPv4 packets through a
+//! `PacketSource`, such as a TUN device or, in tests, an in-memory `Pipe`.
+//!
+//! There is no background thread: whichever stream is blocked on a read or
+//! write drives the whole interface, receiving packets, running the
+//! retransmission timers and sending whatever is due.
+
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub(crate) mod segment;
+mod tcb;
+
+use segment::{Segment, ACK, RST, SYN};
+use tcb::{reset_for, State, Tcb};
+
+pub use segment::checksum;
+
+/// How long a blocked call waits for a packet before checking its timers.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const EPHEMERAL_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// Somewhere to exchange IPv4 packets with the rest of the network.
+pub trait PacketSource: Send {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+
+    /// Waits up to `timeout` for a packet, returning `None` if none came.
+    fn recv(&mut self, timeout: Duration) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// One end of an in-memory link between two packet sources.
+pub struct Pipe {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl Pipe {
+    pub fn pair() -> (Pipe, Pipe) {
+        let (a_tx, b_rx) = mpsc::channel();
+        let (b_tx, a_rx) = mpsc::channel();
+        (Pipe { tx: a_tx, rx: a_rx }, Pipe { tx: b_tx, rx: b_rx })
+    }
+}
+
+impl PacketSource for Pipe {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.tx.send(packet.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the other end of the pipe is gone"))
+    }
+
+    fn recv(&mut self, timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(packet) => Ok(Some(packet)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "the other end of the pipe is gone"))
+            },
+        }
+    }
+}
+
+type Key = (SocketAddrV4, SocketAddrV4);
+
+/// A network interface with one IPv4 address, running TCP over a packet
+/// source. Clones share the same interface.
+#[derive(Clone)]
+pub struct Interface {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    source: Box<dyn PacketSource>,
+    addr: Ipv4Addr,
+    rto: Duration,
+    conns: HashMap<Key, Tcb>,
+    /// Connections that arrived on each listening port, in order.
+    listeners: HashMap<u16, VecDeque<Key>>,
+    next_port: u16,
+}
+
+impl Interface {
+    pub fn new<P: PacketSource + 'static>(source: P, addr: Ipv4Addr) -> Self {
+        let inner = Inner {
+            source: Box::new(source),
+            addr,
+            rto: Duration::from_secs(1),
+            conns: HashMap::new(),
+            listeners: HashMap::new(),
+            next_port: *EPHEMERAL_PORTS.start(),
+        };
+        Interface { inner: Arc::new(Mutex::new(inner)) }
+    }
+
+    pub fn addr(&self) -> Ipv4Addr {
+        self.lock().addr
+    }
+
+    /// Sets how long new connections wait for an acknowledgement before
+    /// sending again; the wait doubles with each retransmission.
+    pub fn set_retransmission_timeout(&self, rto: Duration) {
+        self.lock().rto = rto;
+    }
+
+    /// Opens a connection, giving up after `timeout`.
+    pub fn connect(&self, remote: SocketAddrV4, timeout: Option<Duration>) -> io::Result<UserTcpStream> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let key = {
+            let mut inner = self.lock();
+            let local = SocketAddrV4::new(inner.addr, inner.ephemeral_port(remote)?);
+            let tcb = Tcb::connect(local, remote, rand::random(), inner.rto);
+            inner.conns.insert((local, remote), tcb);
+            inner.transmit()?;
+            (local, remote)
+        };
+
+        let connected = self.wait(deadline, |inner| {
+            let tcb = inner.conns.get(&key)?;
+            match tcb.state {
+                State::Closed => Some(Err(tcb.error.unwrap_or(io::ErrorKind::ConnectionRefused).into())),
+                _ if tcb.is_synchronized() => Some(Ok(())),
+                _ => None,
+            }
+        });
+        if let Err(err) = connected {
+            self.lock().conns.remove(&key);
+            return Err(err);
+        }
+        Ok(UserTcpStream::new(self.clone(), key))
+    }
+
+    pub fn listen(&self, port: u16) -> io::Result<UserTcpListener> {
+        let mut inner = self.lock();
+        if inner.listeners.contains_key(&port) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("already listening on port {}", port)));
+        }
+        inner.listeners.insert(port, VecDeque::new());
+        Ok(UserTcpListener { iface: self.clone(), port })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Drives the interface until `ready` has an answer or `deadline` passes.
+    fn wait<T, F>(&self, deadline: Option<Instant>, mut ready: F) -> io::Result<T>
+    where
+        F: FnMut(&mut Inner) -> Option<io::Result<T>>,
+    {
+        loop {
+            let mut inner = self.lock();
+            if let Some(result) = ready(&mut inner) {
+                // a read may have opened the window, or a write queued data
+                inner.transmit()?;
+                return result;
+            }
+            let now = Instant::now();
+            let mut wait = POLL_INTERVAL;
+            if let Some(deadline) = deadline {
+                if now >= deadline {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "operation timed out"));
+                }
+                wait = wait.min(deadline - now);
+            }
+            if let Some(timer) = inner.conns.values().filter_map(Tcb::next_timer).min() {
+                wait = wait.min(timer.saturating_duration_since(now));
+            }
+            inner.poll(wait)?;
+        }
+    }
+}
+
+impl fmt::Debug for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.lock();
+        f.debug_struct("Interface")
+            .field("addr", &inner.addr)
+            .field("connections", &inner.conns.len())
+            .finish()
+    }
+}
+
+impl Inner {
+    fn ephemeral_port(&mut self, remote: SocketAddrV4) -> io::Result<u16> {
+        for _ in EPHEMERAL_PORTS {
+            let port = self.next_port;
+            self.next_port = if port == *EPHEMERAL_PORTS.end() { *EPHEMERAL_PORTS.start() } else { port + 1 };
+            let key = (SocketAddrV4::new(self.addr, port), remote);
+            if !self.conns.contains_key(&key) && !self.listeners.contains_key(&port) {
+                return Ok(port);
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "no free local ports"))
+    }
+
+    /// Handles the packets that arrive within `wait`, then whatever the timers
+    /// call for.
+    fn poll(&mut self, wait: Duration) -> io::Result<()> {
+        let mut next = self.source.recv(wait)?;
+        while let Some(packet) = next {
+            self.receive(&packet)?;
+            next = self.source.recv(Duration::ZERO)?;
+        }
+
+        let now = Instant::now();
+        for tcb in self.conns.values_mut() {
+            tcb.on_timer(now);
+        }
+        self.transmit()?;
+        self.conns.retain(|_, tcb| !(tcb.orphaned && tcb.state == State::Closed));
+        Ok(())
+    }
+
+    fn receive(&mut self, packet: &[u8]) -> io::Result<()> {
+        // like a real network, drop anything corrupted or misdelivered
+        let seg = match Segment::decode(packet) {
+            Ok(seg) if *seg.dst.ip() == self.addr => seg,
+            _ => return Ok(()),
+        };
+        let now = Instant::now();
+        let key = (seg.dst, seg.src);
+
+        if let Some(tcb) = self.conns.get_mut(&key) {
+            if let Some(reply) = tcb.on_segment(&seg, now) {
+                self.source.send(&reply.encode())?;
+            }
+        } else if seg.has(SYN) && !seg.has(ACK) && self.listeners.contains_key(&seg.dst.port()) {
+            self.conns.insert(key, Tcb::accept(&seg, rand::random(), self.rto));
+            self.listeners.entry(seg.dst.port()).or_default().push_back(key);
+        } else if !seg.has(RST) {
+            self.source.send(&reset_for(&seg).encode())?;
+        }
+        Ok(())
+    }
+
+    fn transmit(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let segments: Vec<Segment> = self.conns.values_mut().flat_map(|tcb| tcb.transmit(now)).collect();
+        for seg in segments {
+            self.source.send(&seg.encode())?;
+        }
+        Ok(())
+    }
+}
+
+/// A connection made through an `Interface`, used like a `TcpStream`.
+///
+/// Dropping it closes the connection; the interface finishes the teardown
+/// the next time it is driven.
+pub struct UserTcpStream {
+    iface: Interface,
+    key: Key,
+    read_timeout: Cell<Option<Duration>>,
+    write_timeout: Cell<Option<Duration>>,
+}
+
+impl UserTcpStream {
+    fn new(iface: Interface, key: Key) -> Self {
+        UserTcpStream { iface, key, read_timeout: Cell::new(None), write_timeout: Cell::new(None) }
+    }
+
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.key.0
+    }
+
+    pub fn peer_addr(&self) -> SocketAddrV4 {
+        self.key.1
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.write_timeout.set(timeout);
+        Ok(())
+    }
+
+    /// Whether the connection is established with nothing waiting to be
+    /// read, i.e. could take a new request.
+    pub fn is_open(&self) -> bool {
+        let mut inner = self.iface.lock();
+        if inner.poll(Duration::ZERO).is_err() {
+            return false;
+        }
+        inner.conns.get(&self.key)
+            .is_some_and(|tcb| tcb.state == State::Established && tcb.readable() == 0)
+    }
+
+    fn with_tcb<T, F>(&self, timeout: Option<Duration>, mut f: F) -> io::Result<T>
+    where
+        F: FnMut(&mut Tcb) -> Option<io::Result<T>>,
+    {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        self.iface.wait(deadline, |inner| match inner.conns.get_mut(&self.key) {
+            None => Some(Err(io::ErrorKind::NotConnected.into())),
+            Some(tcb) => f(tcb),
+        })
+    }
+}
+
+impl Read for UserTcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.with_tcb(self.read_timeout.get(), |tcb| {
+            if tcb.readable() > 0 {
+                Some(Ok(tcb.read(buf)))
+            } else if let Some(kind) = tcb.error {
+                Some(Err(kind.into()))
+            } else if tcb.at_eof() {
+                Some(Ok(0))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl Write for UserTcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with_tcb(self.write_timeout.get(), |tcb| {
+            if let Some(kind) = tcb.error {
+                return Some(Err(kind.into()));
+            }
+            if !tcb.can_write() {
+                return Some(Err(io::ErrorKind::BrokenPipe.into()));
+            }
+            match tcb.write(buf) {
+                0 if !buf.is_empty() => None,
+                n => Some(Ok(n)),
+            }
+        })
+    }
+
+    /// Waits until the peer has acknowledged everything written.
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_tcb(self.write_timeout.get(), |tcb| match tcb.error {
+            Some(kind) => Some(Err(kind.into())),
+            None if tcb.unacknowledged() == 0 => Some(Ok(())),
+            None => None,
+        })
+    }
+}
+
+impl Drop for UserTcpStream {
+    fn drop(&mut self) {
+        let mut inner = self.iface.lock();
+        if let Some(tcb) = inner.conns.get_mut(&self.key) {
+            tcb.close();
+            tcb.orphaned = true;
+        }
+        let _ = inner.transmit();
+    }
+}
+
+impl fmt::Debug for UserTcpStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserTcpStream")
+            .field("local", &self.key.0)
+            .field("peer", &self.key.1)
+            .finish()
+    }
+}
+
+/// Accepts connections to a port of an `Interface`.
+pub struct UserTcpListener {
+    iface: Interface,
+    port: u16,
+}
+
+impl UserTcpListener {
+    /// Waits for a connection to finish its handshake.
+    pub fn accept(&self) -> io::Result<UserTcpStream> {
+        let key = self.iface.wait(None, |inner| {
+            let Inner { listeners, conns, .. } = inner;
+            let queue = listeners.get_mut(&self.port)?;
+            // connections reset during their handshake are never handed out
+            queue.retain(|key| conns.get(key).is_some_and(|tcb| tcb.state != State::Closed));
+            let ready = queue.iter().position(|key| conns[key].is_synchronized())?;
+            queue.remove(ready).map(Ok)
+        })?;
+        Ok(UserTcpStream::new(self.iface.clone(), key))
+    }
+}
+
+impl Drop for UserTcpListener {
+    fn drop(&mut self) {
+        let mut inner = self.iface.lock();
+        if let Some(queue) = inner.listeners.remove(&self.port) {
+            for key in queue {
+                if let Some(tcb) = inner.conns.get_mut(&key) {
+                    tcb.close();
+                    tcb.orphaned = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    /// Loses the packets at the given positions among those sent.
+    struct Lossy {
+        pipe: Pipe,
+        sent: usize,
+        lose: Vec<usize>,
+    }
+
+    impl PacketSource for Lossy {
+        fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+            self.sent += 1;
+            if self.lose.contains(&(self.sent - 1)) {
+                return Ok(());
+            }
+            self.pipe.send(packet)
+        }
+
+        fn recv(&mut self, timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+            self.pipe.recv(timeout)
+        }
+    }
+
+    fn link<P: PacketSource + 'static>(client: P, server: Pipe) -> (Interface, Interface) {
+        let client = Interface::new(client, Ipv4Addr::new(10, 0, 0, 1));
+        let server = Interface::new(server, Ipv4Addr::new(10, 0, 0, 2));
+        for iface in [&client, &server] {
+            iface.set_retransmission_timeout(Duration::from_millis(50));
+        }
+        (client, server)
+    }
+
+    fn echo_once(listener: UserTcpListener) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut conn = listener.accept().unwrap();
+            let mut received = vec![];
+            conn.read_to_end(&mut received).unwrap();
+            conn.write_all(&received).unwrap();
+            conn.flush().unwrap();
+            received
+        })
+    }
+
+    #[test]
+    fn test_transfer_and_teardown() {
+        let (a, b) = Pipe::pair();
+        let (client, server) = link(a, b);
+        let server_thread = echo_once(server.listen(7).unwrap());
+
+        let mut conn = client.connect("10.0.0.2:7".parse().unwrap(), Some(Duration::from_secs(5))).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        conn.write_all(&data).unwrap();
+        conn.flush().unwrap();
+
+        // half-close, so the server sees the end of the data
+        client.lock().conns.get_mut(&conn.key).unwrap().close();
+        let mut echoed = vec![];
+        conn.read_to_end(&mut echoed).unwrap();
+
+        assert_eq!(echoed, data);
+        assert_eq!(server_thread.join().unwrap(), data);
+        drop(conn);
+
+        // both ends close down and forget the connection
+        let deadline = Instant::now() + Duration::from_secs(5);
+        for iface in [&client, &server] {
+            iface.wait(Some(deadline), |inner| inner.conns.is_empty().then_some(Ok(()))).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_retransmits_lost_segments() {
+        let (a, b) = Pipe::pair();
+        // SYN, ACK, then the first data segment goes missing
+        let (client, server) = link(Lossy { pipe: a, sent: 0, lose: vec![0, 2] }, b);
+        let server_thread = echo_once(server.listen(7).unwrap());
+
+        let mut conn = client.connect("10.0.0.2:7".parse().unwrap(), Some(Duration::from_secs(5))).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        conn.write_all(b"hello").unwrap();
+        client.lock().conns.get_mut(&conn.key).unwrap().close();
+
+        let mut echoed = vec![];
+        conn.read_to_end(&mut echoed).unwrap();
+        assert_eq!(echoed, b"hello");
+        assert_eq!(server_thread.join().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_connection_refused() {
+        let (a, b) = Pipe::pair();
+        let (client, server) = link(a, b);
+        let server_thread = thread::spawn(move || {
+            // nothing listens, so the SYN is answered with a reset
+            let _ = server.wait(Some(Instant::now() + Duration::from_millis(500)), |_| None::<io::Result<()>>);
+        });
+
+        let err = client.connect("10.0.0.2:81".parse().unwrap(), Some(Duration::from_secs(5))).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        server_thread.join().unwrap();
+    }
+}
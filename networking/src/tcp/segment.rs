@@ -0,0 +1,189 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+const IP_HEADER_LEN: usize = 20;
+const TCP_HEADER_LEN: usize = 20;
+const PROTOCOL_TCP: u8 = 6;
+
+pub const FIN: u8 = 0x01;
+pub const SYN: u8 = 0x02;
+pub const RST: u8 = 0x04;
+pub const PSH: u8 = 0x08;
+pub const ACK: u8 = 0x10;
+
+/// A TCP segment together with the IPv4 addresses it travels between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub src: SocketAddrV4,
+    pub dst: SocketAddrV4,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub payload: Vec<u8>,
+}
+
+impl Segment {
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// How much sequence space the segment takes up: its payload, plus one
+    /// each for `SYN` and `FIN`.
+    pub fn len(&self) -> u32 {
+        self.payload.len() as u32 + self.has(SYN) as u32 + self.has(FIN) as u32
+    }
+
+    /// The segment as an IPv4 packet, without options.
+    pub fn encode(&self) -> Vec<u8> {
+        let total_len = IP_HEADER_LEN + TCP_HEADER_LEN + self.payload.len();
+        let mut packet = Vec::with_capacity(total_len);
+
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&(total_len as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0x40, 0]); // identification, don't fragment
+        packet.extend_from_slice(&[64, PROTOCOL_TCP, 0, 0]);
+        packet.extend_from_slice(&self.src.ip().octets());
+        packet.extend_from_slice(&self.dst.ip().octets());
+        let sum = checksum(&[&packet[..IP_HEADER_LEN]]);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+
+        packet.extend_from_slice(&self.src.port().to_be_bytes());
+        packet.extend_from_slice(&self.dst.port().to_be_bytes());
+        packet.extend_from_slice(&self.seq.to_be_bytes());
+        packet.extend_from_slice(&self.ack.to_be_bytes());
+        packet.extend_from_slice(&[(TCP_HEADER_LEN as u8 / 4) << 4, self.flags]);
+        packet.extend_from_slice(&self.window.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]); // checksum, urgent pointer
+        packet.extend_from_slice(&self.payload);
+        let sum = checksum(&[&pseudo_header(*self.src.ip(), *self.dst.ip(), TCP_HEADER_LEN + self.payload.len()), &packet[IP_HEADER_LEN..]]);
+        packet[IP_HEADER_LEN + 16..IP_HEADER_LEN + 18].copy_from_slice(&sum.to_be_bytes());
+
+        packet
+    }
+
+    /// Parses an IPv4 packet carrying TCP, checking both checksums.
+    pub fn decode(packet: &[u8]) -> io::Result<Segment> {
+        if packet.len() < IP_HEADER_LEN || packet[0] >> 4 != 4 {
+            return Err(invalid("not an IPv4 packet"));
+        }
+        let header_len = (packet[0] & 0x0f) as usize * 4;
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        if header_len < IP_HEADER_LEN || total_len < header_len || total_len > packet.len() {
+            return Err(invalid("malformed IPv4 header"));
+        }
+        if packet[9] != PROTOCOL_TCP {
+            return Err(invalid("not a TCP packet"));
+        }
+        if checksum(&[&packet[..header_len]]) != 0 {
+            return Err(invalid("bad IPv4 header checksum"));
+        }
+        let src_ip = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+        let dst_ip = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+
+        let tcp = &packet[header_len..total_len];
+        if tcp.len() < TCP_HEADER_LEN {
+            return Err(invalid("truncated TCP header"));
+        }
+        let data_offset = (tcp[12] >> 4) as usize * 4;
+        if data_offset < TCP_HEADER_LEN || data_offset > tcp.len() {
+            return Err(invalid("malformed TCP header"));
+        }
+        if checksum(&[&pseudo_header(src_ip, dst_ip, tcp.len()), tcp]) != 0 {
+            return Err(invalid("bad TCP checksum"));
+        }
+
+        let word = |at: usize| u32::from_be_bytes([tcp[at], tcp[at + 1], tcp[at + 2], tcp[at + 3]]);
+        let half = |at: usize| u16::from_be_bytes([tcp[at], tcp[at + 1]]);
+        Ok(Segment {
+            src: SocketAddrV4::new(src_ip, half(0)),
+            dst: SocketAddrV4::new(dst_ip, half(2)),
+            seq: word(4),
+            ack: word(8),
+            flags: tcp[13],
+            window: half(14),
+            payload: tcp[data_offset..].to_vec(),
+        })
+    }
+}
+
+fn pseudo_header(src: Ipv4Addr, dst: Ipv4Addr, tcp_len: usize) -> [u8; 12] {
+    let mut header = [0; 12];
+    header[..4].copy_from_slice(&src.octets());
+    header[4..8].copy_from_slice(&dst.octets());
+    header[9] = PROTOCOL_TCP;
+    header[10..].copy_from_slice(&(tcp_len as u16).to_be_bytes());
+    header
+}
+
+/// The Internet checksum (RFC 1071) of the concatenated `chunks`, each of
+/// which but the last must have an even length. Data with a correct checksum
+/// in it sums to zero.
+pub fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in chunks {
+        for pair in chunk.chunks(2) {
+            let word = match pair {
+                [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+                [hi] => u16::from_be_bytes([*hi, 0]),
+                _ => 0,
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let segment = Segment {
+            src: "10.0.0.1:49152".parse().unwrap(),
+            dst: "10.0.0.2:80".parse().unwrap(),
+            seq: 0xdead_beef,
+            ack: 42,
+            flags: ACK | PSH,
+            window: 8192,
+            payload: b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+        };
+
+        let packet = segment.encode();
+        assert_eq!(packet.len(), 40 + segment.payload.len());
+        assert_eq!(Segment::decode(&packet).unwrap(), segment);
+        assert_eq!(segment.len(), segment.payload.len() as u32);
+    }
+
+    #[test]
+    fn test_corruption() {
+        let segment = Segment {
+            src: "10.0.0.1:1".parse().unwrap(),
+            dst: "10.0.0.2:2".parse().unwrap(),
+            seq: 1,
+            ack: 0,
+            flags: SYN,
+            window: 1,
+            payload: vec![1, 2, 3],
+        };
+        let mut packet = segment.encode();
+        packet[41] ^= 0xff;
+
+        assert_eq!(Segment::decode(&packet).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(Segment::decode(&packet[..30]).is_err());
+    }
+
+    #[test]
+    fn test_checksum() {
+        // the example from RFC 1071
+        assert_eq!(checksum(&[&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]]), !0xddf2);
+    }
+}
@@ -0,0 +1,540 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddrV4;
+use std::time::{Duration, Instant};
+
+use super::segment::{Segment, ACK, FIN, PSH, RST, SYN};
+
+/// The most payload put in one segment, which keeps packets within an
+/// Ethernet MTU.
+pub const MSS: usize = 1460;
+/// How much unread data is buffered before the window closes.
+const RECV_CAPACITY: usize = 64 * 1024;
+/// How much unacknowledged data writers may queue.
+const SEND_CAPACITY: usize = 64 * 1024;
+/// How often a segment is sent again before the connection is given up on.
+const MAX_RETRIES: u32 = 8;
+const MAX_RTO: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// The state of one connection (a transmission control block, in RFC 793's
+/// words). It does no I/O: segments are fed in with `on_segment`, and the
+/// ones to send are taken out with `transmit`.
+///
+/// Out-of-order segments are dropped rather than buffered, and a timeout
+/// resends everything that hasn't been acknowledged (go-back-N), doubling
+/// the timeout each time.
+#[derive(Debug)]
+pub struct Tcb {
+    pub local: SocketAddrV4,
+    pub remote: SocketAddrV4,
+    pub state: State,
+    /// Why the connection ended, if it didn't end normally.
+    pub error: Option<io::ErrorKind>,
+    /// Set once nobody holds a handle to the connection any more.
+    pub orphaned: bool,
+
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    /// Data not yet acknowledged, starting at sequence number `buf_seq`.
+    send_buf: VecDeque<u8>,
+    buf_seq: u32,
+    fin_queued: bool,
+    fin_seq: Option<u32>,
+
+    rcv_nxt: u32,
+    recv_buf: VecDeque<u8>,
+    peer_closed: bool,
+    ack_pending: bool,
+    advertised: usize,
+
+    initial_rto: Duration,
+    rto: Duration,
+    timer: Option<Instant>,
+    retries: u32,
+    time_wait_until: Option<Instant>,
+}
+
+impl Tcb {
+    /// Starts an active open; the `SYN` goes out with the next `transmit`.
+    pub fn connect(local: SocketAddrV4, remote: SocketAddrV4, iss: u32, rto: Duration) -> Self {
+        Tcb::new(local, remote, State::SynSent, iss, 0, rto)
+    }
+
+    /// Answers a `SYN` received on a listening port.
+    pub fn accept(syn: &Segment, iss: u32, rto: Duration) -> Self {
+        let mut tcb = Tcb::new(syn.dst, syn.src, State::SynReceived, iss, syn.seq.wrapping_add(1), rto);
+        tcb.snd_wnd = syn.window as u32;
+        tcb
+    }
+
+    fn new(local: SocketAddrV4, remote: SocketAddrV4, state: State, iss: u32, rcv_nxt: u32, rto: Duration) -> Self {
+        Tcb {
+            local,
+            remote,
+            state,
+            error: None,
+            orphaned: false,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: MSS as u32,
+            send_buf: VecDeque::new(),
+            buf_seq: iss.wrapping_add(1),
+            fin_queued: false,
+            fin_seq: None,
+            rcv_nxt,
+            recv_buf: VecDeque::new(),
+            peer_closed: false,
+            ack_pending: false,
+            advertised: RECV_CAPACITY,
+            initial_rto: rto,
+            rto,
+            timer: None,
+            retries: 0,
+            time_wait_until: None,
+        }
+    }
+
+    /// Whether the handshake is over, however the connection went since.
+    pub fn is_synchronized(&self) -> bool {
+        !matches!(self.state, State::SynSent | State::SynReceived)
+    }
+
+    /// Whether the peer will send no more data, or can't.
+    pub fn at_eof(&self) -> bool {
+        self.peer_closed || self.state == State::Closed
+    }
+
+    pub fn can_write(&self) -> bool {
+        matches!(self.state, State::Established | State::CloseWait) && !self.fin_queued
+    }
+
+    pub fn readable(&self) -> usize {
+        self.recv_buf.len()
+    }
+
+    /// Bytes written but not yet acknowledged by the peer.
+    pub fn unacknowledged(&self) -> usize {
+        self.send_buf.len()
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.recv_buf.len());
+        for (dst, src) in buf.iter_mut().zip(self.recv_buf.drain(..n)) {
+            *dst = src;
+        }
+        // tell a peer that was held up that the window has opened again
+        if self.advertised < RECV_CAPACITY / 2 && self.window() >= RECV_CAPACITY / 2 {
+            self.ack_pending = true;
+        }
+        n
+    }
+
+    /// Queues as much of `data` as fits in the send buffer.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(SEND_CAPACITY - self.send_buf.len());
+        self.send_buf.extend(&data[..n]);
+        n
+    }
+
+    /// Sends a `FIN` once everything written so far has been sent.
+    pub fn close(&mut self) {
+        match self.state {
+            State::SynSent => self.state = State::Closed,
+            _ => self.fin_queued = true,
+        }
+    }
+
+    /// When `on_timer` next has something to do.
+    pub fn next_timer(&self) -> Option<Instant> {
+        match (self.timer, self.time_wait_until) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn on_timer(&mut self, now: Instant) {
+        if self.time_wait_until.is_some_and(|until| now >= until) {
+            self.state = State::Closed;
+            self.time_wait_until = None;
+        }
+        if self.timer.is_none_or(|at| now < at) {
+            return;
+        }
+        self.timer = None;
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.state = State::Closed;
+            self.error = Some(io::ErrorKind::TimedOut);
+            return;
+        }
+        self.rto = cmp::min(self.rto * 2, MAX_RTO);
+        self.snd_nxt = self.snd_una;
+    }
+
+    pub fn on_segment(&mut self, seg: &Segment, now: Instant) -> Option<Segment> {
+        if seg.has(RST) {
+            let acceptable = match self.state {
+                State::SynSent => seg.has(ACK) && seg.ack == self.iss.wrapping_add(1),
+                _ => seg.seq == self.rcv_nxt || in_window(seg.seq, self.rcv_nxt, self.window() as u32),
+            };
+            if acceptable {
+                self.error = Some(match self.state {
+                    State::SynSent => io::ErrorKind::ConnectionRefused,
+                    _ => io::ErrorKind::ConnectionReset,
+                });
+                self.state = State::Closed;
+                self.timer = None;
+            }
+            return None;
+        }
+
+        match self.state {
+            State::Closed => return None,
+            State::SynSent => {
+                if seg.has(ACK) && seg.ack != self.iss.wrapping_add(1) {
+                    return Some(reset_for(seg));
+                }
+                if seg.has(SYN) && seg.has(ACK) {
+                    self.rcv_nxt = seg.seq.wrapping_add(1);
+                    self.snd_una = seg.ack;
+                    self.snd_wnd = seg.window as u32;
+                    self.state = State::Established;
+                    self.acknowledged();
+                    self.ack_pending = true;
+                }
+                return None;
+            },
+            _ => {},
+        }
+
+        if seg.has(SYN) {
+            if self.state == State::SynReceived && seg.seq.wrapping_add(1) == self.rcv_nxt {
+                // our SYN-ACK was lost; send it again
+                self.snd_nxt = self.snd_una;
+            } else {
+                self.ack_pending = true;
+            }
+            return None;
+        }
+        if !seg.has(ACK) {
+            return None;
+        }
+
+        if after(seg.ack, self.snd_una) && !after(seg.ack, self.snd_nxt) {
+            if self.state == State::SynReceived {
+                self.state = State::Established;
+            }
+            let acked = (seg.ack.wrapping_sub(self.buf_seq) as i32).clamp(0, self.send_buf.len() as i32) as usize;
+            self.send_buf.drain(..acked);
+            self.buf_seq = self.buf_seq.wrapping_add(acked as u32);
+            self.snd_una = seg.ack;
+            self.acknowledged();
+            if self.snd_una != self.snd_nxt {
+                self.timer = Some(now + self.rto);
+            }
+            if self.fin_seq.is_some_and(|fin| after(seg.ack, fin)) {
+                self.state = match self.state {
+                    State::FinWait1 => State::FinWait2,
+                    State::Closing => self.enter_time_wait(now),
+                    State::LastAck => State::Closed,
+                    state => state,
+                };
+            }
+        } else if after(seg.ack, self.snd_nxt) {
+            // acknowledges something we never sent
+            self.ack_pending = true;
+            return None;
+        }
+        if self.state == State::SynReceived {
+            return None;
+        }
+        self.snd_wnd = seg.window as u32;
+
+        self.receive(seg, now);
+        None
+    }
+
+    fn receive(&mut self, seg: &Segment, now: Instant) {
+        let receiving = matches!(self.state, State::Established | State::FinWait1 | State::FinWait2);
+        let end = seg.seq.wrapping_add(seg.payload.len() as u32);
+        if receiving && !seg.payload.is_empty() && !after(seg.seq, self.rcv_nxt) && after(end, self.rcv_nxt) {
+            // skip whatever part of a retransmission we already have
+            let skip = self.rcv_nxt.wrapping_sub(seg.seq) as usize;
+            let take = (seg.payload.len() - skip).min(self.window());
+            self.recv_buf.extend(&seg.payload[skip..skip + take]);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(take as u32);
+            self.ack_pending = true;
+        } else if !seg.payload.is_empty() || seg.has(FIN) {
+            // out of order, a duplicate, or beyond the window
+            self.ack_pending = true;
+        }
+
+        if seg.has(FIN) && receiving && end == self.rcv_nxt {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.peer_closed = true;
+            self.ack_pending = true;
+            self.state = match self.state {
+                State::Established => State::CloseWait,
+                State::FinWait1 => State::Closing,
+                _ => self.enter_time_wait(now),
+            };
+        }
+    }
+
+    /// The segments to send now.
+    pub fn transmit(&mut self, now: Instant) -> Vec<Segment> {
+        let mut out = vec![];
+        match self.state {
+            State::SynSent if self.snd_nxt == self.iss => {
+                out.push(self.segment(self.iss, SYN, vec![]));
+                self.snd_nxt = self.iss.wrapping_add(1);
+            },
+            State::SynReceived if self.snd_nxt == self.iss => {
+                out.push(self.segment(self.iss, SYN | ACK, vec![]));
+                self.snd_nxt = self.iss.wrapping_add(1);
+            },
+            State::Established | State::CloseWait | State::FinWait1 | State::Closing | State::LastAck => {
+                self.transmit_data(&mut out);
+            },
+            _ => {},
+        }
+
+        if self.ack_pending && out.is_empty() && !matches!(self.state, State::SynSent | State::Closed) {
+            out.push(self.segment(self.snd_nxt, ACK, vec![]));
+        }
+        self.ack_pending = false;
+        if self.snd_nxt != self.snd_una && self.timer.is_none() {
+            self.timer = Some(now + self.rto);
+        }
+        out
+    }
+
+    fn transmit_data(&mut self, out: &mut Vec<Segment>) {
+        loop {
+            let sent = self.snd_nxt.wrapping_sub(self.buf_seq) as usize;
+            if sent >= self.send_buf.len() {
+                break;
+            }
+            let usable = self.snd_una.wrapping_add(self.snd_wnd).wrapping_sub(self.snd_nxt) as i32;
+            if usable <= 0 {
+                break;
+            }
+            let n = MSS.min(self.send_buf.len() - sent).min(usable as usize);
+            let payload = self.send_buf.range(sent..sent + n).copied().collect();
+            out.push(self.segment(self.snd_nxt, ACK | PSH, payload));
+            self.snd_nxt = self.snd_nxt.wrapping_add(n as u32);
+        }
+
+        let all_sent = self.snd_nxt == self.buf_seq.wrapping_add(self.send_buf.len() as u32);
+        if self.fin_queued && all_sent && self.fin_seq.is_none_or(|fin| fin == self.snd_nxt) {
+            if self.fin_seq.is_none() {
+                self.fin_seq = Some(self.snd_nxt);
+                self.state = match self.state {
+                    State::Established => State::FinWait1,
+                    State::CloseWait => State::LastAck,
+                    state => state,
+                };
+            }
+            out.push(self.segment(self.snd_nxt, FIN | ACK, vec![]));
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+        }
+    }
+
+    fn segment(&mut self, seq: u32, flags: u8, payload: Vec<u8>) -> Segment {
+        self.advertised = self.window();
+        Segment {
+            src: self.local,
+            dst: self.remote,
+            seq,
+            ack: if flags & ACK != 0 { self.rcv_nxt } else { 0 },
+            flags,
+            window: self.advertised.min(u16::MAX as usize) as u16,
+            payload,
+        }
+    }
+
+    fn window(&self) -> usize {
+        RECV_CAPACITY - self.recv_buf.len()
+    }
+
+    fn acknowledged(&mut self) {
+        self.timer = None;
+        self.retries = 0;
+        self.rto = self.initial_rto;
+    }
+
+    fn enter_time_wait(&mut self, now: Instant) -> State {
+        // long enough to answer a retransmitted FIN
+        self.time_wait_until = Some(now + self.initial_rto * 4);
+        self.timer = None;
+        State::TimeWait
+    }
+}
+
+/// The reset sent in answer to a segment for a connection that doesn't exist.
+pub fn reset_for(seg: &Segment) -> Segment {
+    let (seq, ack, flags) = if seg.has(ACK) {
+        (seg.ack, 0, RST)
+    } else {
+        (0, seg.seq.wrapping_add(seg.len()), RST | ACK)
+    };
+    Segment { src: seg.dst, dst: seg.src, seq, ack, flags, window: 0, payload: vec![] }
+}
+
+/// Compares sequence numbers, which wrap around.
+fn after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+fn in_window(seq: u32, start: u32, len: u32) -> bool {
+    seq.wrapping_sub(start) < len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RTO: Duration = Duration::from_millis(100);
+
+    /// Hands every segment each side sends to the other, until both go quiet.
+    fn exchange(a: &mut Tcb, b: &mut Tcb, now: Instant) {
+        loop {
+            let (to_b, to_a) = (a.transmit(now), b.transmit(now));
+            if to_a.is_empty() && to_b.is_empty() {
+                return;
+            }
+            for seg in to_b {
+                assert!(b.on_segment(&seg, now).is_none());
+            }
+            for seg in to_a {
+                assert!(a.on_segment(&seg, now).is_none());
+            }
+        }
+    }
+
+    fn handshake(now: Instant) -> (Tcb, Tcb) {
+        let mut client = Tcb::connect("10.0.0.1:50000".parse().unwrap(), "10.0.0.2:80".parse().unwrap(), u32::MAX - 10, RTO);
+        let syn = client.transmit(now).remove(0);
+        assert_eq!(syn.flags, SYN);
+
+        let mut server = Tcb::accept(&syn, 7, RTO);
+        exchange(&mut client, &mut server, now);
+        (client, server)
+    }
+
+    #[test]
+    fn test_handshake_and_teardown() {
+        let now = Instant::now();
+        let (mut client, mut server) = handshake(now);
+        assert_eq!((client.state, server.state), (State::Established, State::Established));
+
+        // sequence numbers wrap around during the transfer
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        assert_eq!(client.write(&data), data.len());
+        exchange(&mut client, &mut server, now);
+        let mut received = vec![0; 6000];
+        assert_eq!(server.read(&mut received), data.len());
+        assert_eq!(&received[..data.len()], &data[..]);
+        assert_eq!(client.unacknowledged(), 0);
+
+        client.close();
+        exchange(&mut client, &mut server, now);
+        assert_eq!((client.state, server.state), (State::FinWait2, State::CloseWait));
+        assert!(server.at_eof());
+
+        server.close();
+        exchange(&mut client, &mut server, now);
+        assert_eq!((client.state, server.state), (State::TimeWait, State::Closed));
+
+        client.on_timer(now + RTO * 4);
+        assert_eq!(client.state, State::Closed);
+        assert_eq!(client.error, None);
+    }
+
+    #[test]
+    fn test_retransmission() {
+        let now = Instant::now();
+        let (mut client, mut server) = handshake(now);
+
+        client.write(b"hello");
+        let lost = client.transmit(now);
+        assert_eq!(lost.len(), 1);
+
+        // nothing to send until the timer goes off
+        assert!(client.transmit(now + RTO / 2).is_empty());
+        client.on_timer(now + RTO);
+        let again = client.transmit(now + RTO);
+        assert_eq!(again[0].payload, b"hello");
+        assert_eq!(again[0].seq, lost[0].seq);
+
+        for seg in again {
+            server.on_segment(&seg, now);
+        }
+        exchange(&mut client, &mut server, now);
+        assert_eq!(server.readable(), 5);
+        assert_eq!(client.next_timer(), None);
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let now = Instant::now();
+        let mut client = Tcb::connect("10.0.0.1:50000".parse().unwrap(), "10.0.0.2:80".parse().unwrap(), 1, RTO);
+        let mut at = now;
+        while client.state != State::Closed {
+            client.transmit(at);
+            at = client.next_timer().unwrap();
+            client.on_timer(at);
+        }
+        assert_eq!(client.error, Some(io::ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let now = Instant::now();
+        let (mut client, mut server) = handshake(now);
+
+        client.write(&[1; MSS]);
+        client.write(&[2; MSS]);
+        let segments = client.transmit(now);
+        assert_eq!(segments.len(), 2);
+
+        // the second segment arrives first and is dropped
+        server.on_segment(&segments[1], now);
+        assert_eq!(server.readable(), 0);
+        let dup_ack = server.transmit(now);
+        assert_eq!(dup_ack[0].ack, segments[0].seq);
+
+        server.on_segment(&segments[0], now);
+        assert_eq!(server.readable(), MSS);
+    }
+
+    #[test]
+    fn test_reset() {
+        let now = Instant::now();
+        let mut client = Tcb::connect("10.0.0.1:50000".parse().unwrap(), "10.0.0.2:81".parse().unwrap(), 1, RTO);
+        let syn = client.transmit(now).remove(0);
+
+        client.on_segment(&reset_for(&syn), now);
+
+        assert_eq!(client.state, State::Closed);
+        assert_eq!(client.error, Some(io::ErrorKind::ConnectionRefused));
+    }
+}
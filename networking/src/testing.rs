@@ -0,0 +1,344 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::Url;
+
+/// A scripted HTTP server for tests, answering requests with canned responses.
+///
+/// Unlike `server::Server`, it replays raw bytes, so tests can send responses
+/// a real server never would, and it records what clients sent.
+pub struct StandIn {
+    pub port: u16,
+    requests: Arc<Mutex<Vec<String>>>,
+    server_names: Arc<Mutex<Vec<String>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl StandIn {
+    /// Serves `responses` in order, one per request, over as many connections
+    /// as clients care to open.
+    pub fn serve(responses: Vec<Vec<u8>>) -> StandIn {
+        StandIn::start(Script::Queue(VecDeque::from(responses)), None)
+    }
+
+    /// Answers every request for a path with the same response, and requests
+    /// for any other path with `404 Not Found`.
+    pub fn route(routes: Vec<(&str, Vec<u8>)>) -> StandIn {
+        let routes = routes.into_iter().map(|(path, response)| (path.to_string(), response)).collect();
+        StandIn::start(Script::Routes(routes), None)
+    }
+
+    /// Like `serve`, but over TLS with a certificate for `localhost` signed by
+    /// the returned PEM-encoded certificate authority.
+    pub fn serve_tls(responses: Vec<Vec<u8>>) -> (StandIn, String) {
+        let key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let private_key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.key_pair.serialize_der()));
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![key.cert.der().clone()], private_key)
+            .unwrap();
+        (StandIn::start(Script::Queue(VecDeque::from(responses)), Some(Arc::new(config))), key.cert.pem())
+    }
+
+    fn start(script: Script, tls: Option<Arc<ServerConfig>>) -> StandIn {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let responses = Arc::new(Mutex::new(script));
+        let requests = Arc::new(Mutex::new(vec![]));
+        let server_names = Arc::new(Mutex::new(vec![]));
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let (log, names, accepted) = (requests.clone(), server_names.clone(), connections.clone());
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let conn = conn.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let (responses, log, names, tls) = (responses.clone(), log.clone(), names.clone(), tls.clone());
+                thread::spawn(move || match tls {
+                    None => handle(conn, responses, log),
+                    Some(config) => {
+                        let mut session = ServerConnection::new(config).unwrap();
+                        let mut conn = conn;
+                        if session.complete_io(&mut conn).is_err() {
+                            return;
+                        }
+                        names.lock().unwrap().push(session.server_name().unwrap_or_default().to_string());
+                        handle(StreamOwned::new(session, conn), responses, log)
+                    },
+                });
+            }
+        });
+
+        StandIn { port, requests, server_names, connections }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+
+    /// The heads of the requests received so far, in order, each followed by
+    /// an empty line and the body if there was one.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// How many connections clients have opened.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// The SNI server names sent by TLS clients.
+    pub fn server_names(&self) -> Vec<String> {
+        self.server_names.lock().unwrap().clone()
+    }
+}
+
+enum Script {
+    Queue(VecDeque<Vec<u8>>),
+    Routes(HashMap<String, Vec<u8>>),
+}
+
+impl Script {
+    fn answer(&mut self, head: &str) -> Option<Vec<u8>> {
+        match self {
+            Script::Queue(responses) => responses.pop_front(),
+            Script::Routes(routes) => {
+                let path = head.split(' ').nth(1).unwrap_or_default();
+                Some(routes.get(path).cloned().unwrap_or_else(|| response("404 Not Found", &[], "")))
+            },
+        }
+    }
+}
+
+fn handle<S: Read + Write>(conn: S, responses: Arc<Mutex<Script>>, log: Arc<Mutex<Vec<String>>>) {
+    let mut conn = BufReader::new(conn);
+    loop {
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            if conn.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let length = head.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        if conn.read_exact(&mut body).is_err() {
+            return;
+        }
+
+        let response = responses.lock().unwrap().answer(&head);
+        if !body.is_empty() {
+            head.push_str("\r\n");
+            head.push_str(&String::from_utf8_lossy(&body));
+        }
+        log.lock().unwrap().push(head);
+
+        let response = match response {
+            None => return,
+            Some(response) => response,
+        };
+        if conn.get_mut().write_all(&response).and_then(|_| conn.get_mut().flush()).is_err() {
+            return;
+        }
+    }
+}
+
+/// A forwarding proxy for tests, logging what clients ask it for.
+pub struct ProxyStandIn {
+    pub port: u16,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl ProxyStandIn {
+    /// An HTTP proxy that opens `CONNECT` tunnels and forwards requests for
+    /// absolute URLs. Logs the request heads.
+    pub fn http() -> ProxyStandIn {
+        ProxyStandIn::start(http_proxy)
+    }
+
+    /// A SOCKS5 proxy, requiring `credentials` if given. Logs `auth USER:PASSWORD`
+    /// and `CONNECT HOST:PORT` lines.
+    pub fn socks5(credentials: Option<(&str, &str)>) -> ProxyStandIn {
+        let credentials = credentials.map(|(user, password)| format!("{}:{}", user, password));
+        ProxyStandIn::start(move |conn, log| socks5_proxy(conn, log, credentials.as_deref()))
+    }
+
+    fn start<F>(serve: F) -> ProxyStandIn
+    where
+        F: Fn(TcpStream, &Mutex<Vec<String>>) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let log = Arc::new(Mutex::new(vec![]));
+        let serve = Arc::new(serve);
+
+        let proxy_log = log.clone();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let (conn, log, serve) = (conn.unwrap(), proxy_log.clone(), serve.clone());
+                thread::spawn(move || serve(conn, &log));
+            }
+        });
+        ProxyStandIn { port, log }
+    }
+
+    pub fn log(&self) -> Vec<String> {
+        self.log.lock().unwrap().clone()
+    }
+}
+
+fn http_proxy(conn: TcpStream, log: &Mutex<Vec<String>>) -> io::Result<()> {
+    let mut client = BufReader::new(conn.try_clone()?);
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if client.read_line(&mut line)? == 0 || line == "\r\n" {
+            break;
+        }
+        head.push_str(&line);
+    }
+    log.lock().unwrap().push(head.clone());
+
+    let mut request_line = head.split(' ');
+    let (method, target) = (request_line.next().unwrap_or_default(), request_line.next().unwrap_or_default());
+    let mut upstream = if method == "CONNECT" {
+        let upstream = TcpStream::connect(target)?;
+        (&conn).write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+        upstream
+    } else {
+        let url = Url::parse(target).map_err(io::Error::other)?;
+        let mut upstream = TcpStream::connect((url.host.as_str(), url.port))?;
+        upstream.write_all(format!("{}\r\n", head).as_bytes())?;
+        upstream
+    };
+    upstream.write_all(client.buffer())?;
+    relay(conn, upstream)
+}
+
+fn socks5_proxy(mut conn: TcpStream, log: &Mutex<Vec<String>>, credentials: Option<&str>) -> io::Result<()> {
+    let mut greeting = [0; 2];
+    conn.read_exact(&mut greeting)?;
+    let mut methods = vec![0; greeting[1] as usize];
+    conn.read_exact(&mut methods)?;
+
+    match credentials {
+        Some(expected) if methods.contains(&2) => {
+            conn.write_all(&[5, 2])?;
+            let mut version_and_len = [0; 2];
+            conn.read_exact(&mut version_and_len)?;
+            let mut user = vec![0; version_and_len[1] as usize];
+            conn.read_exact(&mut user)?;
+            let mut len = [0];
+            conn.read_exact(&mut len)?;
+            let mut password = vec![0; len[0] as usize];
+            conn.read_exact(&mut password)?;
+
+            let given = format!("{}:{}", String::from_utf8_lossy(&user), String::from_utf8_lossy(&password));
+            log.lock().unwrap().push(format!("auth {}", given));
+            if given != expected {
+                return conn.write_all(&[1, 1]);
+            }
+            conn.write_all(&[1, 0])?;
+        },
+        None if methods.contains(&0) => conn.write_all(&[5, 0])?,
+        _ => return conn.write_all(&[5, 0xff]),
+    }
+
+    let mut request = [0; 4];
+    conn.read_exact(&mut request)?;
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0; 4];
+            conn.read_exact(&mut ip)?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        },
+        3 => {
+            let mut len = [0];
+            conn.read_exact(&mut len)?;
+            let mut name = vec![0; len[0] as usize];
+            conn.read_exact(&mut name)?;
+            String::from_utf8_lossy(&name).into_owned()
+        },
+        _ => return conn.write_all(&[5, 8, 0, 1, 0, 0, 0, 0, 0, 0]),
+    };
+    let mut port = [0; 2];
+    conn.read_exact(&mut port)?;
+    let port = u16::from_be_bytes(port);
+    log.lock().unwrap().push(format!("CONNECT {}:{}", host, port));
+
+    match TcpStream::connect((host.as_str(), port)) {
+        Err(_) => conn.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]),
+        Ok(upstream) => {
+            conn.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+            relay(conn, upstream)
+        },
+    }
+}
+
+/// Copies bytes both ways until either side is done.
+fn relay(client: TcpStream, upstream: TcpStream) -> io::Result<()> {
+    let (mut client_in, mut upstream_out) = (client.try_clone()?, upstream.try_clone()?);
+    let forward = thread::spawn(move || {
+        let _ = io::copy(&mut client_in, &mut upstream_out);
+        let _ = upstream_out.shutdown(Shutdown::Write);
+    });
+    let (mut upstream_in, mut client_out) = (upstream, client);
+    let _ = io::copy(&mut upstream_in, &mut client_out);
+    let _ = client_out.shutdown(Shutdown::Write);
+    let _ = forward.join();
+    Ok(())
+}
+
+/// A response with a body and an explicit length, so connections can be reused.
+pub fn response(status: &str, headers: &[(&str, &str)], body: &str) -> Vec<u8> {
+    let mut raw = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n", status, body.len());
+    for (name, value) in headers {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw.push_str("\r\n");
+    raw.push_str(body);
+    raw.into_bytes()
+}
+
+/// A scratch directory, removed again when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("libnet-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::SeqCst));
+        let dir = std::env::temp_dir().join(name);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::Stream;
+
+pub type TlsStream = StreamOwned<ClientConnection, Stream>;
+
+/// Starts TLS sessions that verify the server's certificate against a set of
+/// trusted roots and its host name.
+#[derive(Clone)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+}
+
+impl TlsConnector {
+    /// Trusts the Mozilla root certificates bundled with `webpki-roots`.
+    pub fn new() -> Self {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        TlsConnector::with_roots(roots).expect("default TLS configuration")
+    }
+
+    /// A connector shared by every reader that wasn't given its own.
+    pub fn shared() -> &'static TlsConnector {
+        static SHARED: OnceLock<TlsConnector> = OnceLock::new();
+        SHARED.get_or_init(TlsConnector::new)
+    }
+
+    /// Trusts only the certificates in a PEM bundle file.
+    pub fn with_ca_bundle(path: &Path) -> io::Result<Self> {
+        let mut pem = BufReader::new(File::open(path)?);
+        TlsConnector::from_pem(&mut pem)
+    }
+
+    /// Trusts only the certificates in PEM-encoded `bundle`.
+    pub fn with_ca_pem(bundle: &[u8]) -> io::Result<Self> {
+        TlsConnector::from_pem(&mut io::Cursor::new(bundle))
+    }
+
+    fn from_pem(pem: &mut dyn io::BufRead) -> io::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(pem) {
+            roots.add(cert?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        }
+        if roots.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no certificates in CA bundle"));
+        }
+        TlsConnector::with_roots(roots)
+    }
+
+    fn with_roots(roots: RootCertStore) -> io::Result<Self> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(TlsConnector { config: Arc::new(config) })
+    }
+
+    /// Wraps `stream` in a TLS session for `host`, which is sent as the SNI
+    /// server name and checked against the certificate.
+    ///
+    /// The handshake itself happens on the first read or write.
+    pub fn connect(&self, host: &str, stream: Stream) -> io::Result<TlsStream> {
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let conn = ClientConnection::new(self.config.clone(), name).map_err(io::Error::other)?;
+        Ok(StreamOwned::new(conn, stream))
+    }
+}
+
+impl Default for TlsConnector {
+    fn default() -> Self {
+        TlsConnector::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{response, StandIn};
+    use crate::{PageReader, TCPReader};
+
+    #[test]
+    fn test_custom_ca_bundle() {
+        let (server, ca) = StandIn::serve_tls(vec![response("200 OK", &[], "secure")]);
+        let mut reader = TCPReader::new();
+        reader.tls = Some(TlsConnector::with_ca_pem(ca.as_bytes()).unwrap());
+
+        let page = reader.read_page(&format!("https://localhost:{}/private", server.port)).unwrap();
+
+        assert_eq!(page.text(), "secure");
+        assert_eq!(server.server_names(), ["localhost"]);
+        assert!(server.requests()[0].starts_with("GET /private HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_rejects_untrusted_certificate() {
+        let (server, _) = StandIn::serve_tls(vec![response("200 OK", &[], "secure")]);
+
+        let result = TCPReader::new().read_page(&format!("https://localhost:{}/", server.port));
+
+        assert!(result.is_err());
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn test_rejects_wrong_host_name() {
+        let (server, ca) = StandIn::serve_tls(vec![response("200 OK", &[], "secure")]);
+        let mut reader = TCPReader::new();
+        reader.tls = Some(TlsConnector::with_ca_pem(ca.as_bytes()).unwrap());
+
+        // the certificate is only valid for "localhost"
+        let result = reader.read_page(&format!("https://127.0.0.1:{}/", server.port));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_empty_bundle() {
+        assert!(TlsConnector::with_ca_pem(b"not a certificate").is_err());
+    }
+}
@@ -0,0 +1,342 @@
+//! Transcripts of the bytes that go over connections, for when a server and
+//! `TCPReader` don't agree on what was said.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::tcp::segment::{Segment, ACK, FIN, PSH, SYN};
+use crate::Stream;
+
+/// `LINKTYPE_RAW`: each packet is a bare IPv4 or IPv6 packet.
+const LINKTYPE_RAW: u32 = 101;
+
+/// The most payload put in one made-up TCP segment.
+const SEGMENT_SIZE: usize = 1460;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// Timestamped hex and ASCII dumps of every read and write.
+    Hexdump,
+    /// A capture file for Wireshark or tcpdump. What the connection carried,
+    /// after TLS, is written as the segments of a made-up TCP connection
+    /// between the same addresses; IPv6 addresses show up as `0.0.0.0`.
+    Pcap,
+}
+
+impl TraceFormat {
+    /// `Pcap` for `.pcap` and `.cap` files, `Hexdump` for anything else.
+    pub fn for_path(path: &Path) -> TraceFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("pcap") || ext.eq_ignore_ascii_case("cap") => TraceFormat::Pcap,
+            _ => TraceFormat::Hexdump,
+        }
+    }
+}
+
+/// Where traced connections record what they send and receive. One trace
+/// can be shared by any number of connections, which are numbered in the
+/// order they were opened.
+pub struct Trace {
+    format: TraceFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+    start: Instant,
+    connections: AtomicU32,
+}
+
+impl Trace {
+    /// Writes a transcript to `path`, in the format its extension suggests.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Trace> {
+        let path = path.as_ref();
+        Trace::new(BufWriter::new(File::create(path)?), TraceFormat::for_path(path))
+    }
+
+    pub fn new(out: impl Write + Send + 'static, format: TraceFormat) -> io::Result<Trace> {
+        let mut out: Box<dyn Write + Send> = Box::new(out);
+        if format == TraceFormat::Pcap {
+            let mut header = vec![];
+            header.extend_from_slice(&0xa1b2_c3d4_u32.to_le_bytes());
+            header.extend_from_slice(&2_u16.to_le_bytes());
+            header.extend_from_slice(&4_u16.to_le_bytes());
+            header.extend_from_slice(&[0; 8]); // time zone, timestamp accuracy
+            header.extend_from_slice(&65_535_u32.to_le_bytes());
+            header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+            out.write_all(&header)?;
+            out.flush()?;
+        }
+        Ok(Trace { format, out: Mutex::new(out), start: Instant::now(), connections: AtomicU32::new(0) })
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// Wraps `stream` so everything read from or written to it is recorded.
+    pub(crate) fn wrap(self: &Arc<Self>, stream: Stream) -> Stream {
+        let id = self.connections.fetch_add(1, Ordering::SeqCst) + 1;
+        let (local, peer) = match stream.addrs() {
+            Some((local, peer)) => (v4(local), v4(peer)),
+            None => (SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0), SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)),
+        };
+        let mut traced = Traced { inner: stream, trace: self.clone(), id, local, peer, sent: 1, received: 1 };
+        traced.record(Event::Open);
+        Stream::Traced(Box::new(traced))
+    }
+
+    /// Writes `event` on `conn`; a trace that can't be written mustn't break
+    /// the connection, so errors are dropped.
+    fn write(&self, conn: &Traced, event: &Event) {
+        let record = match self.format {
+            TraceFormat::Hexdump => self.hexdump(conn, event),
+            TraceFormat::Pcap => pcap_records(conn, event),
+        };
+        let mut out = self.out.lock().unwrap_or_else(|err| err.into_inner());
+        let _ = out.write_all(&record).and_then(|_| out.flush());
+    }
+
+    fn hexdump(&self, conn: &Traced, event: &Event) -> Vec<u8> {
+        let elapsed = self.start.elapsed();
+        let mut out = format!("[{:4}.{:06}] #{} ", elapsed.as_secs(), elapsed.subsec_micros(), conn.id);
+        match event {
+            Event::Open => writeln!(out, "{} > {} open", conn.local, conn.peer),
+            Event::Sent(data) => writeln!(out, "{} > {} {} bytes", conn.local, conn.peer, data.len()),
+            Event::Received(data) => writeln!(out, "{} > {} {} bytes", conn.peer, conn.local, data.len()),
+            Event::Close => writeln!(out, "{} > {} close", conn.local, conn.peer),
+        }.unwrap();
+        if let Event::Sent(data) | Event::Received(data) = event {
+            out.push_str(&hexdump(data));
+        }
+        out.into_bytes()
+    }
+}
+
+enum Event<'a> {
+    Open,
+    Sent(&'a [u8]),
+    Received(&'a [u8]),
+    Close,
+}
+
+/// A connection whose traffic is recorded in a `Trace`.
+pub struct Traced {
+    inner: Stream,
+    trace: Arc<Trace>,
+    id: u32,
+    local: SocketAddrV4,
+    peer: SocketAddrV4,
+    /// The sequence numbers of the next byte each way, for the pcap format.
+    sent: u32,
+    received: u32,
+}
+
+impl Traced {
+    pub fn get_ref(&self) -> &Stream {
+        &self.inner
+    }
+
+    fn record(&mut self, event: Event) {
+        self.trace.write(self, &event);
+        match event {
+            Event::Sent(data) => self.sent = self.sent.wrapping_add(data.len() as u32),
+            Event::Received(data) => self.received = self.received.wrapping_add(data.len() as u32),
+            Event::Open | Event::Close => {},
+        }
+    }
+}
+
+impl Read for Traced {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.record(Event::Received(&buf[..n]));
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Traced {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.record(Event::Sent(&buf[..n]));
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Drop for Traced {
+    fn drop(&mut self) {
+        self.record(Event::Close);
+    }
+}
+
+/// The pcap records for `event`: the handshake when a connection opens,
+/// data split into segments the peer acknowledges, and a `FIN` each way
+/// when it closes.
+fn pcap_records(conn: &Traced, event: &Event) -> Vec<u8> {
+    let segment = |outbound: bool, flags, payload: &[u8]| {
+        let (src, dst, seq, ack) = match outbound {
+            true => (conn.local, conn.peer, conn.sent, conn.received),
+            false => (conn.peer, conn.local, conn.received, conn.sent),
+        };
+        Segment { src, dst, seq, ack, flags, window: 65_535, payload: payload.to_vec() }
+    };
+    let segments = match event {
+        Event::Open => {
+            let mut syn = segment(true, SYN, &[]);
+            syn.seq = 0;
+            let mut syn_ack = segment(false, SYN | ACK, &[]);
+            syn_ack.seq = 0;
+            vec![syn, syn_ack, segment(true, ACK, &[])]
+        },
+        Event::Sent(data) | Event::Received(data) => {
+            let outbound = matches!(event, Event::Sent(_));
+            let mut segments = vec![];
+            let mut offset = 0;
+            for chunk in data.chunks(SEGMENT_SIZE) {
+                let mut data = segment(outbound, PSH | ACK, chunk);
+                data.seq = data.seq.wrapping_add(offset);
+                offset += chunk.len() as u32;
+                segments.push(data);
+            }
+            let mut ack = segment(!outbound, ACK, &[]);
+            ack.ack = ack.ack.wrapping_add(offset);
+            segments.push(ack);
+            segments
+        },
+        Event::Close => {
+            let fin = segment(true, FIN | ACK, &[]);
+            let mut fin_ack = segment(false, FIN | ACK, &[]);
+            fin_ack.ack = fin_ack.ack.wrapping_add(1);
+            vec![fin, fin_ack]
+        },
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut records = vec![];
+    for segment in segments {
+        let packet = segment.encode();
+        records.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+        records.extend_from_slice(&now.subsec_micros().to_le_bytes());
+        records.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        records.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        records.extend_from_slice(&packet);
+    }
+    records
+}
+
+/// Lines of 16 bytes in hex and, on the right, as ASCII.
+fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        write!(out, "{:08x} ", i * 16).unwrap();
+        for j in 0..16 {
+            if j == 8 {
+                out.push(' ');
+            }
+            match line.get(j) {
+                Some(byte) => write!(out, " {:02x}", byte).unwrap(),
+                None => out.push_str("   "),
+            }
+        }
+        let ascii: String = line.iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+        writeln!(out, "  |{}|", ascii).unwrap();
+    }
+    out
+}
+
+fn v4(addr: SocketAddr) -> SocketAddrV4 {
+    match addr {
+        SocketAddr::V4(addr) => addr,
+        SocketAddr::V6(addr) => SocketAddrV4::new(addr.ip().to_ipv4_mapped().unwrap_or(Ipv4Addr::UNSPECIFIED), addr.port()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::testing::{response, StandIn, TempDir};
+    use crate::{PageReader, TCPReader};
+
+    fn traced_fetch(path: &Path, server: &StandIn) {
+        let trace = Arc::new(Trace::create(path).unwrap());
+        let reader = TCPReader::builder().trace(trace).build_tcp();
+        assert_eq!(reader.read_page(&server.url("/")).unwrap().text(), "hello");
+        // dropping the reader closes its pooled connection
+    }
+
+    #[test]
+    fn test_hexdump_transcript() {
+        let dir = TempDir::new();
+        let path = dir.path().join("trace.txt");
+        let server = StandIn::serve(vec![response("200 OK", &[], "hello")]);
+
+        traced_fetch(&path, &server);
+
+        let transcript = fs::read_to_string(&path).unwrap();
+        let events: Vec<&str> = transcript.lines()
+            .filter(|line| line.starts_with('['))
+            .map(|line| line.rsplit_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(events, ["open", "bytes", "bytes", "close"], "{}", transcript);
+        assert!(transcript.contains(&format!(":{} open\n", server.port)), "{}", transcript);
+        assert!(transcript.contains("00000000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|\n"), "{}", transcript);
+    }
+
+    #[test]
+    fn test_pcap_transcript() {
+        let dir = TempDir::new();
+        let path = dir.path().join("trace.pcap");
+        let server = StandIn::serve(vec![response("200 OK", &[], "hello")]);
+
+        traced_fetch(&path, &server);
+
+        let capture = fs::read(&path).unwrap();
+        assert_eq!(&capture[..4], &0xa1b2_c3d4_u32.to_le_bytes());
+        assert_eq!(&capture[20..24], &LINKTYPE_RAW.to_le_bytes());
+        let mut segments = vec![];
+        let mut rest = &capture[24..];
+        while !rest.is_empty() {
+            let len = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+            segments.push(Segment::decode(&rest[16..16 + len]).unwrap());
+            rest = &rest[16 + len..];
+        }
+
+        let flags: Vec<u8> = segments.iter().map(|segment| segment.flags).collect();
+        assert_eq!(flags, [SYN, SYN | ACK, ACK, PSH | ACK, ACK, PSH | ACK, ACK, FIN | ACK, FIN | ACK]);
+        let client = segments[0].src;
+        assert_eq!(segments[0].dst.port(), server.port);
+
+        let (mut sent, mut received) = (vec![], vec![]);
+        for segment in &segments {
+            // each side's bytes follow on from the last, and are acknowledged
+            let stream = if segment.src == client { &mut sent } else { &mut received };
+            if !segment.payload.is_empty() {
+                assert_eq!(segment.seq, 1 + stream.len() as u32);
+                stream.extend_from_slice(&segment.payload);
+            }
+        }
+        assert!(String::from_utf8(sent).unwrap().starts_with("GET / HTTP/1.1\r\n"));
+        assert_eq!(received, response("200 OK", &[], "hello"));
+        assert_eq!(segments[6].ack, 1 + received.len() as u32);
+    }
+
+    #[test]
+    fn test_format_for_path() {
+        assert_eq!(TraceFormat::for_path(Path::new("out.PCAP")), TraceFormat::Pcap);
+        assert_eq!(TraceFormat::for_path(Path::new("out.txt")), TraceFormat::Hexdump);
+        assert_eq!(hexdump(b"ok\r\n"), format!("00000000  6f 6b 0d 0a{}  |ok..|\n", " ".repeat(37)));
+    }
+}
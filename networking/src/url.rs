@@ -0,0 +1,243 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlError(String);
+
+impl UrlError {
+    pub(crate) fn new(msg: String) -> Self {
+        UrlError(msg)
+    }
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid URL: {}", self.0)
+    }
+}
+
+impl std::error::Error for UrlError {}
+
+/// An absolute URL, split into the parts a request is built from.
+///
+/// The port is always filled in, from the scheme's default if the URL has none,
+/// and the path is never empty. Fragments are dropped since they're never sent.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Url {
+    pub scheme: String,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+    pub query: Option<String>,
+}
+
+impl Url {
+    /// Parses `input`, treating it as an `http` URL if it has no scheme.
+    pub fn parse(input: &str) -> Result<Url, UrlError> {
+        let input = input.trim();
+        let (scheme, rest) = match input.split_once("://") {
+            Some((scheme, rest)) if has_scheme(input) => (scheme.to_ascii_lowercase(), rest),
+            _ => ("http".to_string(), input),
+        };
+
+        let rest = rest.split('#').next().unwrap_or_default();
+        let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, rest) = rest.split_at(authority_end);
+        let (path, query) = match rest.split_once('?') {
+            None => (rest, None),
+            Some((path, query)) => (path, Some(query.to_string())),
+        };
+        let path = if path.is_empty() { "/".to_string() } else { path.to_string() };
+
+        let (host, port) = split_host_port(authority)?;
+        if host.is_empty() {
+            return Err(UrlError(format!("no host in {:?}", input)));
+        }
+        let port = match port {
+            Some(port) => port,
+            None => Url::default_port(&scheme)
+                .ok_or_else(|| UrlError(format!("no port for scheme {:?}", scheme)))?,
+        };
+
+        Ok(Url { scheme, host: host.to_ascii_lowercase(), port, path, query })
+    }
+
+    pub fn default_port(scheme: &str) -> Option<u16> {
+        match scheme {
+            "http" | "tcp" | "ws" => Some(80),
+            "https" | "wss" => Some(443),
+            _ => None,
+        }
+    }
+
+    /// Resolves a reference such as a `Location` header or a link against this URL.
+    pub fn join(&self, reference: &str) -> Result<Url, UrlError> {
+        let reference = reference.trim();
+        if has_scheme(reference) {
+            return Url::parse(reference);
+        }
+        if reference.starts_with("//") {
+            return Url::parse(&format!("{}:{}", self.scheme, reference));
+        }
+
+        let reference = reference.split('#').next().unwrap_or_default();
+        let (path, query) = match reference.split_once('?') {
+            None => (reference, None),
+            Some((path, query)) => (path, Some(query.to_string())),
+        };
+
+        let mut url = self.clone();
+        if path.is_empty() {
+            if query.is_some() {
+                url.query = query;
+            }
+            return Ok(url);
+        }
+        url.path = if path.starts_with('/') {
+            remove_dot_segments(path)
+        } else {
+            let base = &self.path[..self.path.rfind('/').map_or(0, |i| i + 1)];
+            remove_dot_segments(&format!("{}{}", base, path))
+        };
+        url.query = query;
+        Ok(url)
+    }
+
+    /// The path and query, as sent in a request line.
+    pub fn request_target(&self) -> String {
+        match &self.query {
+            None => self.path.clone(),
+            Some(query) => format!("{}?{}", self.path, query),
+        }
+    }
+
+    /// The host, with the port only if it isn't the scheme's default.
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if Url::default_port(&self.scheme) == Some(self.port) {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}{}", self.scheme, self.authority(), self.request_target())
+    }
+}
+
+impl std::str::FromStr for Url {
+    type Err = UrlError;
+
+    fn from_str(input: &str) -> Result<Url, Self::Err> {
+        Url::parse(input)
+    }
+}
+
+fn has_scheme(reference: &str) -> bool {
+    match reference.split_once("://") {
+        None => false,
+        Some((scheme, _)) => {
+            !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        },
+    }
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    let mut out = vec![];
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+        match *segment {
+            "." => {},
+            ".." => { out.pop(); },
+            segment => { out.push(segment); continue; },
+        }
+        // "a/b/.." still names a directory
+        if last {
+            out.push("");
+        }
+    }
+    format!("/{}", out.join("/"))
+}
+
+fn split_host_port(authority: &str) -> Result<(&str, Option<u16>), UrlError> {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        // an IPv6 literal, e.g. [::1]:8080
+        let (host, rest) = rest.split_once(']')
+            .ok_or_else(|| UrlError(format!("unclosed IPv6 literal in {:?}", authority)))?;
+        (host, rest.strip_prefix(':'))
+    } else {
+        match authority.rsplit_once(':') {
+            None => (authority, None),
+            Some((host, port)) => (host, Some(port)),
+        }
+    };
+    let port = match port {
+        None | Some("") => None,
+        Some(port) => Some(port.parse().map_err(|_| UrlError(format!("bad port {:?}", port)))?),
+    };
+    Ok((host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let url = Url::parse("HTTPS://Example.com:8443/a/b?x=1&y=2#top").unwrap();
+
+        assert_eq!(url.scheme, "https");
+        assert_eq!(url.host, "example.com");
+        assert_eq!(url.port, 8443);
+        assert_eq!(url.path, "/a/b");
+        assert_eq!(url.query.as_deref(), Some("x=1&y=2"));
+        assert_eq!(url.request_target(), "/a/b?x=1&y=2");
+        assert_eq!(url.to_string(), "https://example.com:8443/a/b?x=1&y=2");
+    }
+
+    #[test]
+    fn test_defaults() {
+        let url = Url::parse("www.rustinaction.com").unwrap();
+
+        assert_eq!(url.scheme, "http");
+        assert_eq!(url.port, 80);
+        assert_eq!(url.path, "/");
+        assert_eq!(url.authority(), "www.rustinaction.com");
+
+        let url = Url::parse("http://[::1]:8080?q").unwrap();
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.request_target(), "/?q");
+        assert_eq!(url.authority(), "[::1]:8080");
+    }
+
+    #[test]
+    fn test_join() {
+        let base = Url::parse("http://example.com/docs/guide/intro.html?v=1").unwrap();
+        let join = |reference| base.join(reference).unwrap().to_string();
+
+        assert_eq!(join("https://other.org/"), "https://other.org/");
+        assert_eq!(join("//cdn.example.com/x.js"), "http://cdn.example.com/x.js");
+        assert_eq!(join("/login?next=/docs"), "http://example.com/login?next=/docs");
+        assert_eq!(join("setup.html"), "http://example.com/docs/guide/setup.html");
+        assert_eq!(join("../api/./index.html"), "http://example.com/docs/api/index.html");
+        assert_eq!(join(".."), "http://example.com/docs/");
+        assert_eq!(join("?v=2"), "http://example.com/docs/guide/intro.html?v=2");
+        assert_eq!(join("#top"), "http://example.com/docs/guide/intro.html?v=1");
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Url::parse("http://").is_err());
+        assert!(Url::parse("http://host:port/").is_err());
+        assert!(Url::parse("gopher://host/").is_err());
+        assert!(Url::parse("http://[::1/").is_err());
+    }
+}
@@ -0,0 +1,443 @@
+//! A WebSocket client (RFC 6455), over the same connections as `TCPReader`.
+
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+
+use crate::connection::timed_out;
+use crate::http::{self, invalid_data, Headers, Request};
+use crate::{options, PageError, Stream, TCPReader, Url};
+
+/// Appended to the client's key to make the `Sec-WebSocket-Accept` the server answers with.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest message accepted from a server.
+const MAX_MESSAGE: usize = 64 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The status code and reason the connection is closed with, if any.
+    Close(Option<(u16, String)>),
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Text(text) => f.write_str(text),
+            Message::Binary(data) => write!(f, "<{} bytes>", data.len()),
+            Message::Ping(_) => f.write_str("<ping>"),
+            Message::Pong(_) => f.write_str("<pong>"),
+            Message::Close(None) => f.write_str("<close>"),
+            Message::Close(Some((code, reason))) => write!(f, "<close {} {}>", code, reason),
+        }
+    }
+}
+
+/// An open WebSocket connection.
+///
+/// Pings from the server are answered as they are received, and a close from
+/// the server is echoed back, as the protocol requires.
+pub struct WebSocket {
+    stream: BufReader<Stream>,
+    headers: Headers,
+    /// The opcode and payload of a fragmented message received in part.
+    partial: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    /// Opens a connection to a `ws://` or `wss://` URL.
+    pub fn connect(url: &str) -> Result<WebSocket, PageError> {
+        WebSocket::connect_with(&TCPReader::new(), Request::get(Url::parse(url)?))
+    }
+
+    /// Opens a connection through `reader`, with its timeouts, proxy and
+    /// default headers, sending the fields of `request` with the handshake.
+    pub fn connect_with(reader: &TCPReader, mut request: Request) -> Result<WebSocket, PageError> {
+        request.url.scheme = match request.url.scheme.as_str() {
+            "ws" | "http" => "http".to_string(),
+            "wss" | "https" => "https".to_string(),
+            scheme => return Err(format!("WebSocket can't connect to {} URLs", scheme).into()),
+        };
+        let deadline = reader.options.deadline();
+        let stream = reader.connect(&request.url, deadline)?;
+        stream.set_read_timeout(options::remaining(reader.options.read_timeout, deadline)?)?;
+        stream.set_write_timeout(reader.options.read_timeout)?;
+        let mut stream = BufReader::new(stream);
+
+        let key = BASE64.encode(rand::random::<[u8; 16]>());
+        for (name, value) in [("Upgrade", "websocket"), ("Connection", "Upgrade"), ("Sec-WebSocket-Version", "13")] {
+            request.headers.insert(name, value);
+        }
+        request.headers.insert("Sec-WebSocket-Key", &key);
+        request.method = http::Method::Get;
+        request.body.clear();
+        reader.write_request(&request, stream.get_mut()).map_err(timed_out)?;
+
+        let head = http::read_head(&mut stream).map_err(timed_out)?;
+        if head.status != 101 {
+            return Err(format!("server refused the WebSocket upgrade: {} {}", head.status, head.reason).into());
+        }
+        if !head.headers.get("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket")) {
+            return Err("server switched to a protocol other than WebSocket".into());
+        }
+        if head.headers.get("Sec-WebSocket-Accept") != Some(accept_key(&key).as_str()) {
+            return Err("server answered the WebSocket handshake with the wrong Sec-WebSocket-Accept".into());
+        }
+        // a connection may rightly stay quiet for a long time
+        stream.get_ref().set_read_timeout(None)?;
+
+        Ok(WebSocket { stream, headers: head.headers, partial: None, close_sent: false, close_received: false })
+    }
+
+    /// The header fields of the server's handshake response, e.g. the
+    /// `Sec-WebSocket-Protocol` it picked.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// How long `receive` may wait for a frame; `None` waits for ever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.get_ref().set_read_timeout(timeout)
+    }
+
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write_frame(OP_BINARY, data),
+            Message::Ping(data) => self.write_frame(OP_PING, data),
+            Message::Pong(data) => self.write_frame(OP_PONG, data),
+            Message::Close(status) => self.send_close(status.as_ref().map(|(code, reason)| (*code, reason.as_str()))),
+        }
+    }
+
+    /// Waits for the next message. Once the server has closed the connection,
+    /// this returns `Message::Close` and then fails with `NotConnected`.
+    pub fn receive(&mut self) -> io::Result<Message> {
+        if self.close_received {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "WebSocket closed"));
+        }
+        loop {
+            let frame = Frame::read(&mut self.stream).map_err(timed_out)?;
+            if frame.masked {
+                return Err(invalid_data("server sent a masked frame".to_string()));
+            }
+            let (opcode, payload) = match (frame.opcode, self.partial.take()) {
+                (OP_CONTINUATION, None) => return Err(invalid_data("continuation without a message to continue".to_string())),
+                (OP_CONTINUATION, Some((opcode, mut payload))) => {
+                    if payload.len() + frame.payload.len() > MAX_MESSAGE {
+                        return Err(invalid_data("message too large".to_string()));
+                    }
+                    payload.extend_from_slice(&frame.payload);
+                    (opcode, payload)
+                },
+                (OP_TEXT | OP_BINARY, Some(_)) => return Err(invalid_data("new message before the last one ended".to_string())),
+                (opcode, partial) => {
+                    // control frames may arrive between the fragments of a message
+                    self.partial = partial;
+                    (opcode, frame.payload)
+                },
+            };
+
+            match opcode {
+                OP_TEXT | OP_BINARY if !frame.fin => self.partial = Some((opcode, payload)),
+                OP_TEXT => {
+                    return String::from_utf8(payload).map(Message::Text).map_err(|_| invalid_data("text message isn't UTF-8".to_string()));
+                },
+                OP_BINARY => return Ok(Message::Binary(payload)),
+                OP_PING => {
+                    if !self.close_sent {
+                        self.write_frame(OP_PONG, &payload)?;
+                    }
+                    return Ok(Message::Ping(payload));
+                },
+                OP_PONG => return Ok(Message::Pong(payload)),
+                OP_CLOSE => {
+                    let status = match payload.len() {
+                        0 => None,
+                        1 => return Err(invalid_data("truncated close status".to_string())),
+                        _ => Some((
+                            u16::from_be_bytes([payload[0], payload[1]]),
+                            String::from_utf8_lossy(&payload[2..]).into_owned(),
+                        )),
+                    };
+                    self.close_received = true;
+                    if !self.close_sent {
+                        self.send_close(status.as_ref().map(|(code, _)| (*code, "")))?;
+                    }
+                    return Ok(Message::Close(status));
+                },
+                _ => return Err(invalid_data(format!("unknown opcode {:#x}", opcode))),
+            }
+        }
+    }
+
+    /// Closes the connection with `code`, e.g. `1000` for a normal closure,
+    /// and waits for the server to agree. Messages still arriving are dropped.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if !self.close_sent {
+            self.send_close(Some((code, reason)))?;
+        }
+        while !self.close_received {
+            self.receive()?;
+        }
+        Ok(())
+    }
+
+    fn send_close(&mut self, status: Option<(u16, &str)>) -> io::Result<()> {
+        let mut payload = vec![];
+        if let Some((code, reason)) = status {
+            payload.extend_from_slice(&code.to_be_bytes());
+            payload.extend_from_slice(reason.as_bytes());
+        }
+        self.write_frame(OP_CLOSE, &payload)?;
+        self.close_sent = true;
+        Ok(())
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if self.close_sent {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "WebSocket closed"));
+        }
+        if opcode >= OP_CLOSE && payload.len() > 125 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "control frames carry at most 125 bytes"));
+        }
+        // clients mask everything they send, so caches can't be fooled into
+        // taking frames for HTTP
+        let frame = Frame { fin: true, opcode, masked: true, payload: payload.to_vec() };
+        let conn = self.stream.get_mut();
+        conn.write_all(&frame.encode(rand::random())).and_then(|_| conn.flush()).map_err(timed_out)
+    }
+}
+
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("close_sent", &self.close_sent)
+            .field("close_received", &self.close_received)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The `Sec-WebSocket-Accept` a server answers `key` with.
+fn accept_key(key: &str) -> String {
+    let mut hash = Sha1::new();
+    hash.update(key.as_bytes());
+    hash.update(GUID.as_bytes());
+    BASE64.encode(hash.finalize())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    masked: bool,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// The frame on the wire, with its payload masked by `key` if `masked` is set.
+    fn encode(&self, key: [u8; 4]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.payload.len() + 14);
+        out.push(if self.fin { 0x80 } else { 0 } | self.opcode);
+        let mask_bit = if self.masked { 0x80 } else { 0 };
+        match self.payload.len() {
+            len @ 0..=125 => out.push(mask_bit | len as u8),
+            len @ 126..=0xffff => {
+                out.push(mask_bit | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            },
+            len => {
+                out.push(mask_bit | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            },
+        }
+        if self.masked {
+            out.extend_from_slice(&key);
+            out.extend(self.payload.iter().enumerate().map(|(i, byte)| byte ^ key[i % 4]));
+        } else {
+            out.extend_from_slice(&self.payload);
+        }
+        out
+    }
+
+    /// Reads a frame, unmasking its payload.
+    fn read<R: Read>(r: &mut R) -> io::Result<Frame> {
+        let mut head = [0; 2];
+        r.read_exact(&mut head)?;
+        if head[0] & 0x70 != 0 {
+            return Err(invalid_data("frame uses reserved bits".to_string()));
+        }
+        let (fin, opcode, masked) = (head[0] & 0x80 != 0, head[0] & 0x0f, head[1] & 0x80 != 0);
+        let len = match head[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                r.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            },
+            127 => {
+                let mut len = [0; 8];
+                r.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            },
+            len => len as u64,
+        };
+        if len > MAX_MESSAGE as u64 {
+            return Err(invalid_data(format!("frame of {} bytes is too large", len)));
+        }
+        if opcode >= OP_CLOSE && (len > 125 || !fin) {
+            return Err(invalid_data("oversized or fragmented control frame".to_string()));
+        }
+
+        let mut key = [0; 4];
+        if masked {
+            r.read_exact(&mut key)?;
+        }
+        let mut payload = vec![0; len as usize];
+        r.read_exact(&mut payload)?;
+        if masked {
+            payload.iter_mut().enumerate().for_each(|(i, byte)| *byte ^= key[i % 4]);
+        }
+        Ok(Frame { fin, opcode, masked, payload })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use crate::http::{read_fields, read_line};
+    use crate::testing::{response, StandIn};
+
+    /// The handshake request's fields and the payloads of the pongs received.
+    type Echoed = (Headers, Vec<Vec<u8>>);
+
+    /// Accepts one connection and echoes data frames as they come, answering
+    /// pings and the close. `fragment me` is echoed in two parts with a ping
+    /// in between, and `ping me` is preceded by a ping.
+    fn echo_server() -> (String, thread::JoinHandle<Echoed>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://127.0.0.1:{}/echo", listener.local_addr().unwrap().port());
+        let server = thread::spawn(move || {
+            let mut conn = BufReader::new(listener.accept().unwrap().0);
+            assert_eq!(read_line(&mut conn).unwrap(), "GET /echo HTTP/1.1");
+            let headers = read_fields(&mut conn).unwrap();
+            let accept = accept_key(headers.get("Sec-WebSocket-Key").unwrap());
+            let head = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept);
+            conn.get_mut().write_all(head.as_bytes()).unwrap();
+
+            let send = |conn: &mut BufReader<TcpStream>, fin, opcode, payload: &[u8]| {
+                let frame = Frame { fin, opcode, masked: false, payload: payload.to_vec() };
+                conn.get_mut().write_all(&frame.encode([0; 4])).unwrap();
+            };
+            let mut pongs = vec![];
+            loop {
+                let frame = Frame::read(&mut conn).unwrap();
+                assert!(frame.masked);
+                match frame.opcode {
+                    OP_PING => send(&mut conn, true, OP_PONG, &frame.payload),
+                    OP_PONG => pongs.push(frame.payload),
+                    OP_CLOSE => {
+                        send(&mut conn, true, OP_CLOSE, &frame.payload);
+                        return (headers, pongs);
+                    },
+                    _ if frame.payload == b"fragment me" => {
+                        send(&mut conn, false, frame.opcode, b"fragment");
+                        send(&mut conn, true, OP_PING, b"between");
+                        send(&mut conn, true, OP_CONTINUATION, b" me");
+                    },
+                    _ => {
+                        if frame.payload == b"ping me" {
+                            send(&mut conn, true, OP_PING, b"hi");
+                        }
+                        send(&mut conn, frame.fin, frame.opcode, &frame.payload);
+                    },
+                }
+            }
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_echo() {
+        let (url, server) = echo_server();
+        let mut socket = WebSocket::connect(&url).unwrap();
+
+        socket.send(&Message::Text("hello".to_string())).unwrap();
+        assert_eq!(socket.receive().unwrap(), Message::Text("hello".to_string()));
+
+        let data: Vec<u8> = (0..70_000).map(|i| i as u8).collect();
+        socket.send(&Message::Binary(data.clone())).unwrap();
+        assert_eq!(socket.receive().unwrap(), Message::Binary(data));
+
+        socket.send(&Message::Ping(b"are you there".to_vec())).unwrap();
+        assert_eq!(socket.receive().unwrap(), Message::Pong(b"are you there".to_vec()));
+
+        socket.send(&Message::Text("ping me".to_string())).unwrap();
+        assert_eq!(socket.receive().unwrap(), Message::Ping(b"hi".to_vec()));
+        assert_eq!(socket.receive().unwrap(), Message::Text("ping me".to_string()));
+
+        socket.send(&Message::Text("fragment me".to_string())).unwrap();
+        assert_eq!(socket.receive().unwrap(), Message::Ping(b"between".to_vec()));
+        assert_eq!(socket.receive().unwrap(), Message::Text("fragment me".to_string()));
+
+        socket.close(1000, "done").unwrap();
+        assert_eq!(socket.receive().unwrap_err().kind(), io::ErrorKind::NotConnected);
+        assert_eq!(socket.send(&Message::Text("late".to_string())).unwrap_err().kind(), io::ErrorKind::NotConnected);
+
+        let (headers, pongs) = server.join().unwrap();
+        assert_eq!(headers.get("Upgrade"), Some("websocket"));
+        assert_eq!(headers.get("Sec-WebSocket-Version"), Some("13"));
+        assert_eq!(pongs, [b"hi".to_vec(), b"between".to_vec()]);
+    }
+
+    #[test]
+    fn test_refused_upgrade() {
+        let server = StandIn::serve(vec![response("200 OK", &[], "not a websocket")]);
+        let url = format!("ws://127.0.0.1:{}/", server.port);
+
+        let err = WebSocket::connect(&url).unwrap_err();
+
+        assert_eq!(err.to_string(), "server refused the WebSocket upgrade: 200 OK");
+        assert!(server.requests()[0].to_ascii_lowercase().contains("connection: upgrade\r\n"));
+    }
+
+    #[test]
+    fn test_frames() {
+        // the examples of RFC 6455, section 5.7
+        let hello = Frame { fin: true, opcode: OP_TEXT, masked: false, payload: b"Hello".to_vec() };
+        assert_eq!(hello.encode([0; 4]), b"\x81\x05Hello");
+        let masked = Frame { masked: true, ..hello.clone() };
+        let wire = masked.encode([0x37, 0xfa, 0x21, 0x3d]);
+        assert_eq!(wire, b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58");
+        assert_eq!(Frame::read(&mut &wire[..]).unwrap(), masked);
+
+        let fragment = Frame { fin: false, opcode: OP_BINARY, masked: false, payload: vec![7; 65_536] };
+        let wire = fragment.encode([0; 4]);
+        assert_eq!(&wire[..10], b"\x02\x7f\x00\x00\x00\x00\x00\x01\x00\x00");
+        assert_eq!(Frame::read(&mut &wire[..]).unwrap(), fragment);
+
+        assert!(Frame::read(&mut &b"\x89\x7e\x00\x80"[..]).is_err());
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+}